@@ -0,0 +1,45 @@
+//! Group administrators who post anonymously.
+//!
+//! Telegram sends their messages on behalf of a placeholder user, `@GroupAnonymousBot`, which
+//! all anonymous administrators of all groups share. Sessions created by the placeholder are
+//! owned by every anonymous administrator, and by every administrator of the group at the time
+//! the session was created, see `State::share_with_admins`.
+use tbot::types::{user, User};
+
+/// The id of the placeholder user
+const PLACEHOLDER_ID: i64 = 1_087_968_824;
+
+/// Return true if `user` is the placeholder of anonymous administrators
+pub(crate) fn is_anonymous_admin(user: &User) -> bool {
+    user.id == user::Id(PLACEHOLDER_ID)
+}
+
+/// Return the placeholder user, for messages in groups that come without a sender
+pub(crate) fn placeholder() -> Option<User> {
+    // tbot borrows strings while deserializing, so it can't read from a `Value`
+    let user = serde_json::json!({
+        "id": PLACEHOLDER_ID,
+        "is_bot": true,
+        "first_name": "Group",
+        "username": "GroupAnonymousBot",
+    });
+    serde_json::from_str(&user.to_string()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn placeholder_is_an_anonymous_admin() {
+        let placeholder = placeholder().expect("The placeholder can be deserialized");
+        assert!(is_anonymous_admin(&placeholder));
+        assert_eq!(placeholder.username.as_deref(), Some("GroupAnonymousBot"));
+    }
+
+    #[test]
+    fn other_users_are_not_anonymous_admins() {
+        assert!(!is_anonymous_admin(&test_util::user(1001, "Alice", None)));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{borrow::Borrow, sync::Arc};
+use std::{borrow::Borrow, sync::Arc, time::Duration};
 
 use tbot::contexts::{
     fields::{Callback, Context},
@@ -6,78 +6,220 @@ use tbot::contexts::{
 };
 use tokio::join;
 
+use log::{debug, error, warn};
+
 use super::util;
-use crate::state::State;
+use crate::state::{CacheKey, LeaveOutcome, State, StateError};
 use tbot::contexts::methods::Callback as OtherCallback;
 
 /// Data callback handler
 pub(crate) async fn data_callback(context: Arc<DataCallback>, state: Arc<State>) {
+    // Buttons of a session carry its key, older buttons fall back to the origin message
+    let data = context.data.to_owned();
+    let (action, cache_key) = match CacheKey::from_callback_data(&data) {
+        Some((action, cache_key)) => (action, Some(cache_key)),
+        None => (data.as_str(), None),
+    };
     // I don't get it working via data_callback_if yet...
-    match context.data.as_str() {
+    match action {
         "25" => _25_pressed(context, state).await,
+        "continue" => continue_pressed(context, state).await,
         "5" => _5_pressed(context, state).await,
-        "help" => help_pressed(context).await,
+        "snooze" => snooze_pressed(context, state).await,
+        "help" => help_pressed(context, state).await,
         "cancel" => cancel_button_pressed(context).await,
-        "join" => join_pressed(context, state).await,
-        "start now" => start_now_pressed(context, state).await,
+        "join" => join_pressed(context, state, cache_key).await,
+        "leave" => leave_pressed(context, state, cache_key).await,
+        "ready" => ready_pressed(context, state, cache_key).await,
+        "start now" => start_now_pressed(context, state, cache_key).await,
         unhandled => {
-            dbg!(format!("Received unhandled callback: {}", unhandled));
+            warn!("Received unhandled callback: {}", unhandled);
         }
     }
 }
 
-async fn start_now_pressed(context: Arc<DataCallback>, state: Arc<State>) {
-    if let Some(message) = context.origin.to_owned().message() {
+/// Return the session a button refers to: the key of its callback data or its origin message
+///
+/// Keys of sessions in another chat than the button are ignored, since a button can only
+/// belong to a session of its own chat.
+fn target_session(context: &DataCallback, cache_key: Option<CacheKey>) -> Option<CacheKey> {
+    let origin = context.origin.to_owned().message();
+    match (cache_key, origin) {
+        (Some(cache_key), Some(origin)) if cache_key.chat_id() != origin.chat.id => {
+            warn!(
+                "Ignoring a button of chat {} for message {} in chat {}",
+                origin.chat.id,
+                cache_key.message_id(),
+                cache_key.chat_id()
+            );
+            None
+        }
+        (Some(cache_key), _) => Some(cache_key),
+        (None, origin) => origin.map(|message| CacheKey::new(message.chat.id, message.id)),
+    }
+}
+
+async fn start_now_pressed(
+    context: Arc<DataCallback>,
+    state: Arc<State>,
+    cache_key: Option<CacheKey>,
+) {
+    if let Some(cache_key) = target_session(&context, cache_key) {
         match state
-            .start_session_now(context.bot(), context.from(), &message)
+            .start_session_now(context.bot(), context.from(), &cache_key)
             .await
         {
             Ok(msg) => {
-                context.notify(&*msg).call().await.unwrap();
+                context.notify(&msg).call().await.unwrap();
             }
-            Err(msg) => {
-                context.notify(&*msg).call().await.unwrap();
+            Err(err) => {
+                context.notify(&err.to_string()).call().await.unwrap();
             }
         }
     } else {
-        dbg!("Context is not from a Message.");
+        debug!("Context is not from a Message.");
     }
 }
 
 async fn _25_pressed(context: Arc<DataCallback>, state: Arc<State>) {
     join!(delete_message(context.clone()), async {
         if let Some(message) = context.origin.to_owned().message() {
+            if !state
+                .debounce_creation(message.chat.id, context.from.id)
+                .await
+            {
+                debug!("Ignoring a repeated tap of user {}", context.from.id);
+                return;
+            }
             util::create_pomodoro(
                 context.bot(),
                 state,
                 message.chat.to_owned(),
                 context.from.to_owned(),
+                None,
+                None,
+                None,
             )
             .await;
         } else {
-            dbg!("Context is not from a Message.");
+            debug!("Context is not from a Message.");
+        }
+    });
+}
+
+/// Start the next Pomodoro after a break with the participants and label of the last one
+///
+/// If the last session ended too long ago, a new one is created like with "25".
+async fn continue_pressed(context: Arc<DataCallback>, state: Arc<State>) {
+    join!(delete_message(context.clone()), async {
+        let message = match context.origin.to_owned().message() {
+            Some(message) => message,
+            None => {
+                debug!("Context is not from a Message.");
+                return;
+            }
+        };
+        if !state
+            .debounce_creation(message.chat.id, context.from.id)
+            .await
+        {
+            debug!("Ignoring a repeated tap of user {}", context.from.id);
+            return;
+        }
+        let continuation = state.take_continuation(message.chat.id).await;
+        let label = continuation
+            .as_ref()
+            .and_then(|continuation| continuation.label.to_owned());
+        let created = util::create_pomodoro(
+            context.bot(),
+            state.clone(),
+            message.chat.to_owned(),
+            context.from.to_owned(),
+            None,
+            None,
+            label,
+        )
+        .await;
+        if let (Some(created), Some(continuation)) = (created, continuation) {
+            state
+                .add_continuing_participants(context.bot(), &created, continuation.participants)
+                .await;
         }
     });
 }
 
 async fn _5_pressed(context: Arc<DataCallback>, state: Arc<State>) {
     join!(delete_message(context.clone()), async {
-        if let Some(message) = context.origin.to_owned().message() {
-            util::_5_minute_break(context.bot(), state, message.chat, context.from.to_owned()).await
+        let reply = match context.origin.to_owned().message() {
+            Some(message) => {
+                match util::_5_minute_break(
+                    context.bot(),
+                    state,
+                    message.chat,
+                    context.from.to_owned(),
+                    None,
+                )
+                .await
+                {
+                    Ok(()) => "Break started!".to_string(),
+                    Err(err) => {
+                        debug!("{}", err);
+                        err
+                    }
+                }
+            }
+            None => {
+                debug!("Context is not from a Message.");
+                "This button is not attached to a message.".to_string()
+            }
+        };
+        if let Err(err) = context.notify(&reply).call().await {
+            error!("{}", err);
+        }
+    });
+}
+
+/// Take another 5 minute break instead of continuing
+///
+/// The break asks to continue again once it is over.
+async fn snooze_pressed(context: Arc<DataCallback>, state: Arc<State>) {
+    join!(delete_message(context.clone()), async {
+        let reply = match context.origin.to_owned().message() {
+            Some(message) => {
+                match util::_5_minute_break(
+                    context.bot(),
+                    state,
+                    message.chat,
+                    context.from.to_owned(),
+                    Some(Duration::from_secs(5 * 60)),
+                )
+                .await
+                {
+                    Ok(()) => "Snoozed for 5 minutes".to_string(),
+                    Err(err) => {
+                        debug!("{}", err);
+                        err
+                    }
+                }
+            }
+            None => {
+                debug!("Context is not from a Message.");
+                "This button is not attached to a message.".to_string()
+            }
+        };
+        if let Err(err) = context.notify(&reply).call().await {
+            error!("{}", err);
         }
     });
 }
 
-async fn help_pressed(context: Arc<DataCallback>) {
+async fn help_pressed(context: Arc<DataCallback>, state: Arc<State>) {
     join!(delete_message(context.clone()), async {
         if context.origin.borrow().is_message() {
-            util::send_help_text(
-                context.bot(),
-                context.origin.to_owned().expect_message().chat.id,
-            )
-            .await;
+            let chat_id = context.origin.to_owned().expect_message().chat.id;
+            util::send_help_text(context.bot(), &state, chat_id).await;
         } else {
-            dbg!("Not a Message");
+            debug!("Not a Message");
         }
     },);
 }
@@ -87,24 +229,80 @@ async fn cancel_button_pressed(context: Arc<DataCallback>) {
     delete_message(context).await
 }
 
-async fn join_pressed(context: Arc<DataCallback>, state: Arc<State>) {
+async fn join_pressed(context: Arc<DataCallback>, state: Arc<State>, cache_key: Option<CacheKey>) {
     if let Some(message) = context.origin.to_owned().message() {
-        match state
-            .add_participant(&*context.bot, &message, context.from.to_owned())
-            .await
-        {
-            Ok(msg) => {
-                // How do I merge this into one statement?
-                context.notify(msg).call().await.unwrap_or_else(|msg| {
-                    dbg!(msg);
-                });
-            }
-            Err(msg) => {
-                dbg!(msg);
-            }
+        let result = state
+            .join_session(
+                &context.bot,
+                &message.chat,
+                target_session(&context, cache_key),
+                context.from.to_owned(),
+            )
+            .await;
+        let answer = match result {
+            Ok(msg) => context.notify(msg).call().await,
+            // The button outlived its session, which deserves more than a toast
+            Err(err @ StateError::SessionNotFound) => context.alert(&err.to_string()).call().await,
+            Err(err) => context.notify(&err.to_string()).call().await,
+        };
+        if let Err(err) = answer {
+            error!("{}", err);
         }
     } else {
-        dbg!("Context is not a message");
+        debug!("Context is not a message");
+    }
+}
+
+/// Mark the user as ready for the session of the button
+async fn ready_pressed(context: Arc<DataCallback>, state: Arc<State>, cache_key: Option<CacheKey>) {
+    let cache_key = match target_session(&context, cache_key) {
+        Some(cache_key) => cache_key,
+        None => {
+            debug!("Context is not a message");
+            return;
+        }
+    };
+    let answer = match state
+        .mark_ready(&context.bot, &cache_key, &context.from.id)
+        .await
+    {
+        Ok(msg) => context.notify(msg).call().await,
+        Err(err @ StateError::SessionNotFound) => context.alert(&err.to_string()).call().await,
+        Err(err) => context.notify(&err.to_string()).call().await,
+    };
+    if let Err(err) = answer {
+        error!("{}", err);
+    }
+}
+
+/// Leave the session of the button
+///
+/// If the last participant leaves, the session and its message are deleted.
+async fn leave_pressed(context: Arc<DataCallback>, state: Arc<State>, cache_key: Option<CacheKey>) {
+    let reply = match target_session(&context, cache_key) {
+        Some(cache_key) => {
+            match state
+                .leave_session(&context.bot, &cache_key, &context.from)
+                .await
+            {
+                Ok(LeaveOutcome::Left(_)) => "Bye!".to_string(),
+                Ok(LeaveOutcome::NotSubscribed) => {
+                    "You are not subscribed to this session.".to_string()
+                }
+                Ok(LeaveOutcome::NotFound) => "This session does not exist (anymore).".to_string(),
+                Err(err) => {
+                    error!("{}", err);
+                    "Something went wrong while leaving the session.".to_string()
+                }
+            }
+        }
+        None => {
+            debug!("Context is not a message");
+            return;
+        }
+    };
+    if let Err(err) = context.notify(&reply).call().await {
+        error!("{}", err);
     }
 }
 
@@ -118,11 +316,11 @@ async fn delete_message(context: Arc<DataCallback>) {
                 .call()
                 .await
             {
-                dbg!(message.to_string());
+                error!("{}", message);
             }
         }
         None => {
-            dbg!("Could not extract message.");
+            debug!("Could not extract message.");
         }
     }
 }
@@ -1,14 +1,33 @@
-use std::sync::Arc;
+use core::time::Duration;
+use std::{ops::RangeInclusive, sync::Arc};
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use log::{debug, error};
 
 use tbot::contexts::fields::{Context, Message};
 use tbot::contexts::methods::ChatMethods;
 use tbot::contexts::{Command, Text};
 
-use tbot::types::keyboard::inline::Keyboard;
+use tbot::types::{
+    chat, chat::member::Status, input_file::Document, keyboard::inline::Keyboard, user, User,
+};
 
+use crate::anonymous;
 use crate::bot::util;
-use crate::markup::inline::START_MENU;
-use crate::state::State;
+use crate::chat_kind::{classify, ChatKindClass};
+use crate::markup::{self, inline::START_MENU};
+use crate::messages::{self, Lang};
+use crate::state::{CacheKey, LeaveOutcome, QuietHours, State, StateError, LEADERBOARD_WINDOW};
+use crate::time;
+
+/// The most Pomodoros that can be chained with `/focus`
+const MAX_ROUNDS: u32 = 12;
+/// How often a Pomodoro can be created with `/25 every <interval>`, from every minute to weekly
+const RECURRENCE_INTERVALS: RangeInclusive<Duration> =
+    Duration::from_secs(60)..=Duration::from_secs(60 * 60 * 24 * 7);
+/// Durations that are checked elsewhere, see `State::set_defaults`
+const ANY_DURATION: RangeInclusive<Duration> = Duration::ZERO..=Duration::MAX;
 
 /// Start command
 ///
@@ -17,11 +36,14 @@ pub(crate) async fn start(context: Arc<Command<Text>>, state: Arc<State>) {
     // Attempt to start the session if the message is a reply to the bot
     if let Some(message) = &context.reply_to {
         if let Some(user) = context.from() {
-            if let Ok(_) = util::start_pomodoro_now(context.bot(), user, message, state).await {
+            if util::start_pomodoro_now(context.bot(), user, message, state)
+                .await
+                .is_ok()
+            {
                 return;
             }
         } else {
-            dbg!("User not found");
+            debug!("User not found");
         }
     }
     let text = "Choose one of the following:";
@@ -32,84 +54,1308 @@ pub(crate) async fn start(context: Arc<Command<Text>>, state: Arc<State>) {
         .call()
         .await
     {
-        dbg!(call_result);
-        return;
+        error!("{}", call_result);
     }
 }
 
 /// Command to display information on usage
-pub(crate) async fn help(context: Arc<Command<Text>>, _state: Arc<State>) {
-    util::send_help_text(context.bot(), context.chat.id).await
+pub(crate) async fn help(context: Arc<Command<Text>>, state: Arc<State>) {
+    util::send_help_text(context.bot(), &state, context.chat.id).await
 }
 
 /// Command to create a 25 minute long Pomodoro session
+///
+/// An optional duration like `/25 50` or `/25 1h30m` overrides the default length.
+/// A suffix like `/25 at 14:30` schedules the session for that time in the chat's timezone.
+/// A suffix like `/25 every 1h` creates a new session every hour until `/stopevery`.
+/// The word `focus`, e.g. `/25 50 focus`, turns on focus mode for the new session in a group.
+/// Any other words after the duration label the session, e.g. `/25 50 Chapter 3 review`.
 pub(crate) async fn _25(context: Arc<Command<Text>>, state: Arc<State>) {
     let from_user = match context.from.to_owned() {
         Some(user) => user,
+        // Posts in channels have no author, so sessions there belong to the bot
+        None if classify(&context.chat) == ChatKindClass::Channel => {
+            match context.bot().get_me().call().await {
+                Ok(me) => me.user,
+                Err(err) => {
+                    error!("{}", err);
+                    return;
+                }
+            }
+        }
+        // Anonymous administrators are usually sent as a placeholder, but not always
+        None if classify(&context.chat) == ChatKindClass::Group => match anonymous::placeholder() {
+            Some(user) => user,
+            None => {
+                error!("Could not create the placeholder of anonymous administrators");
+                return;
+            }
+        },
         None => {
-            dbg!("Could not unwrap User");
+            debug!("Could not unwrap User");
+            return;
+        }
+    };
+    let mut arguments: Vec<&str> = context.text.value.split_whitespace().collect();
+    let focus = match arguments.iter().position(|&a| a == "focus") {
+        Some(index) => {
+            arguments.remove(index);
+            true
+        }
+        None => false,
+    };
+    if let Some(index) = arguments.iter().position(|&a| a == "every") {
+        let reply = register_recurrence(
+            &context,
+            &state,
+            from_user,
+            &arguments[..index].join(""),
+            &arguments[index + 1..].join(""),
+        )
+        .await
+        .unwrap_or_else(|err| err);
+        if let Err(err) = context.send_message_in_reply(&reply).call().await {
+            error!("{}", err);
+        }
+        return;
+    }
+    // Only an `at` followed by a time schedules the session, it might be part of the label
+    let clock_argument = match arguments
+        .windows(2)
+        .position(|pair| pair[0] == "at" && starts_with_digit(pair[1]))
+    {
+        Some(index) => {
+            let clock = arguments[index + 1].to_string();
+            arguments.drain(index..index + 2);
+            Some(clock)
+        }
+        None => None,
+    };
+    let (duration_argument, label) = split_duration(&arguments);
+    let label = markup::sanitize_label(&label.join(" "));
+    let duration = if duration_argument.is_empty() {
+        Ok(None)
+    } else {
+        time::parse_duration(&duration_argument, state.duration_bounds()).map(Some)
+    };
+    let start_time = match clock_argument {
+        Some(clock) => {
+            let tz = state.get_timezone(context.chat.id).await;
+            time::parse_clock_time(&clock, tz).map(Some).ok_or(())
+        }
+        None => Ok(None),
+    };
+    let (duration, start_time) = match (duration, start_time) {
+        (Ok(duration), Ok(start_time)) => (duration, start_time),
+        (duration, _) => {
+            let usage = "Usage: /25 [duration] [at HH:MM] [focus] [label]\n\n\
+            Examples: /25, /25 50, /25 90m, /25 1h30m, /25 at 14:30, /25 focus, \
+            /25 50 Chapter 3 review";
+            let reply = match duration {
+                Err(err @ time::ParseError::OutOfRange(_)) => err.to_string(),
+                _ => usage.to_string(),
+            };
+            if let Err(err) = context.send_message_in_reply(&reply).call().await {
+                error!("{}", err);
+            }
             return;
         }
     };
-    util::create_pomodoro(
+    let owner_id = from_user.id;
+    let message = util::create_pomodoro(
         context.bot(),
         state.clone(),
         context.chat.to_owned(),
         from_user,
+        start_time,
+        duration,
+        label,
     )
     .await;
+    if let (true, Some(message)) = (focus, message) {
+        let reply = enable_focus_mode(&context, &state, &CacheKey::from(&message), &owner_id).await;
+        if let Err(err) = context.send_message_in_reply(&reply).call().await {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Return whether an argument starts with a digit, like times do
+fn starts_with_digit(argument: &str) -> bool {
+    argument.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Split arguments into the longest leading duration, like `50` or `1h 30m`, and the rest
+///
+/// The rest is the label, which may start with a digit too, e.g. `/25 3rd chapter`. A plain
+/// number is only a duration on its own, so `/25 50 3 kids` lasts 50 minutes.
+fn split_duration<'a>(arguments: &'a [&'a str]) -> (String, &'a [&'a str]) {
+    for end in (1..=arguments.len()).rev() {
+        let words = &arguments[..end];
+        if end > 1 && words.iter().any(|word| word.parse::<u64>().is_ok()) {
+            continue;
+        }
+        let duration = words.join("");
+        // Durations that are out of range are still durations, they are rejected later
+        if time::parse_duration(&duration, ANY_DURATION) != Err(time::ParseError::Malformed) {
+            return (duration, &arguments[end..]);
+        }
+    }
+    (String::new(), arguments)
+}
+
+/// Register a Pomodoro that is created every `interval`, e.g. `/25 50 every 1h`
+async fn register_recurrence(
+    context: &Command<Text>,
+    state: &State,
+    from_user: User,
+    duration: &str,
+    interval: &str,
+) -> Result<String, String> {
+    let usage = "Usage: /25 [duration] every <interval>\n\n\
+    Examples: /25 every 1h, /25 50 every 1h";
+    let duration = match duration {
+        "" => None,
+        duration => Some(
+            time::parse_duration(duration, state.duration_bounds()).map_err(|err| match err {
+                time::ParseError::Malformed => usage.to_string(),
+                err => err.to_string(),
+            })?,
+        ),
+    };
+    let interval =
+        time::parse_duration(interval, RECURRENCE_INTERVALS).map_err(|err| match err {
+            time::ParseError::Malformed => usage.to_string(),
+            err => err.to_string(),
+        })?;
+    state
+        .add_recurrence(context.chat.to_owned(), from_user, interval, duration)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Stop all recurring Pomodoros of the chat
+pub(crate) async fn stop_every(context: Arc<Command<Text>>, state: Arc<State>) {
+    let reply = match state.stop_recurrences(context.chat.id).await {
+        0 => "There are no recurring sessions in this chat.",
+        _ => "Recurring sessions have been stopped.",
+    };
+    if let Err(err) = context.send_message_in_reply(reply).call().await {
+        error!("{}", err);
+    }
 }
 
 /// Command to create a 5 minute break
+///
+/// An optional duration like `/5 10` overrides the default length.
 pub(crate) async fn _5(context: Arc<Command<Text>>, state: Arc<State>) {
     if let Some(user) = context.from.to_owned() {
-        util::_5_minute_break(context.bot(), state, context.chat.to_owned(), user).await;
+        let duration = if context.text.value.trim().is_empty() {
+            None
+        } else {
+            match time::parse_duration(&context.text.value, state.duration_bounds()) {
+                Ok(duration) => Some(duration),
+                Err(err) => {
+                    let reply = match err {
+                        time::ParseError::Malformed => "Usage: /5 [duration]\n\n\
+                        Examples: /5, /5 10, /5 15m"
+                            .to_string(),
+                        err => err.to_string(),
+                    };
+                    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+                        error!("{}", err);
+                    }
+                    return;
+                }
+            }
+        };
+        if let Err(err) = util::_5_minute_break(
+            context.bot(),
+            state,
+            context.chat.to_owned(),
+            user,
+            duration,
+        )
+        .await
+        {
+            debug!("{}", err);
+            if let Err(err) = context.send_message_in_reply(&err.to_string()).call().await {
+                error!("{}", err);
+            }
+        }
     } else {
-        dbg!("Could not extract user!");
+        debug!("Could not extract user!");
     }
 }
 
 /// Join a Session
+///
+/// If the command is a reply to a session, that session is joined. Otherwise the newest session
+/// of the chat that has not started yet is joined.
+///
+/// The owner of a session can add someone else with `/join @username`.
 pub(crate) async fn join(context: Arc<Command<Text>>, state: Arc<State>) {
+    if reject_in_private_chat(&context).await {
+        return;
+    }
+    let username = context.text.value.trim();
+    if !username.is_empty() {
+        let user_id = match context.from() {
+            Some(user) => user.id,
+            None => return,
+        };
+        let reply = match target_session(&context, &state).await {
+            Ok(cache_key) => state
+                .add_participant_by_username(context.bot(), &cache_key, &user_id, username)
+                .await
+                .unwrap_or_else(|err| err.to_string()),
+            Err(err) => err.to_string(),
+        };
+        if let Err(err) = context.send_message_in_reply(&reply).call().await {
+            error!("{}", err);
+        }
+        return;
+    }
+
+    let cache_key = context.reply_to.as_ref().map(CacheKey::from);
     match context.from() {
-        Some(user) => match state.join_latest_session(context.chat(), user) {
-            Ok(message) => {
-                state
-                    .update_participants_text(context.bot(), &message)
-                    .await;
-            }
-            Err(err) => {
-                if let Err(err) = context.send_message_in_reply(&err).call().await {
-                    dbg!(err.to_string());
+        Some(user) => {
+            if let Err(err) = state
+                .join_session(context.bot(), context.chat(), cache_key, user.to_owned())
+                .await
+            {
+                if let Err(err) = context.send_message_in_reply(&err.to_string()).call().await {
+                    error!("{}", err);
                 }
             }
-        },
+        }
         None => {
-            dbg!("Could not determine user");
-            return;
+            debug!("Could not determine user");
         }
     }
 }
 
 /// Leave from a subscribed Pomodoro
 ///
-/// This tries to figure out the most recent session the user is subscribed and if successful, unsubscribes the user
+/// If the command is a reply to a session, the user leaves exactly that session.
+/// Otherwise this tries to figure out the most recent session the user is subscribed and if successful, unsubscribes the user
 /// Otherwise the bot will replay they didn't found a session
 pub(crate) async fn leave(context: Arc<Command<Text>>, state: Arc<State>) {
+    if reject_in_private_chat(&context).await {
+        return;
+    }
+    let user = match context.from() {
+        Some(user) => user,
+        None => {
+            debug!("Could not determine user");
+            return;
+        }
+    };
+    let result = match &context.reply_to {
+        Some(message) => {
+            state
+                .leave_session(context.bot(), &CacheKey::from(message), user)
+                .await
+        }
+        None => {
+            state
+                .leave_latest_session(context.bot(), context.chat(), user)
+                .await
+        }
+    };
+    let reply = match result {
+        Ok(LeaveOutcome::Left(msg)) => msg,
+        Ok(LeaveOutcome::NotSubscribed) => match context.reply_to {
+            Some(_) => "You are not subscribed to this session.".to_string(),
+            None => "You are not subscribed to any sessions.".to_string(),
+        },
+        Ok(LeaveOutcome::NotFound) => "This session does not exist (anymore).".to_string(),
+        Err(err) => {
+            error!("{}", err);
+            "Something went wrong while leaving the session.".to_string()
+        }
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Tell the user that there is nobody to join or leave in a private chat
+///
+/// Returns true if the command was sent in a private chat.
+async fn reject_in_private_chat(context: &Command<Text>) -> bool {
+    if classify(context.chat()) != ChatKindClass::Private {
+        return false;
+    }
+    let hint =
+        "Your sessions in this chat are yours alone, so there is nothing to join or leave.\n\n\
+    Hint: Reply /cancel to a session to stop it.";
+    if let Err(err) = context.send_message_in_reply(hint).call().await {
+        error!("{}", err);
+    }
+    true
+}
+
+/// Restore the session you cancelled or left last in this chat
+pub(crate) async fn undo(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let reply = state
+        .undo(context.bot(), context.chat(), &user_id)
+        .await
+        .unwrap_or_else(|err| err.to_string());
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Stop being mentioned when a session starts or ends, while staying subscribed
+///
+/// Applies to the session the command replies to, or to the newest session of the user.
+pub(crate) async fn mute(context: Arc<Command<Text>>, state: Arc<State>) {
+    set_muted(context, state, true).await
+}
+
+/// Be mentioned again when a session starts or ends
+///
+/// Applies to the session the command replies to, or to the newest session of the user.
+pub(crate) async fn unmute(context: Arc<Command<Text>>, state: Arc<State>) {
+    set_muted(context, state, false).await
+}
+
+async fn set_muted(context: Arc<Command<Text>>, state: Arc<State>, muted: bool) {
+    let user = match context.from() {
+        Some(user) => user,
+        None => {
+            debug!("Could not determine user");
+            return;
+        }
+    };
+    let cache_key = match &context.reply_to {
+        Some(message) => Some(CacheKey::from(message)),
+        None => state.latest_session_of_user(context.chat(), &user.id).await,
+    };
+    let reply = match cache_key {
+        Some(cache_key) => match state.set_muted(&cache_key, &user.id, muted).await {
+            Ok(msg) => msg,
+            Err(err) => err.to_string(),
+        },
+        None => "You are not subscribed to any sessions.".to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Cancel a session
+///
+/// The command has to be sent as a reply to the session message. Only the creator of the session
+/// is permitted to cancel it.
+pub(crate) async fn cancel(context: Arc<Command<Text>>, state: Arc<State>) {
     let user = match context.from() {
         Some(user) => user,
         None => {
-            dbg!("Could not determine user");
+            debug!("Could not determine user");
+            return;
+        }
+    };
+    let message = match &context.reply_to {
+        Some(message) => message,
+        None => {
+            let hint = "Reply /cancel to the session you want to cancel.";
+            if let Err(err) = context.send_message_in_reply(hint).call().await {
+                error!("{}", err);
+            }
             return;
         }
     };
-    match state
-        .leave_latest_session(context.bot(), context.chat(), user)
+    let send_result = match state
+        .cancel_session(context.bot(), &CacheKey::from(message), user)
+        .await
+    {
+        Ok(msg) => context.send_message(&msg).call().await,
+        Err(err) => context.send_message_in_reply(&err.to_string()).call().await,
+    };
+    if let Err(err) = send_result {
+        error!("{}", err);
+    }
+}
+
+/// Remove all sessions of a group, e.g. to clean up after a busy day
+///
+/// Only administrators of the group may do this.
+pub(crate) async fn clear_all(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user = match context.from() {
+        Some(user) => user,
+        None => return,
+    };
+    let reply = if classify(context.chat()) != ChatKindClass::Group {
+        "/clearall only works in groups, use /cancel instead.".to_string()
+    } else if !is_admin(&context, user).await {
+        "Only administrators of this group can clear all sessions.".to_string()
+    } else {
+        match state
+            .clear_chat_sessions(context.bot(), context.chat.id)
+            .await
+        {
+            0 => "There are no sessions to clear.".to_string(),
+            1 => "Cleared 1 session.".to_string(),
+            cleared => format!("Cleared {} sessions.", cleared),
+        }
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Send the active sessions of the chat as a JSON file
+///
+/// Only administrators may export the sessions of a group.
+pub(crate) async fn export(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user = match context.from() {
+        Some(user) => user,
+        None => return,
+    };
+    let reply = match classify(context.chat()) {
+        ChatKindClass::Group if !is_admin(&context, user).await => {
+            "Only administrators of this group can export its sessions."
+        }
+        ChatKindClass::Group | ChatKindClass::Private => {
+            let export = state.export_chat(context.chat.id).await;
+            if export["sessions"].as_array().is_some_and(Vec::is_empty) {
+                "There are no active sessions in this chat."
+            } else {
+                let bytes = match serde_json::to_vec_pretty(&export) {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        error!("{}", err);
+                        return;
+                    }
+                };
+                let filename = format!("sessions-{}.json", context.chat.id);
+                let document = Document::with_bytes(&filename, &bytes);
+                if let Err(err) = context.send_document_in_reply(document).call().await {
+                    error!("{}", err);
+                }
+                return;
+            }
+        }
+        _ => "/export only works in groups and private chats.",
+    };
+    if let Err(err) = context.send_message_in_reply(reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Return whether `user` is an administrator of the chat of the command
+///
+/// Anonymous administrators always are, otherwise the chat member is looked up.
+async fn is_admin(context: &Command<Text>, user: &User) -> bool {
+    if anonymous::is_anonymous_admin(user) {
+        return true;
+    }
+    match context
+        .bot()
+        .get_chat_member(context.chat.id, user.id)
+        .call()
+        .await
+    {
+        Ok(member) => member.status.is_creator() || member.status.is_administrator(),
+        Err(err) => {
+            error!("{}", err);
+            false
+        }
+    }
+}
+
+/// Set the timezone of a chat
+///
+/// Without an argument, the currently configured timezone is shown.
+pub(crate) async fn set_timezone(context: Arc<Command<Text>>, state: Arc<State>) {
+    let argument = context.text.value.trim();
+    let reply = if argument.is_empty() {
+        format!(
+            "The timezone of this chat is {}.\n\n\
+            Hint: Use /tz <timezone> to change it, e.g. /tz Europe/Berlin",
+            state.get_timezone(context.chat.id).await.name()
+        )
+    } else {
+        match argument.parse::<Tz>() {
+            Ok(tz) => {
+                state.set_timezone(context.chat.id, tz).await;
+                format!("The timezone of this chat has been set to {}.", tz.name())
+            }
+            Err(_) => format!(
+                "Unknown timezone \"{}\". Use a name like Europe/Berlin or America/New_York.",
+                argument
+            ),
+        }
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Show the remaining time of a session
+///
+/// If the command is a reply to a session, the status of that session is shown.
+/// Otherwise the newest session of the chat is used.
+pub(crate) async fn status(context: Arc<Command<Text>>, state: Arc<State>) {
+    let status = match &context.reply_to {
+        Some(message) => state.session_status(&CacheKey::from(message)).await,
+        None => state.latest_session_status(context.chat()).await,
+    };
+    let reply = match status {
+        Ok(msg) => msg,
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Pause a running Pomodoro
+///
+/// If the command is a reply to a session, that session is paused.
+/// Otherwise the newest session of the chat is used.
+pub(crate) async fn pause(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let result = match target_session(&context, &state).await {
+        Ok(cache_key) => state.pause_session(&cache_key, &user_id).await,
+        Err(err) => Err(err),
+    };
+    let reply = match result {
+        Ok(msg) => msg,
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Skip the break of your session and end it right away
+///
+/// If the command is a reply to a session, that session's break is skipped.
+/// Otherwise the newest session of the chat is used.
+pub(crate) async fn skip_break(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let result = match target_session(&context, &state).await {
+        Ok(cache_key) => state.skip_break(context.bot(), &cache_key, &user_id).await,
+        Err(err) => Err(err),
+    };
+    let reply = match result {
+        Ok(msg) => msg,
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Add time to a running Pomodoro, e.g. `/extend 5`
+///
+/// If the command is a reply to a session, that session is extended.
+/// Otherwise the newest session of the chat is used.
+pub(crate) async fn extend(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let reply = match time::parse_duration(&context.text.value, state.duration_bounds()) {
+        Ok(extra) => match target_session(&context, &state).await {
+            Ok(cache_key) => state
+                .extend_session(&cache_key, &user_id, extra)
+                .await
+                .unwrap_or_else(|err| err.to_string()),
+            Err(err) => err.to_string(),
+        },
+        Err(time::ParseError::Malformed) => "Usage: /extend <duration>, e.g. /extend 5".to_string(),
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Resume a paused Pomodoro
+///
+/// If the command is a reply to a session, that session is resumed.
+/// Otherwise the newest session of the chat is used.
+pub(crate) async fn resume(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let result = match target_session(&context, &state).await {
+        Ok(cache_key) => state.resume_session(&cache_key, &user_id).await,
+        Err(err) => Err(err),
+    };
+    let reply = match result {
+        Ok(msg) => msg,
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Return the session a command refers to: the replied-to message or the newest session of the chat
+async fn target_session(context: &Command<Text>, state: &State) -> Result<CacheKey, StateError> {
+    match &context.reply_to {
+        Some(message) => Ok(CacheKey::from(message)),
+        None => state
+            .latest_session(context.chat())
+            .await
+            .ok_or(StateError::NoSessions),
+    }
+}
+
+/// Change when the participants of a Pomodoro are reminded before it ends, e.g. `/remind 2`
+///
+/// `/remind off` disables the reminder.
+pub(crate) async fn remind(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let argument = context.text.value.trim();
+    let offset = match argument {
+        "off" | "0" => None,
+        _ => match time::parse_duration(argument, state.duration_bounds()) {
+            Ok(offset) => Some(offset),
+            Err(err) => {
+                let reply = match err {
+                    time::ParseError::Malformed => {
+                        "Usage: /remind <minutes>, e.g. /remind 2, or /remind off".to_string()
+                    }
+                    err => err.to_string(),
+                };
+                if let Err(err) = context.send_message_in_reply(&reply).call().await {
+                    error!("{}", err);
+                }
+                return;
+            }
+        },
+    };
+    let result = match target_session(&context, &state).await {
+        Ok(cache_key) => state.set_reminder(&cache_key, &user_id, offset).await,
+        Err(err) => Err(err),
+    };
+    let reply = match result {
+        Ok(msg) => msg,
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// List all active sessions of the chat
+pub(crate) async fn list(context: Arc<Command<Text>>, state: Arc<State>) {
+    let reply = state.list_sessions(context.chat()).await;
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Show the most recently completed Pomodoros of the chat, e.g. `/history 10`
+pub(crate) async fn history(context: Arc<Command<Text>>, state: Arc<State>) {
+    let argument = context.text.value.trim();
+    let count = match argument {
+        "" => Some(5),
+        count => count.parse::<usize>().ok().filter(|&count| count > 0),
+    };
+    let reply = match count {
+        Some(count) => state.history(context.chat.id, count).await,
+        None => "Usage: /history [count], e.g. /history 10".to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Show all sessions the user takes part in, across all chats
+pub(crate) async fn my_sessions(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let reply = state.user_sessions_summary(&user_id).await;
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Show how many Pomodoros the user completed and how long they focused in total
+pub(crate) async fn stats(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let reply = match state.user_stats(&user_id).await {
+        Some(stats) => format!(
+            "You have completed {} Pomodoro{} with {} of focus time.",
+            stats.completed_pomodoros,
+            if stats.completed_pomodoros == 1 {
+                ""
+            } else {
+                "s"
+            },
+            time::format_minutes(stats.focus_time)
+        ),
+        None => "No sessions completed yet.".to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Show the users of a group who completed the most Pomodoros in the last 7 days
+pub(crate) async fn leaderboard(context: Arc<Command<Text>>, state: Arc<State>) {
+    let reply = if classify(context.chat()) != ChatKindClass::Group {
+        "/leaderboard only works in groups, use /stats to see your own Pomodoros.".to_string()
+    } else {
+        let since = Utc::now()
+            - chrono::Duration::from_std(LEADERBOARD_WINDOW)
+                .unwrap_or_else(|_| chrono::Duration::weeks(1));
+        let ranks = state.leaderboard(context.chat.id, since).await;
+        if ranks.is_empty() {
+            "Nobody completed a Pomodoro in this group in the last 7 days.".to_string()
+        } else {
+            let lines: Vec<String> = ranks
+                .iter()
+                .take(10)
+                .map(|rank| {
+                    let place = match rank.place {
+                        1 => "🥇".to_string(),
+                        2 => "🥈".to_string(),
+                        3 => "🥉".to_string(),
+                        place => format!("{}.", place),
+                    };
+                    format!(
+                        "{} {}: {} Pomodoro{}, {}",
+                        place,
+                        rank.user.username.as_ref().unwrap_or(&rank.user.first_name),
+                        rank.pomodoros,
+                        if rank.pomodoros == 1 { "" } else { "s" },
+                        time::format_minutes(rank.focus_time)
+                    )
+                })
+                .collect();
+            format!("Top focusers of the last 7 days:\n\n{}", lines.join("\n"))
+        }
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Show the settings in effect for this chat
+pub(crate) async fn settings(context: Arc<Command<Text>>, state: Arc<State>) {
+    let settings = state.chat_settings(context.chat.id).await;
+    if let Err(err) = context
+        .send_message_in_reply(&settings.describe())
+        .call()
+        .await
+    {
+        error!("{}", err);
+    }
+}
+
+/// Turn notifications about new participants on or off for this chat, e.g. `/notify off`
+///
+/// Only administrators may change it in groups.
+pub(crate) async fn notify(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user = match context.from() {
+        Some(user) => user,
+        None => return,
+    };
+    let reply = match context.text.value.trim() {
+        "on" | "off"
+            if classify(context.chat()) == ChatKindClass::Group
+                && !is_admin(&context, user).await =>
+        {
+            "Only administrators of this group can change join notifications.".to_string()
+        }
+        "on" => {
+            state.set_join_notifications(context.chat.id, true).await;
+            "Creators will be notified when someone joins their session.".to_string()
+        }
+        "off" => {
+            state.set_join_notifications(context.chat.id, false).await;
+            "Creators will no longer be notified when someone joins their session.".to_string()
+        }
+        _ => format!(
+            "Join notifications are {} in this chat.\n\n\
+            Hint: Use /notify on or /notify off to change it.",
+            if state.join_notifications_enabled(context.chat.id).await {
+                "on"
+            } else {
+                "off"
+            }
+        ),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Turn the continue prompt after breaks on or off for this group, e.g. `/askcontinue on`
+pub(crate) async fn ask_continue(context: Arc<Command<Text>>, state: Arc<State>) {
+    let reply = match context.text.value.trim() {
+        "on" => {
+            state.set_continue_prompt(context.chat.id, true).await;
+            "You will be asked to continue after every break.".to_string()
+        }
+        "off" => {
+            state.set_continue_prompt(context.chat.id, false).await;
+            "You will no longer be asked to continue after a break.".to_string()
+        }
+        _ => format!(
+            "The continue prompt after breaks is {} in this chat.\n\n\
+            Hint: Use /askcontinue on or /askcontinue off to change it.",
+            if state.continue_prompt_enabled(context.chat.id).await {
+                "on"
+            } else {
+                "off"
+            }
+        ),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Forward feedback to the operator of the bot, e.g. `/feedback The reminders are great`
+///
+/// The forwarded message names the sender and the chat, so the operator can get back to them.
+pub(crate) async fn feedback(context: Arc<Command<Text>>, state: Arc<State>) {
+    let text = context.text.value.trim();
+    let reply = match (state.owner_chat_id(), context.from()) {
+        _ if text.is_empty() => "Usage: /feedback <text>",
+        (None, _) => "Feedback is not configured",
+        (Some(owner_chat_id), from) => {
+            let sender = match from {
+                Some(user) => format!(
+                    "{}{} (id {})",
+                    user.first_name,
+                    user.username
+                        .as_ref()
+                        .map(|username| format!(" @{}", username))
+                        .unwrap_or_default(),
+                    user.id
+                ),
+                None => "an anonymous sender".to_string(),
+            };
+            let chat = match &context.chat.kind {
+                chat::Kind::Group { title, .. }
+                | chat::Kind::Supergroup { title, .. }
+                | chat::Kind::Channel { title, .. } => format!("\"{}\"", title),
+                _ => "a private chat".to_string(),
+            };
+            let msg = format!(
+                "Feedback from {} in {} (chat id {}):\n\n{}",
+                sender, chat, context.chat.id, text
+            );
+            match context.bot().send_message(owner_chat_id, &msg).call().await {
+                Ok(_) => "Thanks for your feedback!",
+                Err(err) => {
+                    error!("Failed to forward feedback to {}: {}", owner_chat_id, err);
+                    "Sorry, your feedback could not be delivered. Please try again later."
+                }
+            }
+        }
+    };
+    if let Err(err) = context.send_message_in_reply(reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Turn pinning the messages of sessions on or off for this group, e.g. `/pin on`
+pub(crate) async fn pin(context: Arc<Command<Text>>, state: Arc<State>) {
+    let reply = match context.text.value.trim() {
+        "on" => {
+            state.set_pin_sessions(context.chat.id, true).await;
+            "The messages of new sessions will be pinned. \
+            I need to be allowed to pin messages for that."
+                .to_string()
+        }
+        "off" => {
+            state.set_pin_sessions(context.chat.id, false).await;
+            "The messages of sessions will no longer be pinned.".to_string()
+        }
+        _ => format!(
+            "Pinning sessions is {} in this chat.\n\n\
+            Hint: Use /pin on or /pin off to change it.",
+            if state.pin_sessions_enabled(context.chat.id).await {
+                "on"
+            } else {
+                "off"
+            }
+        ),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Set the quiet hours of this chat, e.g. `/quiet 22:00-08:00`, or turn them off with `/quiet off`
+///
+/// During the quiet hours, nobody is mentioned in the messages of sessions.
+pub(crate) async fn quiet(context: Arc<Command<Text>>, state: Arc<State>) {
+    let reply = match context.text.value.trim() {
+        "" => match state.get_quiet_hours(context.chat.id).await {
+            Some(quiet_hours) => format!(
+                "The quiet hours of this chat are {} ({}).\n\n\
+                Hint: Use /quiet off to turn them off.",
+                quiet_hours,
+                state.get_timezone(context.chat.id).await
+            ),
+            None => "This chat has no quiet hours.\n\n\
+                Hint: Use e.g. /quiet 22:00-08:00 to set them."
+                .to_string(),
+        },
+        "off" => {
+            state.set_quiet_hours(context.chat.id, None).await;
+            "Quiet hours are off, participants will be mentioned again.".to_string()
+        }
+        range => match QuietHours::parse(range) {
+            Some(quiet_hours) => {
+                state
+                    .set_quiet_hours(context.chat.id, Some(quiet_hours))
+                    .await;
+                format!(
+                    "Nobody will be mentioned between {} and {} ({}).",
+                    quiet_hours.start.format("%H:%M"),
+                    quiet_hours.end.format("%H:%M"),
+                    state.get_timezone(context.chat.id).await
+                )
+            }
+            None => "Please give the quiet hours as a range, e.g. /quiet 22:00-08:00".to_string(),
+        },
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Turn focus mode of a session on or off, e.g. `/focus on`
+///
+/// While a Pomodoro in focus mode is running, all other messages in the group are deleted.
+/// Refers to the replied-to session or the newest session of the chat.
+/// A number instead, e.g. `/focus 4`, creates a chain of that many Pomodoros.
+pub(crate) async fn focus(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user = match context.from() {
+        Some(user) => user,
+        None => return,
+    };
+    let argument = context.text.value.trim();
+    if let Ok(rounds) = argument.parse::<u32>() {
+        start_rounds(&context, &state, user, rounds).await;
+        return;
+    }
+    let reply = match (argument, target_session(&context, &state).await) {
+        (_, Err(err)) => err.to_string(),
+        ("on", Ok(cache_key)) => enable_focus_mode(&context, &state, &cache_key, &user.id).await,
+        ("off", Ok(cache_key)) => state
+            .set_focus_mode(&cache_key, &user.id, false)
+            .await
+            .unwrap_or_else(|err| err.to_string()),
+        _ => "Usage: /focus on or /focus off, as a reply to a session or for the newest one.\n\
+            /focus 4 runs 4 Pomodoros in a row with a break in between."
+            .to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Create a Pomodoro that is followed by more until `rounds` are done, with a break in between
+async fn start_rounds(context: &Command<Text>, state: &Arc<State>, user: &User, rounds: u32) {
+    if rounds == 0 || rounds > MAX_ROUNDS {
+        let reply = format!("A chain has between 1 and {} rounds.", MAX_ROUNDS);
+        if let Err(err) = context.send_message_in_reply(&reply).call().await {
+            error!("{}", err);
+        }
+        return;
+    }
+    let message = util::create_pomodoro(
+        context.bot(),
+        state.clone(),
+        context.chat.to_owned(),
+        user.to_owned(),
+        None,
+        None,
+        None,
+    )
+    .await;
+    if let Some(message) = message {
+        let reply = state
+            .start_rounds(&CacheKey::from(&message), &user.id, rounds)
+            .await
+            .unwrap_or_else(|err| err.to_string());
+        if let Err(err) = context.send_message_in_reply(&reply).call().await {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Stop a chain of Pomodoros started with `/focus 4` after its current round
+///
+/// Refers to the replied-to session or the newest session of the chat.
+pub(crate) async fn stop_focus(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user = match context.from() {
+        Some(user) => user,
+        None => return,
+    };
+    let reply = match target_session(&context, &state).await {
+        Ok(cache_key) => state
+            .stop_rounds(&cache_key, &user.id)
+            .await
+            .unwrap_or_else(|err| err.to_string()),
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Turn on focus mode of a session if the bot is allowed to delete messages in the chat
+async fn enable_focus_mode(
+    context: &Command<Text>,
+    state: &State,
+    cache_key: &CacheKey,
+    owner_id: &user::Id,
+) -> String {
+    if !can_delete_messages(context).await {
+        return "Focus mode needs me to be an administrator allowed to delete messages."
+            .to_string();
+    }
+    state
+        .set_focus_mode(cache_key, owner_id, true)
+        .await
+        .unwrap_or_else(|err| err.to_string())
+}
+
+/// Return whether the bot is allowed to delete messages of others in the chat of the command
+async fn can_delete_messages(context: &Command<Text>) -> bool {
+    let me = match context.bot().get_me().call().await {
+        Ok(me) => me,
+        Err(err) => {
+            error!("{}", err);
+            return false;
+        }
+    };
+    match context
+        .bot()
+        .get_chat_member(context.chat.id, me.user.id)
+        .call()
         .await
     {
-        Ok(_msg) => {}
+        Ok(member) => match member.status {
+            Status::Creator { .. } => true,
+            Status::Administrator {
+                can_delete_messages,
+                ..
+            } => can_delete_messages,
+            _ => false,
+        },
         Err(err) => {
-            dbg!(err.to_string());
+            error!("{}", err);
+            false
+        }
+    }
+}
+
+/// Set the language of the chat, e.g. `/lang de`
+///
+/// Without an argument, the current language is shown.
+pub(crate) async fn set_language(context: Arc<Command<Text>>, state: Arc<State>) {
+    let argument = context.text.value.trim();
+    let reply = match argument.parse::<Lang>() {
+        Ok(lang) => {
+            state.set_language(context.chat.id, lang).await;
+            messages::t(lang, "language_set").to_string()
         }
+        Err(_) => {
+            let lang = state.get_language(context.chat.id).await;
+            messages::tf(lang, "language_usage", &[&lang.code()])
+        }
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Move the start of your waiting session, e.g. `/reschedule 14:30`
+///
+/// The command has to be sent as a reply to the session message.
+pub(crate) async fn reschedule(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let tz = state.get_timezone(context.chat.id).await;
+    let new_start = time::parse_clock_time(&context.text.value, tz);
+    let reply = match (&context.reply_to, new_start) {
+        (Some(message), Some(new_start)) => state
+            .reschedule_session(context.bot(), &CacheKey::from(message), &user_id, new_start)
+            .await
+            .unwrap_or_else(|err| err.to_string()),
+        (Some(_), None) => "Usage: /reschedule <HH:MM>, sent as a reply to the session".to_string(),
+        (None, _) => "Please reply to the session you want to reschedule.".to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Tell when a Pomodoro created with /25 would start, without creating one
+///
+/// Groups and channels use the same alignment as `util::create_pomodoro`, sessions in private
+/// chats start immediately.
+pub(crate) async fn when(context: Arc<Command<Text>>, state: Arc<State>) {
+    let lang = state.get_language(context.chat.id).await;
+    let reply = match classify(&context.chat) {
+        ChatKindClass::Group | ChatKindClass::Channel => {
+            let tz = state.get_timezone(context.chat.id).await;
+            let hh_mm = time::future_point_as_hh_mm(state.start_interval(), tz);
+            messages::tf(lang, "next_start_at", &[&hh_mm, &tz.name()])
+        }
+        ChatKindClass::Private => messages::t(lang, "next_start_now").to_string(),
+        ChatKindClass::Other => return,
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Remove a participant from your session, e.g. `/kick @alice`
+///
+/// The command has to be sent as a reply to the session message.
+pub(crate) async fn kick(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let target = context.text.value.trim();
+    let reply = match &context.reply_to {
+        Some(_) if target.is_empty() => {
+            "Usage: /kick <username>, sent as a reply to the session".to_string()
+        }
+        Some(message) => state
+            .kick_participant(context.bot(), &CacheKey::from(message), &user_id, target)
+            .await
+            .unwrap_or_else(|err| err.to_string()),
+        None => "Please reply to the session you want to kick someone from.".to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Hand the ownership of a session over to another participant, e.g. `/transfer @alice`
+///
+/// The command has to be sent as a reply to the session message.
+pub(crate) async fn transfer(context: Arc<Command<Text>>, state: Arc<State>) {
+    let user_id = match &context.from {
+        Some(user) => user.id,
+        None => return,
+    };
+    let new_owner = context.text.value.trim();
+    let result = match &context.reply_to {
+        Some(_) if new_owner.is_empty() => {
+            Err("Usage: /transfer <username>, sent as a reply to the session".to_string())
+        }
+        Some(message) => state
+            .transfer_ownership(&CacheKey::from(message), &user_id, new_owner)
+            .await
+            .map_err(|err| err.to_string()),
+        None => Err("Please reply to the session you want to transfer.".to_string()),
+    };
+    let reply = match result {
+        Ok(msg) | Err(msg) => msg,
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+/// Set the default Pomodoro and break durations of the chat, e.g. `/setdefault 50 10`
+pub(crate) async fn set_defaults(context: Arc<Command<Text>>, state: Arc<State>) {
+    let arguments: Vec<&str> = context.text.value.split_whitespace().collect();
+    let durations = match arguments.as_slice() {
+        // The state checks the bounds of default durations itself
+        [pomodoro, break_duration] => time::parse_duration(pomodoro, ANY_DURATION)
+            .ok()
+            .zip(time::parse_duration(break_duration, ANY_DURATION).ok()),
+        _ => None,
+    };
+    let reply = match durations {
+        Some((pomodoro, break_duration)) => {
+            match state
+                .set_defaults(context.chat.id, pomodoro, break_duration)
+                .await
+            {
+                Ok(()) => format!(
+                    "New sessions in this chat last {} with a {} break.",
+                    time::format_minutes(pomodoro),
+                    time::format_minutes(break_duration)
+                ),
+                Err(err) => err.to_string(),
+            }
+        }
+        None => "Usage: /setdefault <pomodoro> <break>, e.g. /setdefault 50 10".to_string(),
+    };
+    if let Err(err) = context.send_message_in_reply(&reply).call().await {
+        error!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str) -> (String, String) {
+        let arguments: Vec<&str> = text.split_whitespace().collect();
+        let (duration, label) = split_duration(&arguments);
+        (duration, label.join(" "))
+    }
+
+    fn pair(duration: &str, label: &str) -> (String, String) {
+        (duration.to_string(), label.to_string())
+    }
+
+    #[test]
+    fn split_duration_takes_the_longest_duration() {
+        assert_eq!(split("50"), pair("50", ""));
+        assert_eq!(split("1h 30m"), pair("1h30m", ""));
+        assert_eq!(
+            split("1h 30m Chapter 3 review"),
+            pair("1h30m", "Chapter 3 review")
+        );
+        assert_eq!(split("90m 2nd draft"), pair("90m", "2nd draft"));
+    }
+
+    #[test]
+    fn split_duration_keeps_labels_that_start_with_a_digit() {
+        assert_eq!(split("3rd chapter"), pair("", "3rd chapter"));
+        assert_eq!(split("50 3rd chapter"), pair("50", "3rd chapter"));
+        assert_eq!(split("50 3 kids"), pair("50", "3 kids"));
+        assert_eq!(split("Chapter 3"), pair("", "Chapter 3"));
+        assert_eq!(split(""), pair("", ""));
+    }
+
+    #[test]
+    fn split_duration_leaves_the_range_to_parse_duration() {
+        assert_eq!(split("100000 words"), pair("100000", "words"));
     }
 }
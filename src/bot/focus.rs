@@ -0,0 +1,32 @@
+//! Deleting chatter in groups while a Pomodoro in focus mode is running.
+use std::sync::Arc;
+
+use log::debug;
+use tbot::contexts::methods::ChatMethods;
+
+use crate::{
+    chat_kind::{classify, ChatKindClass},
+    state::State,
+};
+
+/// Delete a message if a Pomodoro in focus mode is running in its group
+///
+/// Commands never reach this handler, so the bot can still be used during a session.
+/// Messages of bots are kept.
+pub(crate) async fn delete_chatter<C: ChatMethods>(context: Arc<C>, state: Arc<State>) {
+    if classify(context.chat()) != ChatKindClass::Group
+        || context.from().is_none_or(|user| user.is_bot)
+        || !state.is_focused(context.chat().id).await
+    {
+        return;
+    }
+    // Focus mode is only turned on if the bot may delete messages, but that can change
+    if let Err(err) = context.delete_this_message().call().await {
+        debug!(
+            "Could not delete message {} in chat {}: {}",
+            context.message_id(),
+            context.chat().id,
+            err
+        );
+    }
+}
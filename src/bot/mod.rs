@@ -1,3 +1,4 @@
 pub(crate) mod callback;
 pub(crate) mod command;
+pub(crate) mod focus;
 pub(crate) mod util;
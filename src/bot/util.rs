@@ -1,127 +1,344 @@
+use core::time::Duration;
 use std::sync::Arc;
 
+use chrono_tz::Tz;
+use tokio::{join, time::Instant};
+
 use tbot::{
-    types::{chat, chat::Kind, Chat, Message, User},
+    errors::MethodCall,
+    types::{chat, Chat, Message, User},
     Bot,
 };
 
-use crate::{markup::inline, state::State, time};
+use log::{debug, error, warn};
 
+use crate::{
+    anonymous,
+    chat_kind::{classify, ChatKindClass},
+    markup::{self, inline},
+    messages::{self, Lang},
+    state::{CacheKey, Dispatcher, State, StateError},
+    time,
+};
 
 /// Register a new Pomodoro
-pub(crate) async fn create_pomodoro(bot: &Bot, state: Arc<State>, chat: Chat, from_user: User) {
-    let message_content = match chat.kind {
-        Kind::Group { .. } | Kind::Supergroup { .. } => {
-            let hh_mm = time::future_point_as_hh_mm();
-            format!(
-                "@{} has created a new Pomodoro!\n\
-            Session will start at {} (UTC)\n\n\
-            Subscribers:",
-                from_user.username.to_owned().unwrap(),
-                hh_mm
-            )
-        }
-        Kind::Private { .. } => "Pomodoro session has been started!".to_string(),
-        _ => {
-            dbg!("Message is not from a group or private chat");
-            return;
-        }
+///
+/// If `start_time` is `None`, the session starts at the default time of the chat kind.
+/// If `duration` is `None`, the session falls back to the default Pomodoro length of the chat.
+/// The `label` should already be sanitized, see `markup::sanitize_label`.
+/// Returns the message of the session if it was registered.
+pub(crate) async fn create_pomodoro(
+    bot: &Bot,
+    state: Arc<State>,
+    chat: Chat,
+    from_user: User,
+    start_time: Option<Instant>,
+    duration: Option<Duration>,
+    label: Option<String>,
+) -> Option<Message> {
+    let duration = match duration {
+        Some(duration) => Some(duration),
+        None => state
+            .get_defaults(chat.id)
+            .await
+            .map(|(pomodoro, _break)| pomodoro),
     };
-    let send_message = match chat.kind {
-        Kind::Group { .. } | Kind::Supergroup { .. } => bot
-            .send_message(*&chat.id, &message_content)
-            .reply_markup(inline::JOIN),
-        Kind::Private { .. } => bot.send_message(*&chat.id, &message_content),
-        _ => {
-            dbg!("Message is not from a group or private chat");
-            return;
-        }
+    let lang = state.get_language(chat.id).await;
+    let tz = state.get_timezone(chat.id).await;
+    let minutes = duration.map(|duration| duration.as_secs() / 60);
+    let starts_at = match start_time {
+        Some(start_time) => Some(time::instant_as_hh_mm(start_time, tz)),
+        None if classify(&chat) == ChatKindClass::Private => None,
+        None => Some(time::future_point_as_hh_mm(state.start_interval(), tz)),
     };
-    match send_message.call().await {
+    let (message_content, header) = announcement(
+        &chat,
+        &from_user,
+        starts_at,
+        minutes,
+        lang,
+        tz,
+        label.as_deref(),
+    )?;
+    let dispatcher = state.dispatcher(bot);
+    if let Err(err) = state.may_create_pomodoro(&chat, &from_user.id).await {
+        let msg = err.to_string();
+        debug!("{}", msg);
+        if let Err(err) = dispatcher
+            .call(chat.id, |bot| bot.send_message(chat.id, &msg).call())
+            .await
+        {
+            error!("{}", err);
+        }
+        return None;
+    }
+    let is_group = classify(&chat) == ChatKindClass::Group;
+    let send_message = dispatcher.call(chat.id, |bot| {
+        let send = bot.send_message(chat.id, &message_content);
+        if is_group {
+            send.reply_markup(inline::JOIN).call()
+        } else {
+            send.call()
+        }
+    });
+    let by_anonymous_admin = anonymous::is_anonymous_admin(&from_user);
+    match send_message.await {
         Ok(message) => {
-            if let Err(msg) = state
-                .new_pomodoro(message.to_owned(), from_user, None, None)
+            if let Err(err) = state
+                .new_pomodoro(
+                    message.to_owned(),
+                    from_user,
+                    start_time,
+                    duration,
+                    header,
+                    label,
+                )
                 .await
             {
-                dbg!(msg);
-                return;
+                let msg = err.to_string();
+                debug!("{}", msg);
+                // The session was rejected, replace its message with the reason
+                let (chat_id, message_id) = (message.chat.id, message.id);
+                let delete = dispatcher.call(chat_id, |bot| {
+                    bot.delete_message(chat_id, message_id).call()
+                });
+                let send = dispatcher.call(chat_id, |bot| bot.send_message(chat_id, &msg).call());
+                let (delete_result, send_result) = join!(delete, send);
+                if let Err(err) = delete_result {
+                    error!("{}", err);
+                }
+                if let Err(err) = send_result {
+                    error!("{}", err);
+                }
+                return None;
             }
-            match message.chat.kind {
-                chat::Kind::Supergroup { .. } | chat::Kind::Group { .. } => {
-                    state.update_participants_text(bot, &message).await
+            if classify(&message.chat) == ChatKindClass::Group {
+                if by_anonymous_admin {
+                    state.share_with_admins(bot, &message).await;
+                }
+                state.update_participants_text(bot, &message).await;
+                state.pin_session(bot, &message).await;
+                if let Some(quiet_hours) = state.get_quiet_hours(chat.id).await {
+                    if quiet_hours.is_quiet_now(tz) {
+                        let notice = messages::tf(lang, "quiet_hours_active", &[&quiet_hours]);
+                        let send = dispatcher
+                            .call(chat.id, |bot| bot.send_message(chat.id, &notice).call());
+                        if let Err(err) = send.await {
+                            error!("{}", err);
+                        }
+                    }
                 }
-                _ => {}
             }
+            Some(message)
+        }
+        Err(e) if classify(&chat) == ChatKindClass::Channel => {
+            // There is nobody to tell in the channel itself
+            warn!(
+                "Could not post in channel {}, is the bot an administrator allowed to post? {}",
+                chat.id, e
+            );
+            None
+        }
+        // Nothing was registered, so there is nothing to clean up but the creator should know
+        Err(e) if lacks_permission(&e) => {
+            warn!(
+                "Could not create a Pomodoro in chat {}, the bot may not send messages: {}",
+                chat.id, e
+            );
+            if !by_anonymous_admin {
+                explain_missing_permission(dispatcher, &chat, &from_user).await;
+            }
+            None
         }
         Err(e) => {
-            dbg!(e);
-            return;
+            error!("{}", e);
+            None
+        }
+    }
+}
+
+/// Render the message of a new Pomodoro of `from_user` in `chat`
+///
+/// `starts_at` is the start time as `HH:MM`, or `None` if the session starts right away.
+/// Returns the text of the message and the header above its list of subscribers, or `None` if
+/// the chat is not supported.
+fn announcement(
+    chat: &Chat,
+    from_user: &User,
+    starts_at: Option<String>,
+    minutes: Option<u64>,
+    lang: Lang,
+    tz: Tz,
+    label: Option<&str>,
+) -> Option<(String, Option<String>)> {
+    match classify(chat) {
+        ChatKindClass::Group => {
+            let hh_mm = starts_at.unwrap_or_default();
+            let username = from_user.username.as_ref().unwrap_or(&from_user.first_name);
+            let created = match minutes {
+                Some(minutes) => messages::tf(
+                    lang,
+                    "pomodoro_created_group_length",
+                    &[username, &minutes, &hh_mm, &tz.name()],
+                ),
+                None => messages::tf(
+                    lang,
+                    "pomodoro_created_group",
+                    &[username, &hh_mm, &tz.name()],
+                ),
+            };
+            let created = markup::with_label(&created, label);
+            let text = format!("{}\n\n{}", created, messages::t(lang, "subscribers"));
+            Some((text, Some(created)))
+        }
+        ChatKindClass::Private => {
+            let text = match (starts_at, minutes) {
+                (Some(hh_mm), Some(minutes)) => messages::tf(
+                    lang,
+                    "pomodoro_scheduled_length",
+                    &[&minutes, &hh_mm, &tz.name()],
+                ),
+                (Some(hh_mm), None) => {
+                    messages::tf(lang, "pomodoro_scheduled", &[&hh_mm, &tz.name()])
+                }
+                (None, Some(minutes)) => messages::tf(lang, "pomodoro_started_length", &[&minutes]),
+                (None, None) => messages::t(lang, "pomodoro_started").to_string(),
+            };
+            let text = markup::with_label(&text, label);
+            Some((text.to_owned(), Some(text)))
+        }
+        // Channels are treated like groups, but nobody can be mentioned or join
+        ChatKindClass::Channel => {
+            let hh_mm = starts_at.unwrap_or_default();
+            let text = match minutes {
+                Some(minutes) => messages::tf(
+                    lang,
+                    "pomodoro_scheduled_length",
+                    &[&minutes, &hh_mm, &tz.name()],
+                ),
+                None => messages::tf(lang, "pomodoro_scheduled", &[&hh_mm, &tz.name()]),
+            };
+            let text = markup::with_label(&text, label);
+            Some((text.to_owned(), Some(text)))
         }
+        ChatKindClass::Other => {
+            debug!("Message is not from a group, a channel or a private chat");
+            None
+        }
+    }
+}
+
+/// Return whether a call failed because the bot is not allowed to do it in the chat
+fn lacks_permission(err: &MethodCall) -> bool {
+    match err {
+        MethodCall::RequestError {
+            error_code: 403, ..
+        } => true,
+        MethodCall::RequestError {
+            error_code: 400,
+            description,
+            ..
+        } => description.contains("rights") || description.contains("CHAT_WRITE_FORBIDDEN"),
+        _ => false,
+    }
+}
+
+/// Tell a user in private that their Pomodoro could not be posted in a group
+///
+/// This only works if the user started a private chat with the bot before.
+async fn explain_missing_permission(dispatcher: Dispatcher<'_>, chat: &Chat, user: &User) {
+    let title = match &chat.kind {
+        chat::Kind::Group { title, .. } | chat::Kind::Supergroup { title, .. } => title.as_str(),
+        _ => "the group",
+    };
+    let text = format!(
+        "I could not post your Pomodoro in {}, because I'm not allowed to send messages there. \
+        Please ask an administrator to let me post and try again.",
+        title
+    );
+    let user_chat = chat::Id::from(user.id);
+    if let Err(err) = dispatcher
+        .call(user_chat, |bot| bot.send_message(user_chat, &text).call())
+        .await
+    {
+        debug!("Could not tell user {} about it either: {}", user.id, err);
     }
 }
 
-/// Start a 5 minute break
-pub(crate) async fn _5_minute_break(bot: &Bot, state: Arc<State>, chat: Chat, user: User) {
+/// Start a break
+///
+/// If `duration` is `None`, the break lasts as long as the default break of the chat, or 5
+/// minutes.
+pub(crate) async fn _5_minute_break(
+    bot: &Bot,
+    state: Arc<State>,
+    chat: Chat,
+    user: User,
+    duration: Option<Duration>,
+) -> Result<(), String> {
     let username = match &user.username {
         Some(user) => user,
         _ => &user.first_name,
     };
-    let message_content = match chat.kind {
-        Kind::Group { .. } | Kind::Supergroup { .. } => {
-            format!("@{}, your 5 minute break has begun!", username)
-        }
-        _ => "Your 5 minute break has begun!".to_string(),
+    let duration = match duration {
+        Some(duration) => Some(duration),
+        None => state
+            .get_defaults(chat.id)
+            .await
+            .map(|(_pomodoro, break_duration)| break_duration),
     };
-    match bot.send_message(chat.id, &message_content).call().await {
-        Ok(message) => {
-            if let Err(err) = state.new_break(message, user, None, None) {
-                dbg!(err);
-            }
-        }
-        Err(e) => {
-            dbg!(e);
+    if let Some(remaining) = state.remaining_break(&chat, &user.id).await {
+        return Err(format!(
+            "You're already on a break. {} left.",
+            time::format_minutes(remaining)
+        ));
+    }
+    let minutes = duration.map_or(5, |duration| duration.as_secs() / 60);
+    let message_content = match classify(&chat) {
+        ChatKindClass::Group => {
+            format!("@{}, your {} minute break has begun!", username, minutes)
         }
+        _ => format!("Your {} minute break has begun!", minutes),
+    };
+    let send_message = state.dispatcher(bot).call(chat.id, |bot| {
+        bot.send_message(chat.id, &message_content).call()
+    });
+    match send_message.await {
+        Ok(message) => state
+            .new_break(message, user, None, duration)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(e) => Err(e.to_string()),
     }
 }
 
 /// Display information on usage
-pub(crate) async fn send_help_text(bot: &Bot, chat_id: chat::Id) {
-    let bot_username = match bot.get_me().call().await {
-        Ok(me) => format!("@{}", me.user.username.unwrap_or(me.user.first_name)),
-        Err(err) => {
-            dbg!(err.to_string());
-            "".to_string()
-        }
+///
+/// The bot's username is taken from the state and only fetched if it is unknown.
+pub(crate) async fn send_help_text(bot: &Bot, state: &State, chat_id: chat::Id) {
+    let lang = state.get_language(chat_id).await;
+    let bot_username = match state.bot_username() {
+        "" => match bot.get_me().call().await {
+            Ok(me) => format!("@{}", me.user.username.unwrap_or(me.user.first_name)),
+            Err(err) => {
+                error!("{}", err);
+                "".to_string()
+            }
+        },
+        username => format!("@{}", username),
     };
 
-    if let Err(err_msg) = bot
-        .send_message(
-            chat_id,
-            &format!(
-                "\
-{} — Yet another Pomodoro Timer bot for telegram.
-
-Commands:
-/25 — Create a new Timer with a duration of 25 minutes.
-/5 — Initiate a short 5 minute break
-/join — Join a session
-/leave — Leave a session
-/help — Show this help message.
-
-This bot supports multiplayer mode!
-Create a /25 in a group and a button will show up for others \
-to join. As soon as the clock hits `minute % 5 == 0`, you will be pinged to start your session.
-
-Made with 🥰🦀 by @yayayayaka
-https://github.com/yayayayaka/chaostomato",
-                bot_username
-            ),
-        )
-        .reply_markup(inline::GOT_IT)
-        .call()
+    let help = messages::tf(lang, "help", &[&bot_username]);
+    if let Err(err_msg) = state
+        .dispatcher(bot)
+        .call(chat_id, |bot| {
+            bot.send_message(chat_id, &help)
+                .reply_markup(inline::HELP)
+                .call()
+        })
         .await
     {
-        dbg!(err_msg.to_string());
+        error!("{}", err_msg);
     }
 }
 
@@ -131,9 +348,47 @@ pub(crate) async fn start_pomodoro_now(
     user: &User,
     message: &Message,
     state: Arc<State>,
-) -> Result<String, String> {
-    match state.start_session_now(bot, user, message).await {
+) -> Result<String, StateError> {
+    match state
+        .start_session_now(bot, user, &CacheKey::from(message))
+        .await
+    {
         Ok(ok) => Ok(ok),
         Err(err) => Err(err),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn group_announcement(from_user: &User) -> (String, Option<String>) {
+        announcement(
+            &test_util::group(-100),
+            from_user,
+            Some("12:05".to_string()),
+            Some(25),
+            Lang::En,
+            Tz::UTC,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn creator_without_username_is_shown_by_first_name() {
+        let alice = test_util::user(7, "Alice", None);
+        let (text, header) = group_announcement(&alice);
+        assert!(text.starts_with("@Alice has created a new 25 minute Pomodoro!"));
+        assert!(text.ends_with("Subscribers:"));
+        assert_eq!(header.as_deref(), text.strip_suffix("\n\nSubscribers:"));
+    }
+
+    #[test]
+    fn creator_with_username_is_shown_by_username() {
+        let alice = test_util::user(7, "Alice", Some("alice42"));
+        let (text, _header) = group_announcement(&alice);
+        assert!(text.starts_with("@alice42 has created a new 25 minute Pomodoro!"));
+    }
+}
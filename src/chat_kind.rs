@@ -0,0 +1,28 @@
+//! Classification of chats by how the bot treats them.
+use tbot::types::chat;
+
+/// How the bot treats a chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChatKindClass {
+    /// Groups and supergroups, where sessions are shared with other members
+    Group,
+    /// Private chats with a single user
+    Private,
+    /// Channels, where the bot posts as an administrator.
+    ///
+    /// Posts in channels have no author and members cannot be mentioned, so sessions in channels
+    /// belong to the bot and have no participants to join or ping.
+    Channel,
+    /// Chats that are not supported
+    Other,
+}
+
+/// Return how the bot treats `chat`
+pub(crate) fn classify(chat: &chat::Chat) -> ChatKindClass {
+    match chat.kind {
+        chat::Kind::Group { .. } | chat::Kind::Supergroup { .. } => ChatKindClass::Group,
+        chat::Kind::Private { .. } => ChatKindClass::Private,
+        chat::Kind::Channel { .. } => ChatKindClass::Channel,
+        _ => ChatKindClass::Other,
+    }
+}
@@ -0,0 +1,172 @@
+use core::time::Duration;
+use std::{env, str::FromStr};
+
+use log::warn;
+use tbot::types::chat;
+
+/// Global configuration of the bot.
+///
+/// Every setting can be overridden with an environment variable, see `Config::from_env`.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// Duration of a short break
+    pub(crate) break_duration: Duration,
+    /// Number of completed Pomodoros after which a long break is taken instead of a short one.
+    /// A value of `0` disables long breaks.
+    pub(crate) long_break_interval: u32,
+    /// Duration of a long break
+    pub(crate) long_break_duration: Duration,
+    /// How long before the end of a Pomodoro its participants get reminded by default.
+    /// A value of `0` disables reminders.
+    pub(crate) reminder_offset: Duration,
+    /// Maximum number of concurrent sessions in a single chat.
+    /// A value of `0` disables the limit.
+    pub(crate) max_sessions_per_chat: usize,
+    /// Whether a Pomodoro in a group needs at least one participant besides its creator before
+    /// it can be started early.
+    pub(crate) early_start_requires_participants: bool,
+    /// Whether a user may only have one Pomodoro waiting to be started per chat.
+    pub(crate) one_pending_session_per_user: bool,
+    /// Group Pomodoros start at the next `minute % start_interval == 0`.
+    /// A value of `0` is treated as `1`.
+    pub(crate) start_interval: u32,
+    /// The hour (UTC) at which every chat gets a summary of the Pomodoros completed that day.
+    /// `None` disables the summary.
+    pub(crate) daily_summary_hour: Option<u32>,
+    /// How long after its creation a group Pomodoro starts right away if nobody joined its
+    /// creator, instead of waiting for the aligned start. A value of `0` disables it.
+    pub(crate) solo_start_delay: Duration,
+    /// Number of completed Pomodoros that are remembered for /history across all chats.
+    pub(crate) history_size: usize,
+    /// How long a cancelled or abandoned session can be restored with /undo.
+    /// A value of `0` disables /undo.
+    pub(crate) undo_window: Duration,
+    /// How long a session whose last participant left is kept, so someone else can still join
+    /// it. A value of `0` deletes it right away.
+    pub(crate) empty_session_grace: Duration,
+    /// The shortest Pomodoro or break a user may ask for, e.g. with `/25 50`.
+    pub(crate) min_duration: Duration,
+    /// The longest Pomodoro or break a user may ask for, which keeps the DelayQueue free of
+    /// entries far in the future.
+    pub(crate) max_duration: Duration,
+    /// Whether /export leaves out the ids of users and only names them.
+    pub(crate) redact_user_ids: bool,
+    /// The chat that /feedback is forwarded to, usually the private chat with the operator.
+    /// `None` disables /feedback.
+    pub(crate) owner_chat_id: Option<chat::Id>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            break_duration: Duration::from_secs(60 * 5),
+            long_break_interval: 4,
+            long_break_duration: Duration::from_secs(60 * 15),
+            reminder_offset: Duration::from_secs(60),
+            max_sessions_per_chat: 10,
+            early_start_requires_participants: false,
+            one_pending_session_per_user: false,
+            start_interval: 5,
+            daily_summary_hour: None,
+            solo_start_delay: Duration::from_secs(0),
+            history_size: 100,
+            undo_window: Duration::from_secs(60),
+            empty_session_grace: Duration::from_secs(0),
+            min_duration: Duration::from_secs(60),
+            max_duration: Duration::from_secs(60 * 60 * 4),
+            redact_user_ids: false,
+            owner_chat_id: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read the configuration from the environment, falling back to the defaults.
+    ///
+    /// - `BREAK_DURATION`: duration of a short break in minutes
+    /// - `LONG_BREAK_INTERVAL`: number of Pomodoros until a long break
+    /// - `LONG_BREAK_DURATION`: duration of a long break in minutes
+    /// - `REMINDER_OFFSET`: minutes before the end of a Pomodoro to remind its participants
+    /// - `MAX_SESSIONS_PER_CHAT`: maximum number of concurrent sessions in a chat
+    /// - `EARLY_START_REQUIRES_PARTICIPANTS`: `true` or `false`
+    /// - `ONE_PENDING_SESSION_PER_USER`: `true` or `false`
+    /// - `START_INTERVAL`: minutes group Pomodoros are aligned to
+    /// - `DAILY_SUMMARY_HOUR`: hour (UTC, `0`-`23`) of the daily summary
+    /// - `SOLO_START_DELAY`: minutes after which a group Pomodoro without other participants starts
+    /// - `HISTORY_SIZE`: number of completed Pomodoros that are remembered
+    /// - `UNDO_WINDOW`: minutes a cancelled or abandoned session can be restored
+    /// - `EMPTY_SESSION_GRACE`: minutes a session is kept after its last participant left
+    /// - `MIN_DURATION`: minutes of the shortest Pomodoro or break a user may ask for
+    /// - `MAX_DURATION`: minutes of the longest Pomodoro or break a user may ask for
+    /// - `REDACT_USER_IDS`: `true` or `false`
+    /// - `OWNER_CHAT_ID`: id of the chat that /feedback is forwarded to
+    pub(crate) fn from_env() -> Config {
+        let default = Config::default();
+        Config {
+            break_duration: env_minutes_or("BREAK_DURATION", default.break_duration),
+            long_break_interval: env_or("LONG_BREAK_INTERVAL", default.long_break_interval),
+            long_break_duration: env_minutes_or("LONG_BREAK_DURATION", default.long_break_duration),
+            reminder_offset: env_minutes_or("REMINDER_OFFSET", default.reminder_offset),
+            max_sessions_per_chat: env_or("MAX_SESSIONS_PER_CHAT", default.max_sessions_per_chat),
+            early_start_requires_participants: env_or(
+                "EARLY_START_REQUIRES_PARTICIPANTS",
+                default.early_start_requires_participants,
+            ),
+            one_pending_session_per_user: env_or(
+                "ONE_PENDING_SESSION_PER_USER",
+                default.one_pending_session_per_user,
+            ),
+            start_interval: env_or("START_INTERVAL", default.start_interval),
+            daily_summary_hour: env_hour("DAILY_SUMMARY_HOUR"),
+            solo_start_delay: env_minutes_or("SOLO_START_DELAY", default.solo_start_delay),
+            history_size: env_or("HISTORY_SIZE", default.history_size),
+            undo_window: env_minutes_or("UNDO_WINDOW", default.undo_window),
+            empty_session_grace: env_minutes_or("EMPTY_SESSION_GRACE", default.empty_session_grace),
+            min_duration: env_minutes_or("MIN_DURATION", default.min_duration),
+            max_duration: env_minutes_or("MAX_DURATION", default.max_duration),
+            redact_user_ids: env_or("REDACT_USER_IDS", default.redact_user_ids),
+            owner_chat_id: env_chat_id("OWNER_CHAT_ID"),
+        }
+    }
+}
+
+/// Parse the environment variable `key`, returning `default` if it is unset or malformed
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key).map(|value| value.parse::<T>()) {
+        Ok(Ok(value)) => value,
+        Ok(Err(_)) => {
+            warn!("Ignoring malformed value of {}", key);
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+/// Parse the environment variable `key` as an hour of the day, returning `None` if it is unset
+/// or not between `0` and `23`
+fn env_hour(key: &str) -> Option<u32> {
+    match env::var(key).ok()?.parse::<u32>() {
+        Ok(hour) if hour < 24 => Some(hour),
+        _ => {
+            warn!("Ignoring malformed value of {}", key);
+            None
+        }
+    }
+}
+
+/// Parse the environment variable `key` as the id of a chat, returning `None` if it is unset or
+/// not a number
+fn env_chat_id(key: &str) -> Option<chat::Id> {
+    match env::var(key).ok()?.parse::<i64>() {
+        Ok(id) => Some(chat::Id(id)),
+        Err(_) => {
+            warn!("Ignoring malformed value of {}", key);
+            None
+        }
+    }
+}
+
+/// Parse the environment variable `key` as a number of minutes
+fn env_minutes_or(key: &str, default: Duration) -> Duration {
+    Duration::from_secs(60 * env_or(key, default.as_secs() / 60))
+}
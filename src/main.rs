@@ -1,40 +1,288 @@
-use tbot::errors::MethodCall;
+use std::{env, path::PathBuf, process};
 
-use bot::{callback, command};
+use log::{error, info, warn};
+use tbot::{errors::MethodCall, state::StatefulEventLoop};
+use tokio::signal;
+
+use bot::{callback, command, focus};
+use config::Config;
 use state::State;
 
 use state::periodic;
 
+mod anonymous;
 mod bot;
+mod chat_kind;
+mod config;
 pub(crate) mod markup;
+mod messages;
+mod monitoring;
 mod state;
+#[cfg(test)]
+mod test_util;
 mod time;
 
 #[tokio::main]
 async fn main() -> Result<(), MethodCall> {
-    let bot = tbot::from_env!("BOT_TOKEN");
-    let mut event_loop = bot.clone().stateful_event_loop(State::default());
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let token = match env::var("BOT_TOKEN") {
+        Ok(token) if !token.trim().is_empty() => token,
+        _ => {
+            eprintln!("Set BOT_TOKEN to your Telegram bot token");
+            process::exit(1);
+        }
+    };
+    let bot = tbot::Bot::new(token);
+
+    // Fetch the bot's username, for the help text and commands like `/start@username`
+    let username = match bot.get_me().call().await {
+        Ok(me) => me.user.username,
+        Err(err) => {
+            error!("Failed to fetch the bot's username: {}", err);
+            None
+        }
+    };
+
+    // Restore the sessions of a previous run
+    let mut state = State::new(Config::from_env());
+    if let Some(username) = &username {
+        state.set_bot_username(username.to_owned());
+    }
+    let session_file = env::var("SESSION_FILE").ok().map(PathBuf::from);
+    if let Some(path) = &session_file {
+        match state.load_from_disk(path).await {
+            Ok(restored) => info!("Restored {} sessions from {}", restored, path.display()),
+            Err(msg) => error!(
+                "Failed to restore sessions from {}: {}",
+                path.display(),
+                msg
+            ),
+        }
+    }
+    // Restore the settings of chats, independent of the sessions
+    let config_file = env::var("CONFIG_FILE").ok().map(PathBuf::from);
+    if let Some(path) = &config_file {
+        match state.load_chat_config(path).await {
+            Ok(restored) => info!(
+                "Restored the settings of {} chats from {}",
+                restored,
+                path.display()
+            ),
+            Err(msg) => error!(
+                "Failed to restore the settings of chats from {}, using the defaults: {}",
+                path.display(),
+                msg
+            ),
+        }
+    }
+    let mut event_loop = bot.clone().stateful_event_loop(state);
 
-    // Fetch the bot's username
-    if let Err(msg) = event_loop.fetch_username().await {
-        dbg!(msg);
+    if let Some(username) = username {
+        event_loop.username(username);
     }
 
-    // Register bot commands
-    event_loop.start(command::start);
-    event_loop.help(command::help);
-    event_loop.command("25", command::_25);
-    event_loop.command("5", command::_5);
-    event_loop.command("join", command::join);
-    event_loop.command("leave", command::leave);
+    // Register bot commands, see `spellings`
+    event_loop.commands(spellings(&["start"]), command::start);
+    event_loop.commands(spellings(&["help"]), command::help);
+    event_loop.commands(spellings(&["25", "pomodoro"]), command::_25);
+    event_loop.commands(spellings(&["5", "break"]), command::_5);
+    event_loop.commands(spellings(&["join"]), command::join);
+    event_loop.commands(spellings(&["leave"]), command::leave);
+    event_loop.commands(spellings(&["mute"]), command::mute);
+    event_loop.commands(spellings(&["unmute"]), command::unmute);
+    event_loop.commands(spellings(&["cancel"]), command::cancel);
+    event_loop.commands(spellings(&["undo"]), command::undo);
+    event_loop.commands(spellings(&["clearall"]), command::clear_all);
+    event_loop.commands(spellings(&["export"]), command::export);
+    event_loop.commands(spellings(&["transfer"]), command::transfer);
+    event_loop.commands(spellings(&["kick"]), command::kick);
+    event_loop.commands(spellings(&["tz"]), command::set_timezone);
+    event_loop.commands(spellings(&["notify"]), command::notify);
+    event_loop.commands(spellings(&["askcontinue"]), command::ask_continue);
+    event_loop.commands(spellings(&["pin"]), command::pin);
+    event_loop.commands(spellings(&["quiet"]), command::quiet);
+    event_loop.commands(spellings(&["focus"]), command::focus);
+    event_loop.commands(spellings(&["stopfocus"]), command::stop_focus);
+    event_loop.commands(spellings(&["lang"]), command::set_language);
+    event_loop.commands(spellings(&["setdefault"]), command::set_defaults);
+    event_loop.commands(spellings(&["settings"]), command::settings);
+    event_loop.commands(spellings(&["feedback"]), command::feedback);
+    event_loop.commands(spellings(&["stopevery"]), command::stop_every);
+    event_loop.commands(spellings(&["when"]), command::when);
+    event_loop.commands(spellings(&["status"]), command::status);
+    event_loop.commands(spellings(&["list"]), command::list);
+    event_loop.commands(spellings(&["stats"]), command::stats);
+    event_loop.commands(spellings(&["leaderboard"]), command::leaderboard);
+    event_loop.commands(spellings(&["history"]), command::history);
+    event_loop.commands(spellings(&["mysessions", "whoami"]), command::my_sessions);
+    event_loop.commands(spellings(&["pause"]), command::pause);
+    event_loop.commands(spellings(&["resume"]), command::resume);
+    event_loop.commands(spellings(&["skipbreak"]), command::skip_break);
+    event_loop.commands(spellings(&["extend"]), command::extend);
+    event_loop.commands(spellings(&["reschedule"]), command::reschedule);
+    event_loop.commands(spellings(&["remind"]), command::remind);
     event_loop.data_callback(callback::data_callback);
 
+    // Delete chatter during Pomodoros in focus mode
+    event_loop.text(focus::delete_chatter);
+    event_loop.photo(focus::delete_chatter);
+    event_loop.sticker(focus::delete_chatter);
+    event_loop.animation(focus::delete_chatter);
+    event_loop.video(focus::delete_chatter);
+    event_loop.voice(focus::delete_chatter);
+    event_loop.document(focus::delete_chatter);
+
     // The loop to check for expired sessions that need to be handled
-    tokio::spawn(periodic::poll_for_expired_entries(
-        bot,
+    tokio::spawn(periodic::supervise_expired_entries(
+        bot.clone(),
         event_loop.get_state(),
     ));
 
-    event_loop.polling().start().await.unwrap();
+    // Log the lifecycle events of all sessions
+    tokio::spawn(periodic::log_session_events(
+        event_loop.get_state().subscribe_events(),
+    ));
+
+    // Show the progress of running Pomodoros
+    tokio::spawn(periodic::update_progress(
+        bot.clone(),
+        event_loop.get_state(),
+    ));
+
+    // Remove participants who left their group
+    tokio::spawn(periodic::prune_departed_participants(
+        bot.clone(),
+        event_loop.get_state(),
+    ));
+
+    // The loop to create recurring sessions
+    tokio::spawn(periodic::poll_recurrences(
+        bot.clone(),
+        event_loop.get_state(),
+    ));
+
+    // Post a summary of the day to every chat
+    if let Some(hour) = event_loop.get_state().daily_summary_hour() {
+        tokio::spawn(periodic::post_daily_summaries(
+            bot.clone(),
+            event_loop.get_state(),
+            hour,
+        ));
+    }
+
+    // Save sessions regularly so they survive a restart
+    if let Some(path) = &session_file {
+        tokio::spawn(periodic::persist_sessions(
+            event_loop.get_state(),
+            path.to_owned(),
+        ));
+    }
+
+    // Save the settings of chats regularly so they survive a restart
+    if let Some(path) = &config_file {
+        tokio::spawn(periodic::persist_chat_config(
+            event_loop.get_state(),
+            path.to_owned(),
+        ));
+    }
+
+    // Expose the number of sessions for monitoring
+    match env::var("METRICS_PORT").map(|port| port.parse::<u16>()) {
+        Ok(Ok(port)) => {
+            tokio::spawn(monitoring::serve(event_loop.get_state(), port));
+        }
+        Ok(Err(_)) => warn!("Ignoring malformed value of METRICS_PORT"),
+        Err(_) => {}
+    }
+
+    let state = event_loop.get_state();
+    tokio::select! {
+        _ = receive_updates(event_loop) => {}
+        _ = shutdown_signal() => {
+            info!("Shutting down");
+            if let Some(path) = &config_file {
+                if let Err(err) = state.save_chat_config(path).await {
+                    error!("Failed to save the settings of chats to {}: {}", path.display(), err);
+                }
+            }
+            match &session_file {
+                // Sessions are restored on the next start, so there is nothing to interrupt
+                Some(path) => {
+                    if let Err(err) = state.save_to_disk(path).await {
+                        error!("Failed to save sessions to {}: {}", path.display(), err);
+                    }
+                }
+                None => state.shutdown_notify(&bot).await,
+            }
+        }
+    }
     Ok(())
 }
+
+/// Return the names of commands in the spellings users type, e.g. `join`, `Join` and `JOIN`
+///
+/// tbot matches commands exactly, so every spelling is registered on its own. The spellings are
+/// leaked, which is fine since commands are only registered once at startup.
+fn spellings(names: &[&'static str]) -> Vec<&'static str> {
+    let mut spellings: Vec<&'static str> = Vec::new();
+    for &name in names {
+        let mut chars = name.chars();
+        let capitalized = match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        };
+        for spelling in [name.to_string(), capitalized, name.to_uppercase()] {
+            if !spellings.contains(&spelling.as_str()) {
+                spellings.push(Box::leak(spelling.into_boxed_str()));
+            }
+        }
+    }
+    spellings
+}
+
+/// Receive updates from Telegram.
+///
+/// If `WEBHOOK_URL` is set, a webhook server listening on `WEBHOOK_PORT` (default 8080) is
+/// started, which is useful behind a reverse proxy. Otherwise updates are fetched by polling.
+async fn receive_updates(event_loop: StatefulEventLoop<State>) {
+    match env::var("WEBHOOK_URL") {
+        Ok(url) => {
+            let port = match env::var("WEBHOOK_PORT").map(|port| port.parse::<u16>()) {
+                Ok(Ok(port)) => port,
+                Ok(Err(_)) => {
+                    warn!("Ignoring malformed value of WEBHOOK_PORT");
+                    8080
+                }
+                Err(_) => 8080,
+            };
+            info!("Receiving updates via webhook on port {}", port);
+            event_loop.webhook(&url, port).http().start().await.unwrap();
+        }
+        Err(_) => {
+            event_loop.polling().start().await.unwrap();
+        }
+    }
+}
+
+/// Resolve as soon as the process receives SIGINT or SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(err) => {
+                error!("Failed to listen for SIGTERM: {}", err);
+                let _ = signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = signal::ctrl_c().await;
+    }
+}
@@ -1,3 +1,128 @@
+use tbot::types::User;
+
+/// Escape `text` for a message that is sent with the HTML parse mode
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Maximum number of characters of the label of a session
+pub(crate) const MAX_LABEL_LENGTH: usize = 64;
+
+/// Clean up the label of a session: control characters are removed, whitespace is collapsed and
+/// it is cut off after `MAX_LABEL_LENGTH` characters
+///
+/// Returns `None` if nothing is left.
+pub(crate) fn sanitize_label(label: &str) -> Option<String> {
+    let label: String = label
+        .split_whitespace()
+        .map(|word| word.chars().filter(|c| !c.is_control()).collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_LABEL_LENGTH)
+        .collect();
+    match label.trim_end() {
+        "" => None,
+        label => Some(label.to_string()),
+    }
+}
+
+/// Return `text` followed by the label of a session on its own line, if it has one
+pub(crate) fn with_label(text: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("{}\n📝 {}", text, label),
+        None => text.to_string(),
+    }
+}
+
+/// Return an HTML mention of `user`, which pings them even if they have no username
+pub(crate) fn mention_html(user: &User) -> String {
+    let name = match &user.username {
+        Some(username) => format!("@{}", username),
+        None => user.first_name.to_owned(),
+    };
+    format!(
+        "<a href=\"tg://user?id={}\">{}</a>",
+        user.id,
+        escape_html(&name)
+    )
+}
+
+/// The most characters Telegram accepts in the text of a message
+pub(crate) const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// Return the length of a plain text as Telegram counts it, in UTF-16 code units
+pub(crate) fn text_length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Return the length of an HTML text as Telegram counts it, i.e. without its tags
+pub(crate) fn html_length(html: &str) -> usize {
+    let mut length = 0;
+    let mut in_tag = false;
+    let mut in_entity = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if in_tag => {}
+            // `escape_html` only produces entities of a single character
+            '&' => {
+                in_entity = true;
+                length += 1;
+            }
+            ';' if in_entity => in_entity = false,
+            _ if in_entity => {}
+            c => length += c.len_utf16(),
+        }
+    }
+    length
+}
+
+/// Join `items` with single spaces, but only as many as fit into `budget` characters as
+/// counted by `length`
+///
+/// The items that do not fit are summarized like `…and 12 more`.
+pub(crate) fn join_within(items: &[String], budget: usize, length: fn(&str) -> usize) -> String {
+    // Leave room for the summary of the items that do not fit, as long as there are any
+    let reserve = text_length(&format!(" …and {} more", items.len()));
+    let mut text = String::new();
+    let mut used = 0;
+    for (index, item) in items.iter().enumerate() {
+        let separator = usize::from(index > 0);
+        let needed = separator + length(item) + if index + 1 < items.len() { reserve } else { 0 };
+        if used + needed > budget {
+            if index > 0 {
+                text.push(' ');
+            }
+            text.push_str(&format!("…and {} more", items.len() - index));
+            break;
+        }
+        if index > 0 {
+            text.push(' ');
+        }
+        text.push_str(item);
+        used += separator + length(item);
+    }
+    text
+}
+
+/// Return a text progress bar like `[████░░░░░░] 40%` for a `fraction` between `0.0` and `1.0`
+pub(crate) fn progress_bar(fraction: f64) -> String {
+    const WIDTH: usize = 10;
+    let fraction = fraction.clamp(0.0, 1.0);
+    let filled = (fraction * WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}] {}%",
+        "█".repeat(filled),
+        "░".repeat(WIDTH - filled),
+        (fraction * 100.0).round()
+    )
+}
+
 pub(crate) mod inline {
     use tbot::types::keyboard::inline::{Button, ButtonKind::CallbackData, Markup};
 
@@ -8,9 +133,53 @@ pub(crate) mod inline {
         Button::new("cancel", CallbackData("cancel")),
     ]];
     pub const ASK_TO_CONTINUE: Markup = &[&[
-        Button::new("Yes", CallbackData("25")),
+        Button::new("Yes", CallbackData("continue")),
+        Button::new("Snooze 5 min", CallbackData("snooze")),
         Button::new("No, thanks", CallbackData("cancel")),
     ]];
-    pub const JOIN: Markup = &[&[Button::new("Join", CallbackData("join"))]];
-    pub const GOT_IT: Markup = &[&[Button::new("Got it!", CallbackData("cancel"))]];
+    pub const JOIN: Markup = &[&[
+        Button::new("Join", CallbackData("join")),
+        Button::new("Leave", CallbackData("leave")),
+        Button::new("Ready", CallbackData("ready")),
+    ]];
+    /// Below the help text, so new users can try the bot with a single tap
+    pub const HELP: Markup = &[&[
+        Button::new("Start a Pomodoro", CallbackData("25")),
+        Button::new("Got it!", CallbackData("cancel")),
+    ]];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn mentions(count: i64) -> Vec<String> {
+        (0..count)
+            .map(|id| {
+                let name = format!("participant{}", id);
+                mention_html(&test_util::user(1000 + id, "Participant", Some(&name)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn join_within_summarizes_what_does_not_fit() {
+        let mentions = mentions(500);
+        let text = join_within(&mentions, MAX_MESSAGE_LENGTH, html_length);
+        assert!(html_length(&text) <= MAX_MESSAGE_LENGTH);
+
+        let shown = text.matches("<a href=").count();
+        assert!(shown > 0 && shown < mentions.len());
+        assert!(text.ends_with(&format!(" …and {} more", mentions.len() - shown)));
+        // The mentions that are shown come first and are complete
+        assert!(text.starts_with(&mentions[..shown].join(" ")));
+    }
+
+    #[test]
+    fn join_within_keeps_everything_that_fits() {
+        let mentions = mentions(3);
+        let text = join_within(&mentions, MAX_MESSAGE_LENGTH, html_length);
+        assert_eq!(text, mentions.join(" "));
+    }
 }
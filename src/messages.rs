@@ -0,0 +1,317 @@
+//! A catalog of the user facing messages in all supported languages.
+//!
+//! Messages are looked up by key. Keys that are missing in a language fall back to English.
+use std::{fmt::Display, str::FromStr};
+
+/// A language the bot can talk in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Lang {
+    #[default]
+    En,
+    De,
+    Es,
+}
+
+impl Lang {
+    /// All supported languages
+    pub(crate) const ALL: [Lang; 3] = [Lang::En, Lang::De, Lang::Es];
+
+    /// Return the language code, e.g. `de`
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::De => "de",
+            Lang::Es => "es",
+        }
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code.trim().to_lowercase().as_str() {
+            "en" => Ok(Lang::En),
+            "de" => Ok(Lang::De),
+            "es" => Ok(Lang::Es),
+            other => Err(format!("Unknown language \"{}\"", other)),
+        }
+    }
+}
+
+/// Return the message `key` in the given language
+///
+/// Falls back to English if the key has not been translated, and to the key itself if it is
+/// unknown.
+pub(crate) fn t(lang: Lang, key: &'static str) -> &'static str {
+    lookup(lang, key)
+        .or_else(|| lookup(Lang::En, key))
+        .unwrap_or(key)
+}
+
+/// Return the message `key` in the given language with every `{}` replaced by the next argument
+pub(crate) fn tf(lang: Lang, key: &'static str, args: &[&(dyn Display + Sync)]) -> String {
+    let mut parts = t(lang, key).split("{}");
+    let mut message = parts.next().unwrap_or_default().to_string();
+    for (index, part) in parts.enumerate() {
+        if let Some(arg) = args.get(index) {
+            message.push_str(&arg.to_string());
+        }
+        message.push_str(part);
+    }
+    message
+}
+
+fn lookup(lang: Lang, key: &str) -> Option<&'static str> {
+    match lang {
+        Lang::En => english(key),
+        Lang::De => german(key),
+        Lang::Es => spanish(key),
+    }
+}
+
+fn english(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "subscribers" => "Subscribers:",
+        "pomodoro_created_group" => {
+            "@{} has created a new Pomodoro!\n\
+            Session will start at {} ({})"
+        }
+        "pomodoro_created_group_length" => {
+            "@{} has created a new {} minute Pomodoro!\n\
+            Session will start at {} ({})"
+        }
+        "pomodoro_started" => "Pomodoro session has been started!",
+        "pomodoro_started_length" => "{} minute Pomodoro session has been started!",
+        "pomodoro_scheduled" => "Pomodoro session will start at {} ({})",
+        "pomodoro_scheduled_length" => "{} minute Pomodoro session will start at {} ({})",
+        "session_over_short" => "Session is over! Now take a short, {} minute break",
+        "session_over_long" => "Session is over! Now take a long, {} minute break",
+        "next_start_at" => "Next session start would be at {} ({})",
+        "next_start_now" => "A session created here would start immediately.",
+        "quiet_hours_active" => "It is quiet hours in this chat ({}), nobody will be mentioned.",
+        "language_set" => "The language of this chat has been set to English.",
+        "language_usage" => {
+            "The language of this chat is {}.\n\n\
+            Hint: Use /lang <language> to change it, e.g. /lang de\n\
+            Available languages: en, de, es"
+        }
+        "help" => {
+            "\
+{} — Yet another Pomodoro Timer bot for telegram.
+
+Commands:
+/25 — Create a new Timer with a duration of 25 minutes, e.g. /25 50, /25 at 14:30 or /25 50 Chapter 3 to label it. Also /pomodoro
+/stopevery — Stop the sessions created regularly with /25 every 1h
+/when — Show when a /25 created now would start
+/5 — Initiate a short 5 minute break, e.g. /5 10 for a longer one. Also /break
+/join — Join a session, or add someone to yours with /join @alice
+/leave — Leave a session
+/mute — Stay in a session without being mentioned, /unmute to undo
+/cancel — Cancel a session you created (reply to the session)
+/undo — Restore the session you just cancelled or left
+/clearall — Remove all sessions of this group (admins only)
+/export — Get the active sessions of this chat as a JSON file (admins only in groups)
+/transfer — Hand your session over to another participant, e.g. /transfer @alice (reply to the session)
+/kick — Remove a participant from your session, e.g. /kick @alice (reply to the session)
+/status — Show the remaining time of a session
+/list — List all active sessions of this chat
+/mysessions — List the sessions you take part in
+/history — Show the last completed Pomodoros of this chat, e.g. /history 10
+/stats — Show how many Pomodoros you have completed
+/leaderboard — Show who completed the most Pomodoros in this group in the last 7 days
+/pause — Pause your running Pomodoro
+/resume — Resume your paused Pomodoro
+/skipbreak — Skip the break of your session
+/extend — Add time to your running Pomodoro, e.g. /extend 5
+/reschedule — Move the start of your waiting Pomodoro, e.g. /reschedule 14:30 (reply to the session)
+/remind — Set when you get reminded before a Pomodoro ends, e.g. /remind 2
+/tz — Set the timezone of this chat, e.g. /tz Europe/Berlin
+/notify — Turn notifications about new participants on or off, e.g. /notify off
+/askcontinue — Ask this group whether to continue after a break, e.g. /askcontinue on
+/pin — Pin the messages of sessions in this group, e.g. /pin on
+/quiet — Mention nobody during quiet hours, e.g. /quiet 22:00-08:00 or /quiet off
+/focus — Delete other messages while a Pomodoro is running, e.g. /focus on or /25 focus. /focus 4 runs 4 Pomodoros in a row
+/stopfocus — Stop a chain of Pomodoros after the current round
+/lang — Set the language of this chat, e.g. /lang de
+/setdefault — Set the default Pomodoro and break length of this chat, e.g. /setdefault 50 10
+/settings — Show the settings of this chat
+/feedback — Send feedback to the operator of this bot, e.g. /feedback Great bot!
+/help — Show this help message.
+
+Commands also work capitalized, e.g. /Join or /JOIN.
+
+This bot supports multiplayer mode!
+Create a /25 in a group and a button will show up for others \
+to join. As soon as the clock hits `minute % 5 == 0`, you will be pinged to start your session.
+
+Made with 🥰🦀 by @yayayayaka
+https://github.com/yayayayaka/chaostomato"
+        }
+        _ => return None,
+    })
+}
+
+fn german(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "subscribers" => "Teilnehmer:",
+        "pomodoro_created_group" => {
+            "@{} hat einen neuen Pomodoro erstellt!\n\
+            Die Session beginnt um {} ({})"
+        }
+        "pomodoro_created_group_length" => {
+            "@{} hat einen neuen {}-Minuten-Pomodoro erstellt!\n\
+            Die Session beginnt um {} ({})"
+        }
+        "pomodoro_started" => "Die Pomodoro-Session hat begonnen!",
+        "pomodoro_started_length" => "Die {}-Minuten-Pomodoro-Session hat begonnen!",
+        "pomodoro_scheduled" => "Die Pomodoro-Session beginnt um {} ({})",
+        "pomodoro_scheduled_length" => "Die {}-Minuten-Pomodoro-Session beginnt um {} ({})",
+        "session_over_short" => "Die Session ist vorbei! Zeit für eine kurze Pause von {} Minuten",
+        "session_over_long" => "Die Session ist vorbei! Zeit für eine lange Pause von {} Minuten",
+        "next_start_at" => "Die nächste Session würde um {} ({}) beginnen",
+        "quiet_hours_active" => "In diesem Chat ist gerade Ruhezeit ({}), niemand wird erwähnt.",
+        "next_start_now" => "Eine hier erstellte Session würde sofort beginnen.",
+        "language_set" => "Die Sprache dieses Chats ist jetzt Deutsch.",
+        "language_usage" => {
+            "Die Sprache dieses Chats ist {}.\n\n\
+            Tipp: Mit /lang <Sprache> kannst du sie ändern, z.B. /lang en\n\
+            Verfügbare Sprachen: en, de, es"
+        }
+        "help" => {
+            "\
+{} — Noch ein Pomodoro-Timer-Bot für Telegram.
+
+Befehle:
+/25 — Einen neuen Timer mit 25 Minuten erstellen, z.B. /25 50, /25 at 14:30 oder /25 50 Kapitel 3 mit Beschriftung. Auch /pomodoro
+/stopevery — Die mit /25 every 1h regelmäßig erstellten Sessions beenden
+/when — Anzeigen, wann ein jetzt erstellter /25 beginnen würde
+/5 — Eine kurze Pause von 5 Minuten beginnen, z.B. /5 10 für eine längere. Auch /break
+/join — Einer Session beitreten, oder jemanden mit /join @alice zu deiner hinzufügen
+/leave — Eine Session verlassen
+/mute — In einer Session bleiben, ohne erwähnt zu werden, /unmute macht es rückgängig
+/cancel — Eine eigene Session abbrechen (als Antwort auf die Session)
+/undo — Die Session wiederherstellen, die du gerade abgebrochen oder verlassen hast
+/clearall — Alle Sessions dieser Gruppe entfernen (nur für Admins)
+/export — Die aktiven Sessions dieses Chats als JSON-Datei erhalten (in Gruppen nur für Admins)
+/transfer — Deine Session an einen anderen Teilnehmer übergeben, z.B. /transfer @alice (als Antwort auf die Session)
+/kick — Einen Teilnehmer aus deiner Session entfernen, z.B. /kick @alice (als Antwort auf die Session)
+/status — Die verbleibende Zeit einer Session anzeigen
+/list — Alle aktiven Sessions dieses Chats auflisten
+/mysessions — Die Sessions auflisten, an denen du teilnimmst
+/history — Die zuletzt abgeschlossenen Pomodoros dieses Chats anzeigen, z.B. /history 10
+/stats — Anzeigen, wie viele Pomodoros du abgeschlossen hast
+/leaderboard — Anzeigen, wer in dieser Gruppe in den letzten 7 Tagen die meisten Pomodoros abgeschlossen hat
+/pause — Deinen laufenden Pomodoro pausieren
+/resume — Deinen pausierten Pomodoro fortsetzen
+/skipbreak — Die Pause deiner Session überspringen
+/extend — Deinen laufenden Pomodoro verlängern, z.B. /extend 5
+/reschedule — Den Start deines wartenden Pomodoros verschieben, z.B. /reschedule 14:30 (als Antwort auf die Session)
+/remind — Festlegen, wann du vor dem Ende eines Pomodoros erinnert wirst, z.B. /remind 2
+/tz — Die Zeitzone dieses Chats festlegen, z.B. /tz Europe/Berlin
+/notify — Benachrichtigungen über neue Teilnehmer ein- oder ausschalten, z.B. /notify off
+/askcontinue — Diese Gruppe nach einer Pause fragen, ob es weitergeht, z.B. /askcontinue on
+/pin — Die Nachrichten von Sessions in dieser Gruppe anpinnen, z.B. /pin on
+/quiet — Während der Ruhezeiten niemanden erwähnen, z.B. /quiet 22:00-08:00 oder /quiet off
+/focus — Andere Nachrichten löschen, während ein Pomodoro läuft, z.B. /focus on oder /25 focus. /focus 4 startet 4 Pomodoros hintereinander
+/stopfocus — Eine Kette von Pomodoros nach der aktuellen Runde beenden
+/lang — Die Sprache dieses Chats festlegen, z.B. /lang en
+/setdefault — Die Standardlänge von Pomodoros und Pausen festlegen, z.B. /setdefault 50 10
+/settings — Die Einstellungen dieses Chats anzeigen
+/feedback — Feedback an den Betreiber dieses Bots senden, z.B. /feedback Toller Bot!
+/help — Diese Hilfe anzeigen.
+
+Befehle funktionieren auch großgeschrieben, z.B. /Join oder /JOIN.
+
+Dieser Bot unterstützt einen Mehrspielermodus!
+Erstelle einen /25 in einer Gruppe und andere können über einen Button \
+beitreten. Sobald die Uhr `minute % 5 == 0` erreicht, wirst du zum Start deiner Session gepingt.
+
+Made with 🥰🦀 by @yayayayaka
+https://github.com/yayayayaka/chaostomato"
+        }
+        _ => return None,
+    })
+}
+
+fn spanish(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "subscribers" => "Participantes:",
+        "pomodoro_created_group" => {
+            "¡@{} ha creado un nuevo Pomodoro!\n\
+            La sesión empezará a las {} ({})"
+        }
+        "pomodoro_created_group_length" => {
+            "¡@{} ha creado un nuevo Pomodoro de {} minutos!\n\
+            La sesión empezará a las {} ({})"
+        }
+        "pomodoro_started" => "¡La sesión de Pomodoro ha empezado!",
+        "pomodoro_started_length" => "¡La sesión de Pomodoro de {} minutos ha empezado!",
+        "pomodoro_scheduled" => "La sesión de Pomodoro empezará a las {} ({})",
+        "pomodoro_scheduled_length" => "La sesión de Pomodoro de {} minutos empezará a las {} ({})",
+        "session_over_short" => "¡La sesión ha terminado! Toma un descanso corto de {} minutos",
+        "session_over_long" => "¡La sesión ha terminado! Toma un descanso largo de {} minutos",
+        "next_start_at" => "La próxima sesión empezaría a las {} ({})",
+        "quiet_hours_active" => "Son horas de silencio en este chat ({}), no se mencionará a nadie.",
+        "next_start_now" => "Una sesión creada aquí empezaría inmediatamente.",
+        "language_set" => "El idioma de este chat ahora es español.",
+        "language_usage" => {
+            "El idioma de este chat es {}.\n\n\
+            Consejo: Usa /lang <idioma> para cambiarlo, p. ej. /lang en\n\
+            Idiomas disponibles: en, de, es"
+        }
+        "help" => {
+            "\
+{} — Otro bot de temporizador Pomodoro para Telegram.
+
+Comandos:
+/25 — Crear un nuevo temporizador de 25 minutos, p. ej. /25 50, /25 at 14:30 o /25 50 Capítulo 3 con etiqueta. También /pomodoro
+/stopevery — Detener las sesiones creadas regularmente con /25 every 1h
+/when — Mostrar cuándo empezaría un /25 creado ahora
+/5 — Empezar un descanso corto de 5 minutos, p. ej. /5 10 para uno más largo. También /break
+/join — Unirse a una sesión, o añadir a alguien a la tuya con /join @alice
+/leave — Salir de una sesión
+/mute — Seguir en una sesión sin ser mencionado, /unmute para deshacerlo
+/cancel — Cancelar una sesión que creaste (como respuesta a la sesión)
+/undo — Restaurar la sesión que acabas de cancelar o abandonar
+/clearall — Eliminar todas las sesiones de este grupo (solo administradores)
+/export — Obtener las sesiones activas de este chat como archivo JSON (en grupos solo administradores)
+/transfer — Ceder tu sesión a otro participante, p. ej. /transfer @alice (como respuesta a la sesión)
+/kick — Quitar a un participante de tu sesión, p. ej. /kick @alice (como respuesta a la sesión)
+/status — Mostrar el tiempo restante de una sesión
+/list — Mostrar todas las sesiones activas de este chat
+/mysessions — Mostrar las sesiones en las que participas
+/history — Mostrar los últimos Pomodoros completados de este chat, p. ej. /history 10
+/stats — Mostrar cuántos Pomodoros has completado
+/leaderboard — Mostrar quién completó más Pomodoros en este grupo en los últimos 7 días
+/pause — Pausar tu Pomodoro en curso
+/resume — Reanudar tu Pomodoro pausado
+/skipbreak — Saltar el descanso de tu sesión
+/extend — Alargar tu Pomodoro en curso, p. ej. /extend 5
+/reschedule — Cambiar el inicio de tu Pomodoro en espera, p. ej. /reschedule 14:30 (como respuesta a la sesión)
+/remind — Elegir cuándo recibir un aviso antes de que termine un Pomodoro, p. ej. /remind 2
+/tz — Establecer la zona horaria de este chat, p. ej. /tz Europe/Madrid
+/notify — Activar o desactivar avisos sobre nuevos participantes, p. ej. /notify off
+/askcontinue — Preguntar a este grupo si continuar después de un descanso, p. ej. /askcontinue on
+/pin — Fijar los mensajes de las sesiones en este grupo, p. ej. /pin on
+/quiet — No mencionar a nadie durante las horas de silencio, p. ej. /quiet 22:00-08:00 o /quiet off
+/focus — Borrar otros mensajes mientras corre un Pomodoro, p. ej. /focus on o /25 focus. /focus 4 hace 4 Pomodoros seguidos
+/stopfocus — Detener una cadena de Pomodoros después de la ronda actual
+/lang — Establecer el idioma de este chat, p. ej. /lang en
+/setdefault — Establecer la duración predeterminada de Pomodoros y descansos, p. ej. /setdefault 50 10
+/settings — Mostrar la configuración de este chat
+/feedback — Enviar comentarios al operador de este bot, p. ej. /feedback ¡Gran bot!
+/help — Mostrar esta ayuda.
+
+Los comandos también funcionan en mayúsculas, p. ej. /Join o /JOIN.
+
+¡Este bot tiene modo multijugador!
+Crea un /25 en un grupo y aparecerá un botón para que otros \
+se unan. En cuanto el reloj llegue a `minute % 5 == 0`, recibirás un aviso para empezar tu sesión.
+
+Made with 🥰🦀 by @yayayayaka
+https://github.com/yayayayaka/chaostomato"
+        }
+        _ => return None,
+    })
+}
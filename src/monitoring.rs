@@ -0,0 +1,55 @@
+//! A small HTTP server that exposes the metrics of the bot for monitoring.
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{error, info};
+
+use crate::state::State;
+
+/// Serve the metrics of the bot on `port` until the process exits.
+///
+/// - `GET /metrics`: the session counts in the Prometheus text format
+/// - `GET /health`: a JSON object with the number of active sessions
+pub(crate) async fn serve(state: Arc<State>, port: u16) {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_connection| {
+        let state = Arc::clone(&state);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                respond(Arc::clone(&state), request)
+            }))
+        }
+    });
+    info!("Serving metrics on port {}", port);
+    if let Err(err) = Server::bind(&addr).serve(make_service).await {
+        error!("The metrics server stopped: {}", err);
+    }
+}
+
+/// Answer a single request to the metrics server
+async fn respond(state: Arc<State>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(state.metrics().await.to_prometheus())),
+        (&Method::GET, "/health") => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(
+                "{{\"status\":\"ok\",\"sessions\":{}}}",
+                state.metrics().await.sessions
+            ))),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap_or_else(|err| {
+        error!("Failed to build a response: {}", err);
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    }))
+}
@@ -0,0 +1,65 @@
+use chrono_tz::Tz;
+use core::time::Duration;
+
+use super::QuietHours;
+use crate::{messages::Lang, time};
+
+/// The settings that are in effect for a chat, see `State::chat_settings`
+///
+/// Settings that have not been changed in the chat fall back to the global configuration.
+#[derive(Debug, Clone)]
+pub(crate) struct ChatSettings {
+    pub(crate) timezone: Tz,
+    pub(crate) language: Lang,
+    /// Length of a Pomodoro created without a duration
+    pub(crate) pomodoro_duration: Duration,
+    pub(crate) break_duration: Duration,
+    pub(crate) long_break_duration: Duration,
+    /// Number of Pomodoros until a long break, `0` if long breaks are disabled
+    pub(crate) long_break_interval: u32,
+    pub(crate) join_notifications: bool,
+    pub(crate) ask_to_continue: bool,
+    pub(crate) pin_sessions: bool,
+    pub(crate) quiet_hours: Option<QuietHours>,
+    /// Number of Pomodoros that are created regularly in the chat
+    pub(crate) recurrences: usize,
+}
+
+impl ChatSettings {
+    /// Return a listing of the settings
+    pub(crate) fn describe(&self) -> String {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        let long_breaks = match self.long_break_interval {
+            0 => "off".to_string(),
+            interval => format!(
+                "{} after every {} Pomodoros",
+                time::format_minutes(self.long_break_duration),
+                interval
+            ),
+        };
+        format!(
+            "Settings of this chat:\n\n\
+            Timezone: {}\n\
+            Language: {}\n\
+            Pomodoro: {}\n\
+            Break: {}\n\
+            Long break: {}\n\
+            Join notifications: {}\n\
+            Ask to continue: {}\n\
+            Pin sessions: {}\n\
+            Quiet hours: {}\n\
+            Recurring sessions: {}",
+            self.timezone.name(),
+            self.language.code(),
+            time::format_minutes(self.pomodoro_duration),
+            time::format_minutes(self.break_duration),
+            long_breaks,
+            on_off(self.join_notifications),
+            on_off(self.ask_to_continue),
+            on_off(self.pin_sessions),
+            self.quiet_hours
+                .map_or_else(|| "off".to_string(), |quiet_hours| quiet_hours.to_string()),
+            self.recurrences
+        )
+    }
+}
@@ -0,0 +1,33 @@
+use core::time::Duration;
+
+use tbot::types;
+use tokio::time::Instant;
+
+use super::session::Session;
+
+/// How long after a break its participants can continue with the same session
+const CONTINUE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// What a session whose break is over carries into the next Pomodoro, see
+/// `State::take_continuation`
+#[derive(Debug, Clone)]
+pub(crate) struct Continuation {
+    pub(crate) participants: Vec<types::User>,
+    pub(crate) label: Option<String>,
+    ended_at: Instant,
+}
+
+impl Continuation {
+    pub(super) fn new(session: &Session) -> Continuation {
+        Continuation {
+            participants: session.participants.to_owned(),
+            label: session.label.to_owned(),
+            ended_at: Instant::now(),
+        }
+    }
+
+    /// Return whether the session ended too long ago to continue it
+    pub(super) fn is_expired(&self) -> bool {
+        self.ended_at.elapsed() > CONTINUE_WINDOW
+    }
+}
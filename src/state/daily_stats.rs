@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use tbot::types::{self, user};
+
+/// Number of participants that are named in a daily summary
+const TOP_PARTICIPANTS: usize = 3;
+
+/// The Pomodoros completed in a chat since the last daily summary.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DailyStats {
+    /// Number of Pomodoros that reached their end
+    pub(crate) completed_pomodoros: u32,
+    /// Every participant with the number of Pomodoros they completed
+    pub(crate) participants: HashMap<user::Id, (types::User, u32)>,
+}
+
+impl DailyStats {
+    /// Count a completed Pomodoro with the given participants
+    pub(super) fn add_pomodoro(&mut self, participants: &[types::User]) {
+        self.completed_pomodoros += 1;
+        for user in participants {
+            self.participants
+                .entry(user.id)
+                .or_insert_with(|| (user.to_owned(), 0))
+                .1 += 1;
+        }
+    }
+
+    /// Return the summary that is posted to the chat
+    pub(crate) fn summary(&self) -> String {
+        let mut participants: Vec<&(types::User, u32)> = self.participants.values().collect();
+        participants.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let top: Vec<String> = participants
+            .iter()
+            .take(TOP_PARTICIPANTS)
+            .enumerate()
+            .map(|(index, (user, count))| {
+                format!(
+                    "{}. @{} ({})",
+                    index + 1,
+                    user.username.as_ref().unwrap_or(&user.first_name),
+                    count
+                )
+            })
+            .collect();
+        format!(
+            "Daily summary: {} Pomodoro{} completed today.\n\nTop participants:\n{}",
+            self.completed_pomodoros,
+            if self.completed_pomodoros == 1 {
+                ""
+            } else {
+                "s"
+            },
+            top.join("\n")
+        )
+    }
+}
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, future::Future};
+
+use core::time::Duration;
+use log::warn;
+use tbot::{errors::MethodCall, types::chat, Bot};
+use tokio::{
+    sync::Mutex,
+    time::{delay_for, delay_until, Instant},
+};
+
+/// Calls across all chats: 30 per second
+const GLOBAL_LIMIT: Limit = Limit {
+    interval: Duration::from_millis(34),
+    burst: 30,
+};
+/// Calls in a single group or channel: 20 per minute
+const GROUP_LIMIT: Limit = Limit {
+    interval: Duration::from_secs(3),
+    burst: 20,
+};
+/// Calls in a single private chat: 1 per second, with short bursts
+const PRIVATE_LIMIT: Limit = Limit {
+    interval: Duration::from_secs(1),
+    burst: 3,
+};
+/// How often a call is repeated after Telegram asked to retry it later or it failed for a
+/// transient reason
+const MAX_RETRIES: u32 = 3;
+/// How long to wait before repeating a call that failed for a transient reason the first time,
+/// doubled for every further attempt
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A rate of calls that may be exceeded by up to `burst` calls at once
+struct Limit {
+    interval: Duration,
+    burst: u32,
+}
+
+impl Limit {
+    /// Reserve the next slot of a bucket and return when it is due
+    ///
+    /// `next` is the time at which the bucket is empty again.
+    fn reserve(&self, next: &mut Instant, now: Instant) -> Instant {
+        let start = (*next).max(now);
+        *next = start + self.interval;
+        let tolerance = self.interval * (self.burst - 1);
+        start.checked_sub(tolerance).map_or(now, |due| due.max(now))
+    }
+}
+
+/// The buckets of the global and the per chat limits, see `Dispatcher`
+#[derive(Default)]
+pub(super) struct RateLimits {
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    global: Option<Instant>,
+    chats: HashMap<chat::Id, Instant>,
+}
+
+impl RateLimits {
+    /// Reserve a slot for a call in a chat and return when it may be made
+    async fn reserve(&self, chat_id: chat::Id) -> Instant {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+        // Buckets that are empty again behave like new ones
+        buckets.chats.retain(|_chat_id, next| *next > now);
+        let global = GLOBAL_LIMIT.reserve(buckets.global.get_or_insert(now), now);
+        // Group chats have negative ids
+        let limit = if chat_id.0 < 0 {
+            &GROUP_LIMIT
+        } else {
+            &PRIVATE_LIMIT
+        };
+        let chat = limit.reserve(buckets.chats.entry(chat_id).or_insert(now), now);
+        global.max(chat)
+    }
+}
+
+/// Makes the calls to the Bot API on behalf of the bot, within the rate limits of Telegram.
+///
+/// Calls are delayed so neither the global limit nor the limit of their chat is exceeded. If
+/// Telegram still responds with "Too Many Requests", the call is repeated after the time it
+/// asked for. Calls that failed because of the network or a server error are repeated with an
+/// exponential backoff, logical errors like "chat not found" are returned right away.
+#[derive(Clone, Copy)]
+pub(crate) struct Dispatcher<'a> {
+    bot: &'a Bot,
+    limits: &'a RateLimits,
+}
+
+impl<'a> Dispatcher<'a> {
+    pub(super) fn new(bot: &'a Bot, limits: &'a RateLimits) -> Self {
+        Dispatcher { bot, limits }
+    }
+
+    /// Make a call in a chat, e.g.
+    /// `dispatcher.call(chat_id, |bot| bot.send_message(chat_id, "Hi").call())`
+    ///
+    /// The call is built again for every attempt.
+    pub(crate) async fn call<T, F, Fut>(self, chat_id: chat::Id, call: F) -> Result<T, MethodCall>
+    where
+        F: Fn(&'a Bot) -> Fut,
+        Fut: Future<Output = Result<T, MethodCall>>,
+    {
+        let mut retries = 0;
+        loop {
+            delay_until(self.limits.reserve(chat_id).await).await;
+            match call(self.bot).await {
+                Err(MethodCall::RequestError {
+                    retry_after: Some(seconds),
+                    ..
+                }) if retries < MAX_RETRIES => {
+                    retries += 1;
+                    warn!(
+                        "Hit the rate limit in chat {}, retrying in {} seconds",
+                        chat_id, seconds
+                    );
+                    delay_for(Duration::from_secs(seconds)).await;
+                }
+                Err(err) if retries < MAX_RETRIES && is_transient(&err) => {
+                    let backoff = backoff(retries);
+                    retries += 1;
+                    warn!(
+                        "A call in chat {} failed, retrying in {} seconds: {}",
+                        chat_id,
+                        backoff.as_secs(),
+                        err
+                    );
+                    delay_for(backoff).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Return how long to wait before repeating a call that failed transiently `retries` times before
+fn backoff(retries: u32) -> Duration {
+    FIRST_BACKOFF * 2u32.pow(retries)
+}
+
+/// Return whether a call might succeed if it is repeated as it is
+fn is_transient(err: &MethodCall) -> bool {
+    matches!(
+        err,
+        MethodCall::Network(_)
+            | MethodCall::OutOfService
+            | MethodCall::RequestError {
+                error_code: 500..=599,
+                ..
+            }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn request_error(error_code: u16) -> MethodCall {
+        MethodCall::RequestError {
+            description: "Something went wrong".to_string(),
+            error_code,
+            migrate_to_chat_id: None,
+            retry_after: None,
+        }
+    }
+
+    fn bot() -> Bot {
+        Bot::new("1234567890:ABCDE_FGH".to_string())
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried() {
+        let bot = bot();
+        let limits = RateLimits::default();
+        let attempts = AtomicU32::new(0);
+        let result = Dispatcher::new(&bot, &limits)
+            .call(chat::Id(42), |_bot| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(MethodCall::OutOfService),
+                    _ => Ok("sent"),
+                }
+            })
+            .await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(result.ok(), Some("sent"));
+    }
+
+    #[tokio::test]
+    async fn logical_errors_are_returned_right_away() {
+        let bot = bot();
+        let limits = RateLimits::default();
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = Dispatcher::new(&bot, &limits)
+            .call(chat::Id(42), |_bot| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(request_error(400))
+            })
+            .await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            result,
+            Err(MethodCall::RequestError {
+                error_code: 400,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn client_errors_are_not_transient() {
+        for error_code in [400, 403, 404, 429] {
+            assert!(!is_transient(&request_error(error_code)), "{}", error_code);
+        }
+    }
+
+    #[test]
+    fn server_errors_are_transient() {
+        for error_code in [500, 502, 503, 599] {
+            assert!(is_transient(&request_error(error_code)), "{}", error_code);
+        }
+        assert!(is_transient(&MethodCall::OutOfService));
+    }
+
+    /// A network error, from a response body whose connection broke off
+    async fn network_error() -> MethodCall {
+        let (sender, body) = hyper::Body::channel();
+        sender.abort();
+        MethodCall::Network(hyper::body::to_bytes(body).await.unwrap_err())
+    }
+
+    #[tokio::test]
+    async fn network_errors_are_retried_after_a_backoff() {
+        let bot = bot();
+        let limits = RateLimits::default();
+        let attempts = AtomicU32::new(0);
+        let started = Instant::now();
+        let result = Dispatcher::new(&bot, &limits)
+            .call(chat::Id(42), |_bot| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(network_error().await),
+                    _ => Ok("sent"),
+                }
+            })
+            .await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(result.ok(), Some("sent"));
+        assert!(started.elapsed() >= FIRST_BACKOFF);
+    }
+
+    #[test]
+    fn backoff_doubles_for_every_retry() {
+        let backoffs: Vec<u64> = (0..MAX_RETRIES).map(|r| backoff(r).as_secs()).collect();
+        assert_eq!(backoffs, [1, 2, 4]);
+    }
+}
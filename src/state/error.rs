@@ -0,0 +1,92 @@
+use std::fmt;
+
+/// The reasons an operation on the state can fail
+///
+/// The `Display` implementation is the message shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StateError {
+    /// There is no session for the given message (anymore)
+    SessionNotFound,
+    /// There is no session in the chat the request could refer to
+    NoSessions,
+    /// There is a session for the given message already
+    SessionExists,
+    /// Only the creator of the session is allowed to do this, e.g. "cancel the session"
+    NotOwner(&'static str),
+    /// The user owns the session already
+    AlreadyOwner,
+    /// The user takes part in the session already; `None` if it is the user who asked
+    AlreadyParticipant(Option<String>),
+    /// The user does not take part in the session; `None` if it is the user who asked
+    NotParticipant(Option<String>),
+    /// The bot does not know a user with this username in the chat
+    UnknownUser(String),
+    /// The chat reached the maximum number of concurrent sessions
+    TooManySessions,
+    /// The user has a session waiting to be started in the chat already
+    PendingSession,
+    /// A private chat holds one session at a time, and it has one already
+    PrivateSessionRunning,
+    /// A group Pomodoro can only be started early once others joined
+    NotEnoughParticipants,
+    /// The session is not in a state that allows this
+    InvalidState(&'static str),
+    /// A value supplied by the user is out of range
+    InvalidInput(String),
+    /// Sessions can only be held in groups, channels and private chats
+    UnsupportedChat,
+    /// There is no recently removed session the user could restore
+    NothingToUndo,
+    /// The state is inconsistent, which should never happen
+    Internal(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::SessionNotFound => write!(f, "This session does not exist (anymore)."),
+            StateError::NoSessions => write!(
+                f,
+                "There are no active sessions in this chat.\n\n\
+                Hint: Use /25 to create a new session."
+            ),
+            StateError::SessionExists => write!(f, "This session exists already."),
+            StateError::NotOwner(action) => {
+                write!(f, "Only the creator is allowed to {}", action)
+            }
+            StateError::AlreadyOwner => write!(f, "You already own this session."),
+            StateError::AlreadyParticipant(None) => write!(f, "You are already subscribed!"),
+            StateError::AlreadyParticipant(Some(username)) => {
+                write!(f, "@{} is already subscribed!", username)
+            }
+            StateError::NotParticipant(None) => {
+                write!(f, "You are not subscribed to this session.")
+            }
+            StateError::NotParticipant(Some(username)) => {
+                write!(f, "{} is not a participant of this session.", username)
+            }
+            StateError::UnknownUser(username) => write!(
+                f,
+                "I don't know @{} in this chat. They have to join a session once themselves.",
+                username
+            ),
+            StateError::TooManySessions => write!(f, "Too many active sessions in this chat"),
+            StateError::PendingSession => write!(f, "You already have a pending session here."),
+            StateError::PrivateSessionRunning => write!(
+                f,
+                "You already have a session running. Reply /cancel to it to stop it first."
+            ),
+            StateError::NotEnoughParticipants => {
+                write!(f, "Wait for others to join before starting")
+            }
+            StateError::InvalidState(msg) => write!(f, "{}", msg),
+            StateError::InvalidInput(msg) => write!(f, "{}", msg),
+            StateError::UnsupportedChat => write!(
+                f,
+                "Chat kind is neither a group, a channel nor a private chat"
+            ),
+            StateError::NothingToUndo => write!(f, "Nothing to undo."),
+            StateError::Internal(msg) => write!(f, "Unexpected error: {}", msg),
+        }
+    }
+}
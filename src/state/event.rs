@@ -0,0 +1,32 @@
+use tbot::types::{chat, message};
+use tokio::sync::broadcast;
+
+use super::session_state::SessionState;
+
+/// Number of events a subscriber may fall behind before it misses some
+const CAPACITY: usize = 64;
+
+/// A change in the lifecycle of a session, see `State::subscribe_events`
+#[derive(Debug, Clone)]
+pub(crate) enum SessionEvent {
+    /// A session was created or entered a new state
+    StateChanged {
+        chat_id: chat::Id,
+        message_id: message::Id,
+        state: SessionState,
+    },
+    /// A session was removed, because it is over or has been cancelled
+    Ended {
+        chat_id: chat::Id,
+        message_id: message::Id,
+    },
+}
+
+/// The sending half of the session events channel
+pub(super) struct EventSender(pub(super) broadcast::Sender<SessionEvent>);
+
+impl Default for EventSender {
+    fn default() -> Self {
+        EventSender(broadcast::channel(CAPACITY).0)
+    }
+}
@@ -0,0 +1,63 @@
+//! Export of the sessions of a chat as JSON, e.g. for analysis.
+//!
+//! Unlike the sessions file, the export is meant to be read by people and other tools, so it
+//! only holds what describes a session and leaves out the messages it consists of. Times are
+//! unix timestamps in milliseconds, durations are in seconds.
+use chrono::Utc;
+use serde_json::{json, Value};
+use tbot::types::{chat, User};
+
+use super::{SessionSnapshot, State};
+use crate::time;
+
+impl State {
+    /// Return the active sessions of a chat as JSON, oldest first
+    ///
+    /// Users are identified by their username or first name. Their ids are only included if
+    /// `Config::redact_user_ids` is off.
+    pub(crate) async fn export_chat(&self, chat_id: chat::Id) -> Value {
+        let redact = self.config.redact_user_ids;
+        let user_to_value = |user: &User| {
+            let name = user.username.as_ref().unwrap_or(&user.first_name);
+            if redact {
+                json!({ "name": name })
+            } else {
+                json!({ "id": user.id.0, "name": name })
+            }
+        };
+        let mut sessions: Vec<_> = self
+            .entries
+            .lock()
+            .await
+            .iter()
+            .filter(|(cache_key, _entry)| cache_key.chat_id == chat_id)
+            .map(|(_cache_key, (session, _key))| {
+                let snapshot = SessionSnapshot::from(session);
+                let value = json!({
+                    "message_id": snapshot.cache_key.message_id.0,
+                    "state": snapshot.state,
+                    "label": session.label,
+                    "creator": user_to_value(&session.creator),
+                    "participants": session
+                        .participants
+                        .iter()
+                        .map(user_to_value)
+                        .collect::<Vec<_>>(),
+                    "start_time": time::instant_to_timestamp(snapshot.start_time),
+                    "duration": snapshot.duration.as_secs(),
+                    "remaining": snapshot.remaining.as_secs(),
+                });
+                (session.seq, value)
+            })
+            .collect();
+        sessions.sort_by_key(|(seq, _value)| *seq);
+        json!({
+            "chat_id": chat_id.0,
+            "exported_at": Utc::now().timestamp_millis(),
+            "sessions": sessions
+                .into_iter()
+                .map(|(_seq, value)| value)
+                .collect::<Vec<_>>(),
+        })
+    }
+}
@@ -0,0 +1,42 @@
+use core::time::Duration;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use tbot::types::{self, chat};
+
+/// A Pomodoro that reached its end
+#[derive(Debug, Clone)]
+pub(crate) struct SessionRecord {
+    /// The chat the session took place in
+    pub(crate) chat_id: chat::Id,
+    /// The owner of the session when it ended
+    pub(crate) creator: types::User,
+    /// The participants of the session when it ended
+    pub(crate) participants: Vec<types::User>,
+    /// Duration of the Pomodoro
+    pub(crate) duration: Duration,
+    /// When the Pomodoro ended
+    pub(crate) completed_at: DateTime<Utc>,
+}
+
+impl SessionRecord {
+    /// Return a single line describing the session, with times in the given timezone
+    pub(crate) fn describe(&self, tz: Tz) -> String {
+        let participants = match self.participants.len() {
+            1 => "1 participant".to_string(),
+            count => format!("{} participants", count),
+        };
+        format!(
+            "{}: {} Pomodoro by @{} ({})",
+            self.completed_at
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M"),
+            self.duration.as_secs() / 60,
+            self.creator
+                .username
+                .as_ref()
+                .unwrap_or(&self.creator.first_name),
+            participants
+        )
+    }
+}
@@ -0,0 +1,68 @@
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use tbot::types::{self, chat, user};
+
+/// How long completed Pomodoros count towards the leaderboard
+pub(crate) const LEADERBOARD_WINDOW: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// A Pomodoro that a user completed in a chat
+#[derive(Debug, Clone)]
+pub(super) struct Completion {
+    pub(super) chat_id: chat::Id,
+    pub(super) user: types::User,
+    pub(super) duration: Duration,
+    pub(super) completed_at: DateTime<Utc>,
+}
+
+/// A line of the leaderboard of a chat, see `State::leaderboard`
+#[derive(Debug, Clone)]
+pub(crate) struct Rank {
+    /// The place of the user, starting at 1. Users that are tied share a place.
+    pub(crate) place: usize,
+    pub(crate) user: types::User,
+    pub(crate) pomodoros: u32,
+    pub(crate) focus_time: Duration,
+}
+
+/// Rank the users of a chat by the Pomodoros they completed since `since`
+///
+/// More focus time breaks ties in the number of Pomodoros, users with the same number of both
+/// share a place.
+pub(super) fn rank(
+    completions: &VecDeque<Completion>,
+    chat_id: chat::Id,
+    since: DateTime<Utc>,
+) -> Vec<Rank> {
+    let mut totals: HashMap<user::Id, (types::User, u32, Duration)> = HashMap::new();
+    for completion in completions
+        .iter()
+        .filter(|completion| completion.chat_id == chat_id && completion.completed_at >= since)
+    {
+        let total = totals
+            .entry(completion.user.id)
+            .or_insert_with(|| (completion.user.to_owned(), 0, Duration::from_secs(0)));
+        total.1 += 1;
+        total.2 += completion.duration;
+    }
+    let mut totals: Vec<(types::User, u32, Duration)> = totals.into_values().collect();
+    totals.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+
+    let mut ranks: Vec<Rank> = Vec::with_capacity(totals.len());
+    for (index, (user, pomodoros, focus_time)) in totals.into_iter().enumerate() {
+        let place = match ranks.last() {
+            Some(last) if last.pomodoros == pomodoros && last.focus_time == focus_time => {
+                last.place
+            }
+            _ => index + 1,
+        };
+        ranks.push(Rank {
+            place,
+            user,
+            pomodoros,
+            focus_time,
+        });
+    }
+    ranks
+}
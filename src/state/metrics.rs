@@ -0,0 +1,68 @@
+use std::fmt::Write;
+
+use super::{session_state::SessionState, SessionSnapshot};
+
+/// A snapshot of all active sessions, see `State::metrics`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metrics {
+    /// Number of active sessions across all chats
+    pub(crate) sessions: usize,
+    pub(crate) pomodoros_waiting: usize,
+    pub(crate) pomodoros_running: usize,
+    pub(crate) pomodoros_paused: usize,
+    pub(crate) breaks_waiting: usize,
+    pub(crate) breaks_running: usize,
+    /// Number of participants summed over all sessions, a user in two sessions counts twice
+    pub(crate) participants: usize,
+    /// Number of chats with at least one active session
+    pub(crate) chats: usize,
+}
+
+impl Metrics {
+    /// Count a session
+    pub(super) fn count(&mut self, session: &SessionSnapshot) {
+        self.sessions += 1;
+        self.participants += session.participants;
+        match session.state {
+            SessionState::PomodoroWaiting => self.pomodoros_waiting += 1,
+            SessionState::PomodoroRunning => self.pomodoros_running += 1,
+            SessionState::PomodoroPaused => self.pomodoros_paused += 1,
+            SessionState::BreakWaiting => self.breaks_waiting += 1,
+            SessionState::BreakRunning => self.breaks_running += 1,
+        }
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub(crate) fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "# HELP chaostomato_sessions Number of active sessions by state\n\
+            # TYPE chaostomato_sessions gauge"
+        );
+        for (state, count) in &[
+            ("pomodoro_waiting", self.pomodoros_waiting),
+            ("pomodoro_running", self.pomodoros_running),
+            ("pomodoro_paused", self.pomodoros_paused),
+            ("break_waiting", self.breaks_waiting),
+            ("break_running", self.breaks_running),
+        ] {
+            let _ = writeln!(
+                text,
+                "chaostomato_sessions{{state=\"{}\"}} {}",
+                state, count
+            );
+        }
+        let _ = writeln!(
+            text,
+            "# HELP chaostomato_participants Number of participants of all active sessions\n\
+            # TYPE chaostomato_participants gauge\n\
+            chaostomato_participants {}\n\
+            # HELP chaostomato_chats Number of chats with active sessions\n\
+            # TYPE chaostomato_chats gauge\n\
+            chaostomato_chats {}",
+            self.participants, self.chats
+        );
+        text
+    }
+}
@@ -1,94 +1,324 @@
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    future::Future,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use core::time::Duration;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use tbot::{
+    errors::MethodCall,
     types,
-    types::{chat, keyboard::inline, message, user},
+    types::{chat, keyboard::inline, message, parameters::Text, user},
     Bot,
 };
 use tokio::{
-    join,
-    time::{delay_queue, DelayQueue, Instant},
+    sync::{broadcast, Mutex},
+    time::{delay_for, delay_queue, DelayQueue, Instant},
 };
 
-use self::{session::Session, session_state::SessionState};
+use log::{debug, error, warn};
+
+use self::{
+    dispatcher::RateLimits,
+    event::EventSender,
+    leaderboard::Completion,
+    session::{pin_message, Departure, Nudge, Rounds, Session},
+    tombstone::Tombstone,
+};
 
-use crate::markup::inline::JOIN;
+use crate::{
+    anonymous,
+    chat_kind::{classify, ChatKindClass},
+    config::Config,
+    markup,
+    messages::{self, Lang},
+    time,
+};
 
+mod chat_settings;
+mod continuation;
+mod daily_stats;
+mod dispatcher;
+mod error;
+mod event;
+mod export;
+mod history;
+mod leaderboard;
+mod metrics;
 pub(crate) mod periodic;
+mod persistence;
+mod quiet_hours;
+mod recurrence;
 mod session;
 mod session_state;
+mod snapshot;
+mod tombstone;
+mod user_stats;
+
+pub(crate) use self::{
+    chat_settings::ChatSettings,
+    continuation::Continuation,
+    daily_stats::DailyStats,
+    dispatcher::Dispatcher,
+    error::StateError,
+    event::SessionEvent,
+    history::SessionRecord,
+    leaderboard::{Rank, LEADERBOARD_WINDOW},
+    metrics::Metrics,
+    quiet_hours::QuietHours,
+    recurrence::Recurrence,
+    snapshot::SessionSnapshot,
+    user_stats::UserStats,
+};
+
+/// Maximum number of messages that are sent at once when notifying many chats or users,
+/// which keeps the bot below the rate limits of Telegram.
+const MAX_CONCURRENT_SENDS: usize = 10;
+/// How long a second tap on a button that creates a Pomodoro is ignored, see
+/// `State::debounce_creation`
+const CREATION_DEBOUNCE: Duration = Duration::from_secs(2);
+/// How long a participant is assumed to still be in a group before it is checked again
+const MEMBERSHIP_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Maximum number of memberships that are checked at once, see `State::due_membership_checks`
+const MAX_MEMBERSHIP_CHECKS: usize = 10;
+
+/// The sessions by their message, along with their key in `State::expirations`
+type Entries = HashMap<CacheKey, (Session, Option<delay_queue::Key>)>;
 
 /// The bot's state.
-#[derive(Default, Debug)]
+#[derive(Default)]
 pub(crate) struct State {
     /// A queue that holds information about which item is going to expire next.
     pub(self) expirations: Mutex<DelayQueue<CacheKey>>,
+    /// A queue of reminders that are sent shortly before a Pomodoro ends.
+    ///
+    /// It also holds the heads-ups before a Pomodoro starts and the checks whether it starts early
+    /// because nobody joined. Their keys are kept on the session, so they are removed along with
+    /// it. Keys are only taken or removed while holding the lock of `entries`.
+    pub(self) reminders: Mutex<DelayQueue<(CacheKey, Nudge)>>,
+    /// A queue of sessions that everyone left, which are deleted once their grace period is over.
+    ///
+    /// They are checked against the session when they expire.
+    pub(self) deletions: Mutex<DelayQueue<CacheKey>>,
     /// A HashMap of saved entries with with information about when the entry shall be yielded back.
-    pub(self) entries: Mutex<HashMap<CacheKey, (Session, delay_queue::Key)>>,
+    ///
+    /// Paused sessions are not part of the DelayQueue and therefore have no key.
+    pub(self) entries: Mutex<Entries>,
+    /// The timezone configured for a chat. Chats without an entry use UTC.
+    pub(self) timezones: Mutex<HashMap<chat::Id, Tz>>,
+    /// The language configured for a chat. Chats without an entry use English.
+    pub(self) languages: Mutex<HashMap<chat::Id, Lang>>,
+    /// The default Pomodoro and break durations of a chat.
+    pub(self) defaults: Mutex<HashMap<chat::Id, (Duration, Duration)>>,
+    /// Pomodoros that are created regularly in a chat.
+    pub(self) recurrences: Mutex<HashMap<chat::Id, Vec<Recurrence>>>,
+    /// Chats in which creators are not notified when someone joins their session.
+    pub(self) join_disabled_chats: Mutex<HashSet<chat::Id>>,
+    /// Groups in which the participants are asked to continue once a break is over.
+    pub(self) continue_prompt_chats: Mutex<HashSet<chat::Id>>,
+    /// Groups in which the messages of sessions are pinned.
+    pub(self) pin_chats: Mutex<HashSet<chat::Id>>,
+    /// The quiet hours of chats, during which nobody is mentioned.
+    pub(self) quiet_hours: Mutex<HashMap<chat::Id, QuietHours>>,
+    /// The participants and label of the last session of a chat whose break is over.
+    pub(self) continuations: Mutex<HashMap<chat::Id, Continuation>>,
+    /// When a user last created a Pomodoro with a button in a chat.
+    pub(self) last_creations: Mutex<HashMap<(chat::Id, user::Id), Instant>>,
+    /// When it was last checked that a participant is still in a group.
+    pub(self) membership_checks: Mutex<HashMap<(chat::Id, user::Id), Instant>>,
+    /// Sessions whose participant list is about to be refreshed.
+    pub(self) pending_edits: Mutex<HashSet<CacheKey>>,
+    /// The sequence number of the next session that is created.
+    pub(self) next_seq: AtomicU64,
+    /// The number of Pomodoros completed in a chat since the last long break or ended session.
+    pub(self) completed_pomodoros: Mutex<HashMap<chat::Id, u32>>,
+    /// Statistics about the completed Pomodoros of every user.
+    pub(self) user_stats: Mutex<HashMap<user::Id, UserStats>>,
+    /// The Pomodoros completed in a chat since its last daily summary.
+    pub(self) daily_stats: Mutex<HashMap<chat::Id, DailyStats>>,
+    /// The most recently completed Pomodoros of all chats, oldest first.
+    pub(self) history: Mutex<VecDeque<SessionRecord>>,
+    /// The Pomodoros every user completed within `LEADERBOARD_WINDOW`, oldest first.
+    pub(self) completions: Mutex<VecDeque<Completion>>,
+    /// The session that was removed last in a chat, which can be restored with /undo.
+    pub(self) tombstones: Mutex<HashMap<chat::Id, Tombstone>>,
+    /// Lifecycle events of all sessions, for integrations.
+    pub(self) events: EventSender,
+    /// The rate limits of the calls to the Bot API, see `Dispatcher`.
+    pub(self) rate_limits: RateLimits,
+    /// The global configuration
+    pub(self) config: Config,
+    /// The username of the bot, fetched once at startup. Empty if it could not be fetched.
+    pub(self) bot_username: String,
 }
 
 impl State {
+    /// Create a new State with the given configuration
+    pub(crate) fn new(config: Config) -> State {
+        State {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Remember the username of the bot
+    pub(crate) fn set_bot_username(&mut self, username: String) {
+        self.bot_username = username;
+    }
+
+    /// Return the username of the bot, or an empty string if it is unknown
+    pub(crate) fn bot_username(&self) -> &str {
+        &self.bot_username
+    }
+
+    /// Return a dispatcher that makes calls with `bot` within the rate limits of Telegram
+    pub(crate) fn dispatcher<'a>(&'a self, bot: &'a Bot) -> Dispatcher<'a> {
+        Dispatcher::new(bot, &self.rate_limits)
+    }
+
+    /// Subscribe to the lifecycle events of all sessions
+    ///
+    /// Subscribers that fall too far behind miss the oldest events.
+    pub(crate) fn subscribe_events(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.0.subscribe()
+    }
+
+    /// Return the hour (UTC) of the daily summary, if it is enabled
+    pub(crate) fn daily_summary_hour(&self) -> Option<u32> {
+        self.config.daily_summary_hour
+    }
+
+    /// Return the chat that /feedback is forwarded to, if any
+    pub(crate) fn owner_chat_id(&self) -> Option<chat::Id> {
+        self.config.owner_chat_id
+    }
+
+    /// Return the range of durations users may ask for, see `time::parse_duration`
+    pub(crate) fn duration_bounds(&self) -> RangeInclusive<Duration> {
+        let min = self.config.min_duration;
+        min..=self.config.max_duration.max(min)
+    }
+
+    /// Return the interval in minutes that group Pomodoros are aligned to
+    pub(crate) fn start_interval(&self) -> u32 {
+        self.config.start_interval
+    }
+
     /// Create a new Pomodoro session and add it to the DelayQueue.
     ///
     /// It it possible to override the default start time and duration by passing `Some(Instant)`
     /// to `start_time` and `Some(Duration)` to `duration`.
-    /// The functionality to create sessions with custom durations or start times has not been
-    /// implemented on the bot yet.
+    /// Custom durations can be requested via `/25 <duration>`, custom start times via
+    /// `/25 at <HH:MM>`.
+    ///
+    /// `header` is the text of the message above its list of subscribers, if it has one.
+    ///
+    /// Fails if the chat already reached the maximum number of concurrent sessions, if the
+    /// creator already has a session waiting to be started there and
+    /// `Config::one_pending_session_per_user` is set, or if it is a private chat that has a
+    /// session already.
     pub(crate) async fn new_pomodoro(
         &self,
         message: types::Message,
         creator: types::User,
         start_time: Option<Instant>,
         duration: Option<Duration>,
-    ) -> Result<(), String> {
+        header: Option<String>,
+        label: Option<String>,
+    ) -> Result<(), StateError> {
         let cache_key = CacheKey::new(message.chat.id, message.id);
-        match self.session_exists(&cache_key) {
+        match self.session_exists(&cache_key).await {
             Ok(..) => {
-                let err_msg = format!(
-                    "Message {}  in chat {} is already present in state",
+                warn!(
+                    "Message {} in chat {} is already present in state",
                     &message.id, &message.chat.id
                 );
-                dbg!(&err_msg);
-                Err(err_msg)
+                Err(StateError::SessionExists)
             }
             Err(_) => {
-                let pomodoro = Session::new_pomodoro(message, creator, start_time, duration)?;
-                self.add_session_to_queue(pomodoro);
+                self.may_create_pomodoro(&message.chat, &creator.id).await?;
+                let mut pomodoro = Session::new_pomodoro(
+                    message,
+                    creator,
+                    start_time,
+                    duration,
+                    self.config.start_interval,
+                )?;
+                pomodoro.reminder = Some(self.config.reminder_offset).filter(|o| !o.is_zero());
+                pomodoro.seq = self.next_seq();
+                pomodoro.header = header;
+                pomodoro.label = label;
+                self.publish_state(&cache_key, &pomodoro);
+                self.add_session_to_queue(pomodoro, start_time.is_none())
+                    .await;
                 Ok(())
             }
         }
     }
 
+    /// Check whether `creator_id` may create another Pomodoro in `chat`
+    ///
+    /// It is checked before the message of the session is sent, so a rejected session leaves no
+    /// message behind, and again by `new_pomodoro`.
+    pub(crate) async fn may_create_pomodoro(
+        &self,
+        chat: &chat::Chat,
+        creator_id: &user::Id,
+    ) -> Result<(), StateError> {
+        self.ensure_private_chat_is_free(chat).await?;
+        let sessions = self.sessions_in_chat(chat).await;
+        let max_sessions = self.config.max_sessions_per_chat;
+        if max_sessions > 0 && sessions.len() >= max_sessions {
+            return Err(StateError::TooManySessions);
+        }
+        if self.config.one_pending_session_per_user
+            && sessions
+                .iter()
+                .any(|session| session.is_waiting() && &session.creator.id == creator_id)
+        {
+            return Err(StateError::PendingSession);
+        }
+        Ok(())
+    }
+
     /// Create a new Break session and add it to the DelayQueue.
     ///
     /// It it possible to override the default start time and duration by passing `Some(Instant)`
     /// to `start_time` and `Some(Duration)` to `duration`.
     /// The functionality to create breaks with custom durations or start times has not been
     /// implemented on the bot yet.
-    pub(crate) fn new_break(
+    pub(crate) async fn new_break(
         &self,
         message: types::Message,
         creator: types::User,
         start_time: Option<Instant>,
         duration: Option<Duration>,
-    ) -> Result<(), String> {
+    ) -> Result<(), StateError> {
         let cache_key = CacheKey::new(message.chat.id, message.id);
-        match self.session_exists(&cache_key) {
+        match self.session_exists(&cache_key).await {
             Ok(..) => {
                 // Pomodoro is present in state
-                let err_msg = format!(
-                    "Message {}  in chat {} is already present in state",
+                warn!(
+                    "Message {} in chat {} is already present in state",
                     &message.id, &message.chat.id
                 );
-                dbg!(&err_msg);
-                Err(err_msg)
+                Err(StateError::SessionExists)
             }
             Err(_) => {
                 // Not present in state
-                let pomodoro = Session::new_break(message, creator, start_time, duration)?;
-                self.add_session_to_queue(pomodoro);
+                self.ensure_private_chat_is_free(&message.chat).await?;
+                let mut pomodoro = Session::new_break(message, creator, start_time, duration)?;
+                pomodoro.seq = self.next_seq();
+                self.publish_state(&cache_key, &pomodoro);
+                self.add_session_to_queue(pomodoro, false).await;
                 Ok(())
             }
         }
@@ -97,446 +327,2284 @@ impl State {
     /// Attempt to start a Pomodoro now
     ///
     /// By design, only the creator of the session is permitted to start the session prematurely.
+    /// In groups, the session may additionally need another participant first, see
+    /// `Config::early_start_requires_participants`.
     pub(crate) async fn start_session_now(
         &self,
         bot: &Bot,
         user: &types::User,
-        message: &types::Message,
-    ) -> Result<String, String> {
-        let cache_key = CacheKey::new(message.chat.id, message.id);
-        self.session_exists(&cache_key)?;
-        if let Err(_) = self.is_owner(&cache_key, &user.id) {
-            return Err("Only the creator is allowed to start the session".to_string());
+        cache_key: &CacheKey,
+    ) -> Result<String, StateError> {
+        let cache_key = cache_key.to_owned();
+        self.session_exists(&cache_key).await?;
+        if self.is_owner(&cache_key, &user.id).await.is_err() {
+            return Err(StateError::NotOwner("start the session"));
+        }
+        if self.config.early_start_requires_participants {
+            if let Some((session, _key)) = self.entries.lock().await.get(&cache_key) {
+                let is_group = classify(&session.message.chat) == ChatKindClass::Group;
+                if is_group && session.participants.len() < 2 {
+                    return Err(StateError::NotEnoughParticipants);
+                }
+            }
         }
 
-        let result: Option<(Session, delay_queue::Key)>;
-        {
-            let mut entries = self.entries.lock().unwrap();
-            result = entries.remove(&cache_key);
+        let entry = {
+            let mut entries = self.entries.lock().await;
+            self.take_entry(&mut entries, &cache_key).await
+        };
+        match entry {
+            Some((mut pomodoro, key)) => {
+                self.remove_expiration(key).await;
+                let tz = self.get_timezone(cache_key.chat_id).await;
+                let ends_at = time::instant_as_hh_mm(Instant::now() + pomodoro.duration, tz);
+                pomodoro
+                    .notify_participants_on_start(self.dispatcher(bot))
+                    .await;
+                self.start_session(pomodoro).await;
+                Ok(format!("Let's go! Ends at {}", ends_at))
+            }
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Return how much time is left of a break the user takes part in, in the given chat
+    ///
+    /// Breaks that are about to start count as well, with their full duration left.
+    pub(crate) async fn remaining_break(
+        &self,
+        chat: &types::Chat,
+        user_id: &user::Id,
+    ) -> Option<Duration> {
+        self.sessions_in_chat(chat)
+            .await
+            .iter()
+            .filter(|session| session.is_taking_a_break() || session.is_awaiting_break())
+            .filter(|session| session.participants.iter().any(|user| user.id == *user_id))
+            .map(|session| {
+                (session.start_time + session.duration).saturating_duration_since(Instant::now())
+            })
+            .max()
+    }
+
+    /// Cancel a session and delete the associated message
+    ///
+    /// Only the creator of the session is permitted to cancel it.
+    pub(crate) async fn cancel_session(
+        &self,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        user: &types::User,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, &user.id).await.is_err() {
+            return Err(StateError::NotOwner("cancel the session"));
+        }
+
+        let mut session = self.remove_session_from_queue(cache_key).await?;
+        session.unpin(self.dispatcher(bot)).await;
+        self.reset_completed_pomodoros(cache_key.chat_id).await;
+        self.bury(session, user).await;
+        if let Err(err) = self.delete_message(bot, cache_key).await {
+            error!("{}", err);
         }
-        if let Some((mut pomodoro, key)) = result {
-            pomodoro.state = SessionState::PomodoroRunning;
-            pomodoro.notify_participants_on_start(bot).await;
+        Ok("The session has been cancelled.".to_string())
+    }
 
-            let mut expirations = self.expirations.lock().unwrap();
-            expirations.remove(&key);
-            let mut entries = self.entries.lock().unwrap();
-            let key = expirations.insert(cache_key.to_owned(), pomodoro.duration);
-            entries.insert(cache_key, (pomodoro, key));
+    /// Remove all sessions of a chat and delete their messages
+    ///
+    /// Messages that cannot be deleted, e.g. because they are already gone, are only logged.
+    /// Returns the number of removed sessions.
+    pub(crate) async fn clear_chat_sessions(&self, bot: &Bot, chat_id: chat::Id) -> usize {
+        let removed: Vec<(CacheKey, Session, Option<delay_queue::Key>)> = {
+            let mut entries = self.entries.lock().await;
+            let cache_keys: Vec<CacheKey> = entries
+                .keys()
+                .filter(|cache_key| cache_key.chat_id == chat_id)
+                .cloned()
+                .collect();
+            let mut removed = Vec::with_capacity(cache_keys.len());
+            for cache_key in cache_keys {
+                if let Some((session, delay_key)) = self.take_entry(&mut entries, &cache_key).await
+                {
+                    removed.push((cache_key, session, delay_key));
+                }
+            }
+            removed
+        };
+        let cleared = removed.len();
+        for (cache_key, mut session, delay_key) in removed {
+            self.remove_expiration(delay_key).await;
+            self.publish_ended(&cache_key);
+            session.unpin(self.dispatcher(bot)).await;
+            if let Err(err) = self.delete_message(bot, &cache_key).await {
+                warn!(
+                    "Could not delete message {} in chat {}: {}",
+                    cache_key.message_id, cache_key.chat_id, err
+                );
+            }
         }
-        Ok("Let's go!".to_string())
+        if cleared > 0 {
+            self.reset_completed_pomodoros(chat_id).await;
+        }
+        cleared
     }
 
     /// Start the session by updating the session state and putting it back into the DelayQueue.
-    pub(crate) fn start_session(&self, mut pomodoro: Session) {
-        pomodoro.state = SessionState::PomodoroRunning;
+    pub(crate) async fn start_session(&self, mut pomodoro: Session) {
+        pomodoro.start();
         let cache_key = CacheKey::new(pomodoro.message.chat.id, pomodoro.message.id);
 
+        // The reminder must not expire before the session is back in the entries
+        let mut entries = self.entries.lock().await;
         let delay_key = self
             .expirations
             .lock()
-            .unwrap()
+            .await
             .insert(cache_key.clone(), pomodoro.duration);
+        self.schedule_reminder(&cache_key, &mut pomodoro).await;
+        self.publish_state(&cache_key, &pomodoro);
 
-        self.entries
-            .lock()
-            .unwrap()
-            .insert(cache_key, (pomodoro, delay_key));
-    }
-
-    /// Attempt to add a user to the latest registered chat
-    pub(crate) fn join_latest_session(
-        &self,
-        chat: &types::Chat,
-        user: &types::User,
-    ) -> Result<types::Message, String> {
-        match self.newest_session_in_chat(chat) {
-            Some(cache_key) => match self.entries.lock() {
-                // we have to go deeper!!
-                Ok(mut entries) => match entries.get_mut(&cache_key) {
-                    Some((session, _key)) => {
-                        if session.participants.insert(user.to_owned()) {
-                            // the dream is collapsing
-                            Ok(session.message.to_owned())
-                        } else {
-                            Err(format!(
-                                "@{} is already a participant",
-                                user.username.as_ref().unwrap_or(&user.first_name)
-                            ))
-                        }
-                    }
-                    None => {
-                        let err = "Session not found in State".to_string();
-                        dbg!(&err);
-                        Err(err)
-                    }
-                },
-                Err(err) => {
-                    dbg!(err.to_string());
-                    Err(err.to_string())
-                }
-            },
-            None => Err("This chat does not have any registered sessions yet.\n\n\
-            Hint: Use /25 to create a new session."
-                .to_string()),
-        }
+        entries.insert(cache_key, (pomodoro, Some(delay_key)));
     }
 
     /// Attempt to remove a user from a Pomodoro based on chat id
+    ///
+    /// Only genuine failures are returned as `Err`.
     pub(crate) async fn leave_latest_session(
         &self,
         bot: &Bot,
         chat: &types::Chat,
         user: &types::User,
-    ) -> Result<String, String> {
-        let mut sessions = self.sessions_in_chat(chat);
-        sessions.sort_by(|elem_a, elem_b| elem_a.message.id.0.cmp(&elem_b.message.id.0));
+    ) -> Result<LeaveOutcome, StateError> {
+        let mut sessions = self.sessions_in_chat(chat).await;
+        sessions.sort_by_key(|elem_a| elem_a.seq);
         sessions.reverse();
         for entry in sessions {
-            if entry.participants.contains(&user) {
-                return match self
-                    .remove_participant(
-                        bot,
-                        &CacheKey::new(entry.chat().id, entry.message.id),
-                        user,
-                    )
-                    .await
-                {
-                    Ok(message) => {
-                        self.update_participants_text(bot, &entry.message).await;
-                        Ok(message)
-                    }
-                    Err(err) => Err(err),
-                };
+            if entry.participants.contains(user) {
+                return self
+                    .leave_session(bot, &CacheKey::from(&entry.message), user)
+                    .await;
             }
         }
 
-        Ok("You are not subscribed to any sessions.".to_string())
+        Ok(LeaveOutcome::NotSubscribed)
     }
 
-    /// Put the Pomodoro back to queue for another 5 minutes
-    pub(crate) fn start_break(&self, mut pomodoro: Session) {
-        pomodoro.convert_to_break();
-        let cache_key = CacheKey::new(pomodoro.message.chat.id, pomodoro.message.id);
-
-        let delay_key = self
-            .expirations
-            .lock()
-            .unwrap()
-            .insert(cache_key.clone(), pomodoro.duration);
-
-        self.entries
-            .lock()
-            .unwrap()
-            .insert(cache_key, (pomodoro, delay_key));
+    /// Attempt to remove a user from a specific session
+    ///
+    /// Only genuine failures are returned as `Err`.
+    pub(crate) async fn leave_session(
+        &self,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        user: &types::User,
+    ) -> Result<LeaveOutcome, StateError> {
+        let message = match self.entries.lock().await.get(cache_key) {
+            Some((session, _key)) if session.participants.iter().any(|p| p.id == user.id) => {
+                session.message.to_owned()
+            }
+            Some(_) => return Ok(LeaveOutcome::NotSubscribed),
+            None => return Ok(LeaveOutcome::NotFound),
+        };
+        let result = self.remove_participant(bot, cache_key, user).await?;
+        // The session is gone if its last participant left
+        if self.session_exists(cache_key).await.is_ok() {
+            self.update_participants_text(bot, &message).await;
+        }
+        Ok(LeaveOutcome::Left(result))
     }
-}
 
-/// Methods for handling participants
-impl State {
-    pub(crate) async fn update_participants_text(&self, bot: &Bot, message: &types::Message) {
-        let cache_key = CacheKey::new(message.chat.id, message.id);
-        let (message, participants) = match self.entries.lock() {
-            Ok(entries) => match entries.get(&cache_key) {
-                Some((pomodoro, _key)) => (
-                    pomodoro.message.to_owned(),
-                    pomodoro.participants.to_owned(),
-                ),
-                None => {
-                    dbg!(format!(
-                        "Message id {} in chat {} not found!",
-                        cache_key.message_id, cache_key.chat_id
-                    ));
-                    return;
+    /// Mute or unmute the mentions of a participant when a session starts or ends
+    ///
+    /// Muted participants stay subscribed and keep being listed in the session message.
+    pub(crate) async fn set_muted(
+        &self,
+        cache_key: &CacheKey,
+        user_id: &user::Id,
+        muted: bool,
+    ) -> Result<String, StateError> {
+        match self.entries.lock().await.get_mut(cache_key) {
+            Some((session, _key)) if session.participants.iter().any(|p| p.id == *user_id) => {
+                if muted {
+                    session.muted.insert(*user_id);
+                    Ok("You won't be mentioned in this session anymore.".to_string())
+                } else {
+                    session.muted.remove(user_id);
+                    Ok("You will be mentioned in this session again.".to_string())
                 }
-            },
-            Err(err) => {
-                dbg!(err.to_string());
-                return;
             }
-        };
+            Some(_) => Err(StateError::NotParticipant(None)),
+            None => Err(StateError::SessionNotFound),
+        }
+    }
 
-        let text = match message.kind.to_owned().text() {
-            Some(text) => text,
-            _ => {
-                dbg!("Message is not a Text");
-                return;
+    /// Return the newest session in a chat that a user takes part in
+    pub(crate) async fn latest_session_of_user(
+        &self,
+        chat: &types::Chat,
+        user_id: &user::Id,
+    ) -> Option<CacheKey> {
+        self.newest_session_in_chat(chat, |session| {
+            session.participants.iter().any(|p| p.id == *user_id)
+        })
+        .await
+    }
+
+    /// Restore the session that was cancelled or left last in a chat
+    ///
+    /// Only the user who removed the session can restore it, within `Config::undo_window`.
+    /// The session gets a new message and keeps the time it had left when it was removed.
+    pub(crate) async fn undo(
+        &self,
+        bot: &Bot,
+        chat: &types::Chat,
+        user_id: &user::Id,
+    ) -> Result<String, StateError> {
+        self.ensure_private_chat_is_free(chat).await?;
+        let tombstone = {
+            let mut tombstones = self.tombstones.lock().await;
+            match tombstones.get(&chat.id) {
+                Some(tombstone)
+                    if tombstone.removed_by == *user_id
+                        && tombstone.removed_at.elapsed() <= self.config.undo_window =>
+                {
+                    tombstones.remove(&chat.id)
+                }
+                _ => None,
             }
         };
+        let Tombstone {
+            mut session,
+            text,
+            removed_at,
+            ..
+        } = tombstone.ok_or(StateError::NothingToUndo)?;
 
-        let mut msg: String = text.value;
-        let mut split: Vec<&str> = msg.split("Subscribers:").collect();
-        if split.len() > 1 {
-            split.pop();
-        }
-        split.push("Subscribers:\n");
-        //let subscribed = pomodoro.string_of_subscribed_usernames(bot).await;
-        let mut subscribed_users = String::new();
-        for user in participants.iter() {
-            subscribed_users = format!(
-                "{} @{} ",
-                subscribed_users,
-                user.username.as_ref().unwrap_or(&user.first_name)
-            );
+        let message = self
+            .dispatcher(bot)
+            .call(chat.id, |bot| bot.send_message(chat.id, &text).call())
+            .await
+            .map_err(|err| StateError::Internal(err.to_string()))?;
+        // Paused sessions keep their remaining time, the others continue where they were
+        if !session.is_paused() {
+            session.start_time += removed_at.elapsed();
         }
-
-        split.push(subscribed_users.trim());
-        msg = split.iter().map(|s| s.to_string()).collect();
-
-        let edit_message = match message.chat.kind {
-            chat::Kind::Group { .. } | chat::Kind::Supergroup { .. } => bot
-                .edit_message_text(message.chat.id, message.id, &msg)
-                .reply_markup(inline::Keyboard::new(JOIN)),
-            _ => bot.edit_message_text(message.chat.id, message.id, &msg),
+        session.message = message.to_owned();
+        let cache_key = CacheKey::from(&message);
+        let mut entries = self.entries.lock().await;
+        let delay_key = if session.is_paused() {
+            None
+        } else {
+            Some(
+                self.expirations
+                    .lock()
+                    .await
+                    .insert_at(cache_key.clone(), session.deadline()),
+            )
         };
-
-        if let Err(err_msg) = edit_message.call().await {
-            dbg!(err_msg.to_string());
+        self.schedule_pre_start(&cache_key, &mut session).await;
+        self.schedule_reminder(&cache_key, &mut session).await;
+        self.publish_state(&cache_key, &session);
+        entries.insert(cache_key, (session, delay_key));
+        drop(entries);
+        if classify(chat) == ChatKindClass::Group {
+            self.update_participants_text(bot, &message).await;
         }
+        Ok("The session has been restored.".to_string())
     }
 
-    /// Add a participant to the session
-    pub(crate) async fn add_participant(
-        &self,
-        bot: &Bot,
-        message: &types::Message,
-        user: types::User,
-    ) -> Result<&'static str, String> {
-        let cache_key = CacheKey::new(message.chat.id, message.id);
-        if let Err(msg) = self.session_exists(&cache_key) {
-            dbg!(&msg);
-            return Ok("Pomodoro not found!");
+    /// Return a human readable description of the remaining time of a session
+    pub(crate) async fn session_status(&self, cache_key: &CacheKey) -> Result<String, StateError> {
+        match self.snapshot(cache_key).await {
+            Some(snapshot) => Ok(snapshot.status()),
+            None => Err(StateError::SessionNotFound),
         }
+    }
 
-        match self.entries.lock() {
-            Ok(mut entries) => {
-                if let Some((pomodoro, _key)) = entries.get_mut(&cache_key) {
-                    if !pomodoro.participants.insert(user) {
-                        return Ok("You are already subscribed!");
-                    }
-                }
-            }
-            Err(err) => {
-                dbg!(&err.to_string());
-                return Err(err.to_string());
-            }
-        }
+    /// Return a read-only copy of a session
+    pub(crate) async fn snapshot(&self, cache_key: &CacheKey) -> Option<SessionSnapshot> {
+        self.entries
+            .lock()
+            .await
+            .get(cache_key)
+            .map(|(session, _key)| SessionSnapshot::from(session))
+    }
 
-        self.update_participants_text(bot, message).await;
-        Ok("Yay!")
+    /// Return read-only copies of all sessions across all chats, in no particular order
+    pub(crate) async fn all_snapshots(&self) -> Vec<SessionSnapshot> {
+        self.entries
+            .lock()
+            .await
+            .values()
+            .map(|(session, _key)| SessionSnapshot::from(session))
+            .collect()
     }
-}
 
-/// Private methods
-impl State {
-    /// Checks whether a pomodoro exists in chat
-    fn session_exists(&self, cache_key: &CacheKey) -> Result<(), String> {
-        match self.entries.lock() {
-            Ok(entries) => {
-                if !entries.contains_key(cache_key) {
-                    let err_msg = format!(
-                        "A Pomodoro in chat {} with id {} does not exist!",
-                        cache_key.chat_id.to_string(),
-                        cache_key.message_id.to_string()
-                    );
-                    return Err(err_msg);
-                }
-            }
-            Err(err) => {
-                dbg!(err.to_string());
-                return Err(err.to_string());
-            }
+    /// Return the newest session in a chat, regardless of its state
+    pub(crate) async fn latest_session(&self, chat: &types::Chat) -> Option<CacheKey> {
+        self.newest_session_in_chat(chat, |_session| true).await
+    }
+
+    /// Return the status of the newest session in a chat
+    pub(crate) async fn latest_session_status(
+        &self,
+        chat: &types::Chat,
+    ) -> Result<String, StateError> {
+        match self.latest_session(chat).await {
+            Some(cache_key) => self.session_status(&cache_key).await,
+            None => Err(StateError::NoSessions),
         }
-        Ok(())
     }
 
-    /// Checks whether the specified user is the owner of the session
-    fn is_owner(&self, cache_key: &CacheKey, user_id: &user::Id) -> Result<(), String> {
-        self.session_exists(cache_key)?;
-
-        match self.entries.lock() {
-            Ok(entries) => {
-                if let Some((pomodoro, _key)) = entries.get(cache_key) {
-                    if pomodoro.creator.id.ne(user_id) {
-                        let err_msg = format!(
-                            "User id {} is not the owner of Pomodoro {} in chat {}",
-                            user_id.to_string(),
-                            cache_key.message_id.to_string(),
-                            cache_key.chat_id.to_string()
-                        );
-                        dbg!(&err_msg);
-                        return Err(err_msg);
-                    }
-                }
-            }
-            Err(err) => {
-                dbg!(err.to_string());
-                return Err(err.to_string());
-            }
+    /// Return a summary of all sessions in a chat, ordered by their creation
+    pub(crate) async fn list_sessions(&self, chat: &types::Chat) -> String {
+        let mut sessions = self.sessions_in_chat(chat).await;
+        if sessions.is_empty() {
+            return "No active sessions".to_string();
         }
-        Ok(())
+        sessions.sort_by_key(|session| session.seq);
+
+        let lines: Vec<String> = sessions
+            .iter()
+            .enumerate()
+            .map(|(index, session)| {
+                let snapshot = SessionSnapshot::from(session);
+                let participants = match snapshot.participants {
+                    1 => "1 participant".to_string(),
+                    count => format!("{} participants", count),
+                };
+                format!(
+                    "{}. {}: {} ({})",
+                    index + 1,
+                    snapshot.creator,
+                    snapshot.status(),
+                    participants
+                )
+            })
+            .collect();
+        format!("Active sessions:\n\n{}", lines.join("\n"))
     }
 
-    /// Return a Vec of Sessions for a given chat
-    fn sessions_in_chat(&self, chat: &chat::Chat) -> Vec<Session> {
-        self.entries
-            .lock()
-            .unwrap()
+    /// Return the sessions a user takes part in across all chats, ordered by their creation
+    pub(crate) async fn sessions_for_user(&self, user_id: &user::Id) -> Vec<CacheKey> {
+        let entries = self.entries.lock().await;
+        let mut sessions: Vec<(&CacheKey, &Session)> = entries
             .iter()
-            .filter_map(|(_cache_key, (session, _key))| {
-                if session.message.chat.id.eq(&chat.id) {
-                    Some(session.to_owned())
-                } else {
-                    None
-                }
+            .filter(|(_cache_key, (session, _key))| {
+                session.participants.iter().any(|user| user.id == *user_id)
             })
+            .map(|(cache_key, (session, _key))| (cache_key, session))
+            .collect();
+        sessions.sort_by_key(|(_cache_key, session)| session.seq);
+        sessions
+            .into_iter()
+            .map(|(cache_key, _session)| cache_key.to_owned())
             .collect()
     }
 
-    /// Return the newest session in a chat that has not been started yet.
-    fn newest_session_in_chat(&self, chat: &chat::Chat) -> Option<CacheKey> {
-        let mut sessions = self.sessions_in_chat(chat);
-        sessions.sort_by(|elem_a, elem_b| elem_a.message.id.0.cmp(&elem_b.message.id.0));
-        sessions = sessions
+    /// Return a summary of all sessions a user takes part in, with their chat and status
+    pub(crate) async fn user_sessions_summary(&self, user_id: &user::Id) -> String {
+        let cache_keys = self.sessions_for_user(user_id).await;
+        let entries = self.entries.lock().await;
+        let lines: Vec<String> = cache_keys
             .iter()
-            .filter_map(|elem| {
-                if elem.is_waiting() {
-                    Some(elem.to_owned())
-                } else {
-                    None
-                }
+            .filter_map(|cache_key| entries.get(cache_key))
+            .enumerate()
+            .map(|(index, (session, _key))| {
+                format!(
+                    "{}. {}: {}",
+                    index + 1,
+                    session.chat_name(),
+                    session.status()
+                )
             })
-            .collect::<Vec<Session>>();
-        match sessions.pop() {
-            Some(session) => Some(CacheKey::new(session.message.chat.id, session.message.id)),
-            _ => None,
+            .collect();
+        if lines.is_empty() {
+            return "You're not in any sessions".to_string();
         }
+        format!("Your sessions:\n\n{}", lines.join("\n"))
     }
 
-    /// Add a Session to the DelayQueue
-    fn add_session_to_queue(&self, pomodoro: Session) {
-        let cache_key = CacheKey::new(pomodoro.message.chat.id, pomodoro.message.id);
-        let delay_key;
-        {
-            match self.expirations.lock() {
-                Ok(mut expirations) => {
-                    delay_key = expirations.insert_at(cache_key.clone(), pomodoro.start_time);
-                }
-                Err(err) => {
-                    dbg!(err.to_string());
-                    return;
-                }
-            }
+    /// Remove another participant from a session and refresh its subscriber list
+    ///
+    /// `target` is the username or first name of a participant. Only the creator of the session
+    /// is permitted to kick participants, and they cannot kick themselves.
+    pub(crate) async fn kick_participant(
+        &self,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        owner_id: &user::Id,
+        target: &str,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("kick participants"));
         }
-        {
-            match self.entries.lock() {
-                Ok(mut entries) => {
-                    entries.insert(cache_key, (pomodoro, delay_key));
-                }
-                Err(err) => {
-                    dbg!(err.to_string());
-                    return;
-                }
-            }
+
+        let target = target.trim().trim_start_matches('@');
+        let (message, user) = match self.entries.lock().await.get(cache_key) {
+            Some((session, _key)) => (
+                session.message.to_owned(),
+                session
+                    .participants
+                    .iter()
+                    .find(|user| user.username.as_deref().unwrap_or(&user.first_name) == target)
+                    .cloned(),
+            ),
+            None => return Err(StateError::SessionNotFound),
+        };
+        let user = user.ok_or_else(|| StateError::NotParticipant(Some(target.to_string())))?;
+        if user.id == *owner_id {
+            return Err(StateError::InvalidInput(
+                "You can't kick yourself. Use /leave instead.".to_string(),
+            ));
+        }
+
+        self.remove_participant(bot, cache_key, &user).await?;
+        // The session is gone if its last participant was kicked
+        if self.session_exists(cache_key).await.is_ok() {
+            self.update_participants_text(bot, &message).await;
         }
+        Ok(format!(
+            "@{} has been removed from the session.",
+            user.username.as_ref().unwrap_or(&user.first_name)
+        ))
     }
 
-    /// Remove a session from the DelayQueue
-    fn remove_session_from_queue(&self, cache_key: &CacheKey) -> Result<(), String> {
-        self.session_exists(&cache_key)?;
+    /// Hand the ownership of a session over to another participant
+    ///
+    /// `new_owner` is the username or first name of a participant.
+    /// Only the creator of the session is permitted to transfer it.
+    pub(crate) async fn transfer_ownership(
+        &self,
+        cache_key: &CacheKey,
+        current_owner_id: &user::Id,
+        new_owner: &str,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, current_owner_id).await.is_err() {
+            return Err(StateError::NotOwner("transfer the session"));
+        }
 
-        return match self.entries.lock() {
-            Ok(mut entries) => {
-                if let Some((_, delay_key)) = entries.remove(&cache_key) {
-                    return match self.expirations.lock() {
-                        Ok(mut expirations) => {
-                            expirations.remove(&delay_key);
-                            Ok(())
-                        }
-                        Err(err) => {
-                            dbg!(err.to_string());
-                            Err(err.to_string())
-                        }
-                    };
-                } else {
-                    let err_msg = "Unexpected error".to_string();
-                    dbg!(&err_msg);
-                    Err(err_msg)
-                }
-            }
-            Err(err) => {
-                dbg!(err.to_string());
-                Err(err.to_string())
-            }
+        let new_owner = new_owner.trim().trim_start_matches('@');
+        let mut entries = self.entries.lock().await;
+        let session = match entries.get_mut(cache_key) {
+            Some((session, _key)) => session,
+            None => return Err(StateError::SessionNotFound),
         };
+        let user = session
+            .participants
+            .iter()
+            .find(|user| user.username.as_deref().unwrap_or(&user.first_name) == new_owner)
+            .cloned();
+        match user {
+            Some(user) if user.id == *current_owner_id => Err(StateError::AlreadyOwner),
+            Some(user) => {
+                let msg = format!(
+                    "@{} is the new owner of the session.",
+                    user.username.as_ref().unwrap_or(&user.first_name)
+                );
+                session.creator = user;
+                // The session belongs to a single person from now on
+                session.admins.clear();
+                Ok(msg)
+            }
+            None => Err(StateError::NotParticipant(Some(new_owner.to_string()))),
+        }
     }
 
-    /// Remove a participant from a session.
-    async fn remove_participant(
+    /// Skip the break of a session
+    ///
+    /// The break is taken out of the DelayQueue and ended right away, which asks the
+    /// participants whether to continue or removes the break message in private chats.
+    /// Only the creator of the session is permitted to skip its break.
+    pub(crate) async fn skip_break(
         &self,
         bot: &Bot,
         cache_key: &CacheKey,
-        user: &types::User,
-    ) -> Result<String, String> {
-        self.session_exists(cache_key)?;
-        let mut session_is_empty = false; // work around awaits within a MutexGuard
-
-        let return_val = match self.entries.lock() {
-            Ok(mut entries) => {
-                if let Some((pomodoro, _key)) = entries.get_mut(cache_key) {
-                    pomodoro.participants.retain(|uid| uid.id.ne(&user.id));
-                    if pomodoro.creator.eq(&user) {
-                        // make someone else the owner
-                        match pomodoro.participants.iter().take(1).next() {
-                            Some(user) => {
-                                pomodoro.creator = user.to_owned();
-                            }
-                            None => {
-                                session_is_empty = true;
-                            }
-                        }
-                    }
-                    Ok(format!(
-                        "@{} left the session.",
-                        user.username.as_ref().unwrap_or(&user.first_name)
-                    ))
-                } else {
-                    let err_msg = format!(
-                        "Failed to delete user {} (@{})!",
-                        &user.id,
-                        user.username.as_ref().unwrap_or(&user.first_name)
-                    );
-                    dbg!(&err_msg);
-                    Err(err_msg)
+        owner_id: &user::Id,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("skip the break"));
+        }
+
+        let entry = {
+            let mut entries = self.entries.lock().await;
+            match entries.get(cache_key) {
+                Some((session, _key))
+                    if session.is_taking_a_break() || session.is_awaiting_break() =>
+                {
+                    self.take_entry(&mut entries, cache_key).await
                 }
-            }
-            Err(err) => {
-                dbg!(err.to_string());
-                Err(err.to_string())
+                Some(_) => return Err(StateError::InvalidState("Only breaks can be skipped.")),
+                None => return Err(StateError::SessionNotFound),
             }
         };
-        if session_is_empty {
-            let (delete_message_result, remove_session_result) = join!(
-                bot.delete_message(cache_key.chat_id, cache_key.message_id)
-                    .call(),
-                async { self.remove_session_from_queue(cache_key) }
-            );
-            if let Err(err) = delete_message_result {
-                dbg!(err.to_string());
+        match entry {
+            Some((session, delay_key)) => {
+                self.remove_expiration(delay_key).await;
+                periodic::end_break(self.dispatcher(bot), self, session).await;
+                Ok("Break skipped.".to_string())
             }
-
-            if let Err(err) = remove_session_result {
-                dbg!(err);
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Turn focus mode of a group session on or off
+    ///
+    /// While a Pomodoro in focus mode is running, other messages in its group are deleted.
+    /// Only the creator of the session is permitted to change it.
+    pub(crate) async fn set_focus_mode(
+        &self,
+        cache_key: &CacheKey,
+        owner_id: &user::Id,
+        enabled: bool,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("change the focus mode"));
+        }
+
+        match self.entries.lock().await.get_mut(cache_key) {
+            Some((session, _key)) if classify(&session.message.chat) == ChatKindClass::Group => {
+                session.focus_mode = enabled;
+                Ok(if enabled {
+                    "Focus mode is on, messages are deleted while the Pomodoro is running."
+                } else {
+                    "Focus mode is off."
+                }
+                .to_string())
+            }
+            Some(_) => Err(StateError::InvalidState("Focus mode only works in groups.")),
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Return whether a Pomodoro in focus mode is running in a chat
+    pub(crate) async fn is_focused(&self, chat_id: chat::Id) -> bool {
+        self.entries
+            .lock()
+            .await
+            .iter()
+            .any(|(cache_key, (session, _key))| {
+                cache_key.chat_id == chat_id && session.focus_mode && session.is_running()
+            })
+    }
+
+    /// Chain `rounds` Pomodoros with a break in between, starting with the given one
+    ///
+    /// Every Pomodoro of the chain is as long as the first one.
+    /// Only the creator of the session is permitted to chain it.
+    pub(crate) async fn start_rounds(
+        &self,
+        cache_key: &CacheKey,
+        owner_id: &user::Id,
+        rounds: u32,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("chain the session"));
+        }
+
+        match self.entries.lock().await.get_mut(cache_key) {
+            Some((session, _key))
+                if session.is_waiting() || session.is_running() || session.is_paused() =>
+            {
+                session.rounds = Some(Rounds {
+                    current: 1,
+                    total: rounds,
+                    length: session.duration,
+                });
+                Ok(format!(
+                    "{} Pomodoros will follow each other with a break in between. \
+                    Use /stopfocus to stop after the current one.",
+                    rounds
+                ))
+            }
+            Some(_) => Err(StateError::InvalidState("Only Pomodoros can be chained.")),
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Stop a chain of Pomodoros after its current round
+    ///
+    /// Only the creator of the session is permitted to stop it.
+    pub(crate) async fn stop_rounds(
+        &self,
+        cache_key: &CacheKey,
+        owner_id: &user::Id,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("stop the chain"));
+        }
+
+        let stopped = match self.entries.lock().await.get_mut(cache_key) {
+            Some((session, _key)) => match session.rounds.take() {
+                Some(rounds) => Ok(format!(
+                    "The chain stops after round {} of {}.",
+                    rounds.current, rounds.total
+                )),
+                None => Err(StateError::InvalidState(
+                    "This session is not part of a chain, see /focus.",
+                )),
+            },
+            None => Err(StateError::SessionNotFound),
+        };
+        if stopped.is_ok() {
+            self.reset_completed_pomodoros(cache_key.chat_id).await;
+        }
+        stopped
+    }
+
+    /// Pause a running Pomodoro
+    ///
+    /// The session is taken out of the DelayQueue until it is resumed.
+    /// Only the creator of the session is permitted to pause it.
+    pub(crate) async fn pause_session(
+        &self,
+        cache_key: &CacheKey,
+        user_id: &user::Id,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, user_id).await.is_err() {
+            return Err(StateError::NotOwner("pause the session"));
+        }
+
+        let mut entries = self.entries.lock().await;
+        match entries.get_mut(cache_key) {
+            Some((session, delay_key)) if session.is_running() => {
+                self.remove_expiration(delay_key.take()).await;
+                self.cancel_nudges(session).await;
+                session.pause();
+                self.publish_state(cache_key, session);
+                Ok(format!("Session paused. {}", session.status()))
+            }
+            Some(_) => Err(StateError::InvalidState(
+                "Only running Pomodoros can be paused.",
+            )),
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Add time to a running Pomodoro
+    ///
+    /// The session is put back into the DelayQueue with its new deadline.
+    /// Only the creator of the session is permitted to extend it.
+    pub(crate) async fn extend_session(
+        &self,
+        cache_key: &CacheKey,
+        owner_id: &user::Id,
+        extra: Duration,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("extend the session"));
+        }
+
+        let tz = self.get_timezone(cache_key.chat_id).await;
+        let mut entries = self.entries.lock().await;
+        match entries.get_mut(cache_key) {
+            Some((session, delay_key)) if session.is_running() => {
+                self.remove_expiration(delay_key.take()).await;
+                session.duration += extra;
+                *delay_key = Some(
+                    self.expirations
+                        .lock()
+                        .await
+                        .insert_at(cache_key.to_owned(), session.deadline()),
+                );
+                self.schedule_reminder(cache_key, session).await;
+                Ok(format!(
+                    "Session extended by {}. Ends at {}",
+                    time::format_minutes(extra),
+                    time::instant_as_hh_mm(session.deadline(), tz)
+                ))
+            }
+            Some(_) => Err(StateError::InvalidState(
+                "Only running Pomodoros can be extended.",
+            )),
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Move the start of a Pomodoro that is waiting to be started to `new_start`
+    ///
+    /// The session is put back into the DelayQueue and its message shows the new start time.
+    /// Only the creator of the session is permitted to reschedule it.
+    pub(crate) async fn reschedule_session(
+        &self,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        owner_id: &user::Id,
+        new_start: Instant,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("reschedule the session"));
+        }
+
+        let lang = self.get_language(cache_key.chat_id).await;
+        let tz = self.get_timezone(cache_key.chat_id).await;
+        let hh_mm = time::instant_as_hh_mm(new_start, tz);
+        let (message, header) = {
+            let mut entries = self.entries.lock().await;
+            let (session, delay_key) = match entries.get_mut(cache_key) {
+                Some((session, delay_key)) if session.is_waiting() => (session, delay_key),
+                Some(_) => {
+                    return Err(StateError::InvalidState(
+                        "Only Pomodoros that have not started yet can be rescheduled.",
+                    ))
+                }
+                None => return Err(StateError::SessionNotFound),
+            };
+            self.remove_expiration(delay_key.take()).await;
+            session.start_time = new_start;
+            *delay_key = Some(
+                self.expirations
+                    .lock()
+                    .await
+                    .insert_at(cache_key.to_owned(), new_start),
+            );
+            self.schedule_pre_start(cache_key, session).await;
+
+            let minutes = session.duration.as_secs() / 60;
+            let header = match classify(&session.message.chat) {
+                ChatKindClass::Group => {
+                    let creator = &session.creator;
+                    messages::tf(
+                        lang,
+                        "pomodoro_created_group_length",
+                        &[
+                            creator.username.as_ref().unwrap_or(&creator.first_name),
+                            &minutes,
+                            &hh_mm,
+                            &tz.name(),
+                        ],
+                    )
+                }
+                _ => messages::tf(
+                    lang,
+                    "pomodoro_scheduled_length",
+                    &[&minutes, &hh_mm, &tz.name()],
+                ),
+            };
+            session.header = Some(header.to_owned());
+            (session.message.to_owned(), header)
+        };
+
+        // Only the messages of groups list their participants
+        if classify(&message.chat) == ChatKindClass::Group {
+            self.update_participants_text(bot, &message).await;
+        } else if let Err(err) = self
+            .dispatcher(bot)
+            .call(message.chat.id, |bot| {
+                bot.edit_message_text(message.chat.id, message.id, &header)
+                    .call()
+            })
+            .await
+        {
+            error!("{}", err);
+        }
+        Ok(format!(
+            "The session will start at {} ({}).",
+            hh_mm,
+            tz.name()
+        ))
+    }
+
+    /// Resume a paused Pomodoro
+    ///
+    /// Only the creator of the session is permitted to resume it.
+    pub(crate) async fn resume_session(
+        &self,
+        cache_key: &CacheKey,
+        user_id: &user::Id,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, user_id).await.is_err() {
+            return Err(StateError::NotOwner("resume the session"));
+        }
+
+        let mut entries = self.entries.lock().await;
+        match entries.get_mut(cache_key) {
+            Some((session, delay_key)) if session.is_paused() => {
+                session.resume();
+                *delay_key = Some(
+                    self.expirations
+                        .lock()
+                        .await
+                        .insert_at(cache_key.to_owned(), session.deadline()),
+                );
+                self.schedule_reminder(cache_key, session).await;
+                self.publish_state(cache_key, session);
+                Ok(format!("Session resumed. {}", session.status()))
+            }
+            Some(_) => Err(StateError::InvalidState("This session is not paused.")),
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Change how long before the end of a Pomodoro its participants get reminded
+    ///
+    /// Passing `None` disables the reminder. Only the creator of the session is permitted to
+    /// change it.
+    pub(crate) async fn set_reminder(
+        &self,
+        cache_key: &CacheKey,
+        user_id: &user::Id,
+        offset: Option<Duration>,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, user_id).await.is_err() {
+            return Err(StateError::NotOwner("change the reminder"));
+        }
+
+        let mut entries = self.entries.lock().await;
+        match entries.get_mut(cache_key) {
+            Some((session, _key)) if session.is_waiting() || session.is_running() => {
+                session.reminder = offset;
+                self.schedule_reminder(cache_key, session).await;
+                Ok(match offset {
+                    Some(offset) => format!(
+                        "You will be reminded {} before the Pomodoro ends.",
+                        time::format_minutes(offset)
+                    ),
+                    None => "The reminder has been disabled.".to_string(),
+                })
+            }
+            Some(_) => Err(StateError::InvalidState(
+                "Reminders can only be set for Pomodoros.",
+            )),
+            None => Err(StateError::SessionNotFound),
+        }
+    }
+
+    /// Remove all sessions and tell their participants that the session was interrupted.
+    ///
+    /// Used when the bot shuts down.
+    pub(crate) async fn shutdown_notify(&self, bot: &Bot) {
+        let sessions: Vec<Session> = self
+            .entries
+            .lock()
+            .await
+            .drain()
+            .map(|(_cache_key, (session, _key))| session)
+            .collect();
+        self.expirations.lock().await.clear();
+        self.reminders.lock().await.clear();
+
+        let texts: Vec<String> = sessions
+            .iter()
+            .map(|session| {
+                session.with_mentions("Bot is restarting, your session was interrupted.")
+            })
+            .collect();
+        let dispatcher = self.dispatcher(bot);
+        let notifications = sessions.iter().zip(texts.iter()).map(|(session, text)| {
+            let chat_id = session.message.chat.id;
+            dispatcher.call(chat_id, move |bot| {
+                bot.send_message(chat_id, Text::with_html(text)).call()
+            })
+        });
+        send_all(notifications).await;
+    }
+
+    /// Return how long the break after a Pomodoro in a chat lasts
+    ///
+    /// Short breaks follow the defaults of the chat, see /defaults.
+    pub(crate) async fn break_duration(&self, chat_id: chat::Id, long_break: bool) -> Duration {
+        if long_break {
+            return self.config.long_break_duration;
+        }
+        match self.get_defaults(chat_id).await {
+            Some((_pomodoro, break_duration)) => break_duration,
+            None => self.config.break_duration,
+        }
+    }
+
+    /// Put the Pomodoro back to queue for a break of `break_duration`, which is a long break if
+    /// `long_break` is set.
+    ///
+    /// See `break_duration` for the length of the break after a Pomodoro.
+    pub(crate) async fn start_break(
+        &self,
+        mut pomodoro: Session,
+        long_break: bool,
+        break_duration: Duration,
+    ) {
+        if long_break {
+            pomodoro.convert_to_long_break(break_duration);
+        } else {
+            pomodoro.convert_to_break(break_duration);
+        }
+        let cache_key = CacheKey::new(pomodoro.message.chat.id, pomodoro.message.id);
+
+        let delay_key = self
+            .expirations
+            .lock()
+            .await
+            .insert(cache_key.clone(), pomodoro.duration);
+        self.publish_state(&cache_key, &pomodoro);
+
+        self.entries
+            .lock()
+            .await
+            .insert(cache_key, (pomodoro, Some(delay_key)));
+    }
+
+    /// Start the next Pomodoro of a chain once the break of `previous` is over
+    ///
+    /// The new Pomodoro starts right away and keeps the participants and settings of the
+    /// previous one.
+    async fn start_next_round(
+        &self,
+        dispatcher: Dispatcher<'_>,
+        previous: &Session,
+        rounds: Rounds,
+    ) {
+        let chat_id = previous.chat().id;
+        let message = match previous
+            .notify_participants_on_next_round(dispatcher, &rounds)
+            .await
+        {
+            Ok(message) => message,
+            Err(err) => {
+                error!(
+                    "Failed to announce round {} of {} in chat {}: {}",
+                    rounds.current + 1,
+                    rounds.total,
+                    chat_id,
+                    err
+                );
+                return;
+            }
+        };
+        let current = rounds.current + 1;
+        let header = format!(
+            "Round {} of {}: {} minute Pomodoro",
+            current,
+            rounds.total,
+            rounds.length.as_secs() / 60
+        );
+        if let Err(err) = self
+            .new_pomodoro(
+                message.to_owned(),
+                previous.creator.to_owned(),
+                Some(Instant::now()),
+                Some(rounds.length),
+                Some(markup::with_label(&header, previous.label.as_deref())),
+                previous.label.to_owned(),
+            )
+            .await
+        {
+            error!(
+                "Failed to start round {} in chat {}: {}",
+                current, chat_id, err
+            );
+            return;
+        }
+        let pinned = classify(&message.chat) == ChatKindClass::Group
+            && self.pin_sessions_enabled(chat_id).await
+            && pin_message(dispatcher, &message).await;
+        if let Some((session, _key)) = self.entries.lock().await.get_mut(&CacheKey::from(&message))
+        {
+            session.participants = previous.participants.to_owned();
+            session.muted = previous.muted.to_owned();
+            session.admins = previous.admins.to_owned();
+            session.focus_mode = previous.focus_mode;
+            session.rounds = Some(Rounds { current, ..rounds });
+            if pinned {
+                session.pinned = Some(message.id);
+            }
+        }
+    }
+}
+
+/// Methods for counting completed Pomodoros
+impl State {
+    /// Count a completed Pomodoro in a chat.
+    ///
+    /// Returns true if the chat is due for a long break.
+    pub(crate) async fn complete_pomodoro(&self, chat_id: chat::Id) -> bool {
+        let mut completed_pomodoros = self.completed_pomodoros.lock().await;
+        let count = completed_pomodoros.entry(chat_id).or_insert(0);
+        *count += 1;
+        self.config.long_break_interval > 0 && *count >= self.config.long_break_interval
+    }
+
+    /// Start counting completed Pomodoros from zero again
+    ///
+    /// This happens after a long break and whenever a session ends early or its chain is done.
+    pub(crate) async fn reset_completed_pomodoros(&self, chat_id: chat::Id) {
+        self.completed_pomodoros.lock().await.remove(&chat_id);
+    }
+}
+
+/// Methods for user statistics
+impl State {
+    /// Count a Pomodoro that reached its end for every participant of the session
+    ///
+    /// It also counts towards the next daily summary of the chat.
+    pub(crate) async fn record_completed_pomodoro(&self, pomodoro: &Session) {
+        let mut user_stats = self.user_stats.lock().await;
+        for user in pomodoro.participants.iter() {
+            user_stats
+                .entry(user.id)
+                .or_default()
+                .add_pomodoro(pomodoro.duration);
+        }
+        self.daily_stats
+            .lock()
+            .await
+            .entry(pomodoro.message.chat.id)
+            .or_default()
+            .add_pomodoro(&pomodoro.participants);
+
+        let now = Utc::now();
+        let mut completions = self.completions.lock().await;
+        // Older completions never count towards the leaderboard again
+        while completions.front().is_some_and(|completion| {
+            now.signed_duration_since(completion.completed_at)
+                .to_std()
+                .is_ok_and(|age| age > LEADERBOARD_WINDOW)
+        }) {
+            completions.pop_front();
+        }
+        for user in pomodoro
+            .participants
+            .iter()
+            .filter(|user| !anonymous::is_anonymous_admin(user))
+        {
+            completions.push_back(Completion {
+                chat_id: pomodoro.message.chat.id,
+                user: user.to_owned(),
+                duration: pomodoro.duration,
+                completed_at: now,
+            });
+        }
+    }
+
+    /// Return the users of a chat ranked by the Pomodoros they completed since `since`
+    ///
+    /// Only Pomodoros of the last `LEADERBOARD_WINDOW` are remembered.
+    pub(crate) async fn leaderboard(&self, chat_id: chat::Id, since: DateTime<Utc>) -> Vec<Rank> {
+        leaderboard::rank(&*self.completions.lock().await, chat_id, since)
+    }
+
+    /// Return the statistics of every chat since the last daily summary and reset them
+    pub(crate) async fn take_daily_stats(&self) -> HashMap<chat::Id, DailyStats> {
+        std::mem::take(&mut *self.daily_stats.lock().await)
+    }
+
+    /// Remember a Pomodoro that reached its end for /history
+    ///
+    /// The oldest records are dropped once `Config::history_size` is reached.
+    pub(crate) async fn record_history(&self, pomodoro: &Session) {
+        let history_size = self.config.history_size;
+        if history_size == 0 {
+            return;
+        }
+        let mut history = self.history.lock().await;
+        while history.len() >= history_size {
+            history.pop_front();
+        }
+        history.push_back(SessionRecord {
+            chat_id: pomodoro.message.chat.id,
+            creator: pomodoro.creator.to_owned(),
+            participants: pomodoro.participants.to_owned(),
+            duration: pomodoro.duration,
+            completed_at: Utc::now(),
+        });
+    }
+
+    /// Return a summary of the last `count` completed Pomodoros of a chat, newest first
+    pub(crate) async fn history(&self, chat_id: chat::Id, count: usize) -> String {
+        let tz = self.get_timezone(chat_id).await;
+        let lines: Vec<String> = self
+            .history
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .filter(|record| record.chat_id == chat_id)
+            .take(count)
+            .map(|record| record.describe(tz))
+            .collect();
+        if lines.is_empty() {
+            return "No Pomodoros have been completed in this chat yet.".to_string();
+        }
+        format!("Recently completed Pomodoros:\n\n{}", lines.join("\n"))
+    }
+
+    /// Return the statistics of a user, if they completed any Pomodoro yet
+    pub(crate) async fn user_stats(&self, user_id: &user::Id) -> Option<UserStats> {
+        self.user_stats.lock().await.get(user_id).cloned()
+    }
+}
+
+/// Methods for recurring sessions
+impl State {
+    /// Create a Pomodoro in `chat` every `interval`, starting now
+    ///
+    /// The interval has to be at least as long as the Pomodoro, so the sessions do not overlap.
+    pub(crate) async fn add_recurrence(
+        &self,
+        chat: types::Chat,
+        creator: types::User,
+        interval: Duration,
+        duration: Option<Duration>,
+    ) -> Result<String, StateError> {
+        let pomodoro = match duration {
+            Some(duration) => duration,
+            None => match self.get_defaults(chat.id).await {
+                Some((pomodoro, _break)) => pomodoro,
+                None => Duration::from_secs(60 * 25),
+            },
+        };
+        if interval < pomodoro {
+            return Err(StateError::InvalidInput(format!(
+                "The interval has to be at least {} long.",
+                time::format_minutes(pomodoro)
+            )));
+        }
+        let recurrence = Recurrence {
+            chat,
+            creator,
+            interval,
+            duration,
+            next_run: Instant::now(),
+        };
+        self.recurrences
+            .lock()
+            .await
+            .entry(recurrence.chat.id)
+            .or_default()
+            .push(recurrence);
+        Ok(format!(
+            "A new Pomodoro will be created every {}.\n\n\
+            Hint: Use /stopevery to stop.",
+            time::format_minutes(interval)
+        ))
+    }
+
+    /// Stop all recurring Pomodoros of a chat and return how many there were
+    pub(crate) async fn stop_recurrences(&self, chat_id: chat::Id) -> usize {
+        self.recurrences
+            .lock()
+            .await
+            .remove(&chat_id)
+            .map_or(0, |recurrences| recurrences.len())
+    }
+
+    /// Return the recurrences whose next session is due and schedule their following one
+    pub(self) async fn due_recurrences(&self) -> Vec<Recurrence> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for recurrence in self.recurrences.lock().await.values_mut().flatten() {
+            if recurrence.next_run <= now {
+                due.push(recurrence.to_owned());
+                recurrence.advance();
+            }
+        }
+        due
+    }
+}
+
+/// Methods for handling chat settings
+impl State {
+    /// Set the timezone used to display times in a chat
+    pub(crate) async fn set_timezone(&self, chat_id: chat::Id, tz: Tz) {
+        self.timezones.lock().await.insert(chat_id, tz);
+        self.apply_quiet_hours(chat_id).await;
+    }
+
+    /// Set the language used for messages in a chat
+    pub(crate) async fn set_language(&self, chat_id: chat::Id, lang: Lang) {
+        self.languages.lock().await.insert(chat_id, lang);
+    }
+
+    /// Return the language of a chat, falling back to English if none has been set
+    pub(crate) async fn get_language(&self, chat_id: chat::Id) -> Lang {
+        self.languages
+            .lock()
+            .await
+            .get(&chat_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Set the default Pomodoro and break durations of a chat
+    ///
+    /// Both durations have to be within `duration_bounds`.
+    pub(crate) async fn set_defaults(
+        &self,
+        chat_id: chat::Id,
+        pomodoro: Duration,
+        break_duration: Duration,
+    ) -> Result<(), StateError> {
+        let bounds = self.duration_bounds();
+        if !bounds.contains(&pomodoro) || !bounds.contains(&break_duration) {
+            return Err(StateError::InvalidInput(format!(
+                "Durations have to be between {} and {}.",
+                time::format_minutes(*bounds.start()),
+                time::format_minutes(*bounds.end())
+            )));
+        }
+        self.defaults
+            .lock()
+            .await
+            .insert(chat_id, (pomodoro, break_duration));
+        Ok(())
+    }
+
+    /// Return the default Pomodoro and break durations of a chat, if any have been set
+    pub(crate) async fn get_defaults(&self, chat_id: chat::Id) -> Option<(Duration, Duration)> {
+        self.defaults.lock().await.get(&chat_id).copied()
+    }
+
+    /// Enable or disable notifying creators when someone joins their session in a chat
+    pub(crate) async fn set_join_notifications(&self, chat_id: chat::Id, enabled: bool) {
+        let mut join_disabled_chats = self.join_disabled_chats.lock().await;
+        if enabled {
+            join_disabled_chats.remove(&chat_id);
+        } else {
+            join_disabled_chats.insert(chat_id);
+        }
+    }
+
+    /// Return whether creators are notified when someone joins their session in a chat
+    pub(crate) async fn join_notifications_enabled(&self, chat_id: chat::Id) -> bool {
+        !self.join_disabled_chats.lock().await.contains(&chat_id)
+    }
+
+    /// Enable or disable asking the participants to continue after a break in a group
+    ///
+    /// Private chats are always asked.
+    pub(crate) async fn set_continue_prompt(&self, chat_id: chat::Id, enabled: bool) {
+        let mut continue_prompt_chats = self.continue_prompt_chats.lock().await;
+        if enabled {
+            continue_prompt_chats.insert(chat_id);
+        } else {
+            continue_prompt_chats.remove(&chat_id);
+        }
+    }
+
+    /// Return whether the participants are asked to continue after a break in a group
+    pub(crate) async fn continue_prompt_enabled(&self, chat_id: chat::Id) -> bool {
+        self.continue_prompt_chats.lock().await.contains(&chat_id)
+    }
+
+    /// Remember the participants and label of a session whose break is over, so they can
+    /// continue with the next Pomodoro
+    async fn remember_continuation(&self, session: &Session) {
+        let mut continuations = self.continuations.lock().await;
+        continuations.retain(|_chat_id, continuation| !continuation.is_expired());
+        continuations.insert(session.chat().id, Continuation::new(session));
+    }
+
+    /// Take what the last session of a chat carries into the next Pomodoro, unless it ended
+    /// too long ago
+    pub(crate) async fn take_continuation(&self, chat_id: chat::Id) -> Option<Continuation> {
+        self.continuations
+            .lock()
+            .await
+            .remove(&chat_id)
+            .filter(|continuation| !continuation.is_expired())
+    }
+
+    /// Add the participants of a previous session to a new one and refresh its message
+    ///
+    /// Users who take part in the new session already are skipped.
+    pub(crate) async fn add_continuing_participants(
+        &self,
+        bot: &Bot,
+        message: &types::Message,
+        participants: Vec<types::User>,
+    ) {
+        if let Some((session, _key)) = self.entries.lock().await.get_mut(&CacheKey::from(message)) {
+            for user in participants {
+                if !session.participants.iter().any(|p| p.id == user.id) {
+                    session.participants.push(user);
+                }
+            }
+        }
+        self.update_participants_text(bot, message).await;
+    }
+
+    /// Enable or disable pinning the messages of sessions in a group
+    pub(crate) async fn set_pin_sessions(&self, chat_id: chat::Id, enabled: bool) {
+        let mut pin_chats = self.pin_chats.lock().await;
+        if enabled {
+            pin_chats.insert(chat_id);
+        } else {
+            pin_chats.remove(&chat_id);
+        }
+    }
+
+    /// Return whether the messages of sessions are pinned in a group
+    pub(crate) async fn pin_sessions_enabled(&self, chat_id: chat::Id) -> bool {
+        self.pin_chats.lock().await.contains(&chat_id)
+    }
+
+    /// Set the quiet hours of a chat, `None` turns them off
+    pub(crate) async fn set_quiet_hours(&self, chat_id: chat::Id, quiet_hours: Option<QuietHours>) {
+        match quiet_hours {
+            Some(quiet_hours) => self.quiet_hours.lock().await.insert(chat_id, quiet_hours),
+            None => self.quiet_hours.lock().await.remove(&chat_id),
+        };
+        self.apply_quiet_hours(chat_id).await;
+    }
+
+    /// Return the quiet hours of a chat, if it has any
+    pub(crate) async fn get_quiet_hours(&self, chat_id: chat::Id) -> Option<QuietHours> {
+        self.quiet_hours.lock().await.get(&chat_id).copied()
+    }
+
+    /// Return the quiet hours of a chat along with its timezone, as sessions keep them
+    async fn quiet_hours_with_timezone(&self, chat_id: chat::Id) -> Option<(QuietHours, Tz)> {
+        let quiet_hours = self.get_quiet_hours(chat_id).await?;
+        Some((quiet_hours, self.get_timezone(chat_id).await))
+    }
+
+    /// Update the copy of the quiet hours in every session of a chat
+    async fn apply_quiet_hours(&self, chat_id: chat::Id) {
+        let quiet_hours = self.quiet_hours_with_timezone(chat_id).await;
+        for (cache_key, (session, _key)) in self.entries.lock().await.iter_mut() {
+            if cache_key.chat_id == chat_id {
+                session.quiet_hours = quiet_hours;
+            }
+        }
+    }
+
+    /// Let the current administrators of the group manage a session of an anonymous administrator
+    ///
+    /// The placeholder user of anonymous administrators owns their sessions, so without this
+    /// only other anonymous administrators could manage them.
+    pub(crate) async fn share_with_admins(&self, bot: &Bot, message: &types::Message) {
+        let chat_id = message.chat.id;
+        let admins = match self
+            .dispatcher(bot)
+            .call(chat_id, |bot| bot.get_chat_administrators(chat_id).call())
+            .await
+        {
+            Ok(members) => members.into_iter().map(|member| member.user.id).collect(),
+            Err(err) => {
+                error!(
+                    "Failed to fetch the administrators of chat {}: {}",
+                    chat_id, err
+                );
+                return;
+            }
+        };
+        if let Some((session, _key)) = self.entries.lock().await.get_mut(&CacheKey::from(message)) {
+            session.admins = admins;
+        }
+    }
+
+    /// Pin the message of a session, if pinning is enabled in its chat
+    ///
+    /// Failures, e.g. because the bot is not allowed to pin messages, are only logged.
+    pub(crate) async fn pin_session(&self, bot: &Bot, message: &types::Message) {
+        if !self.pin_sessions_enabled(message.chat.id).await
+            || !pin_message(self.dispatcher(bot), message).await
+        {
+            return;
+        }
+        if let Some((session, _key)) = self.entries.lock().await.get_mut(&CacheKey::from(message)) {
+            session.pinned = Some(message.id);
+        }
+    }
+
+    /// Return the settings in effect for a chat, falling back to the global configuration
+    pub(crate) async fn chat_settings(&self, chat_id: chat::Id) -> ChatSettings {
+        let (pomodoro_duration, break_duration) = self
+            .get_defaults(chat_id)
+            .await
+            .unwrap_or((Duration::from_secs(60 * 25), self.config.break_duration));
+        ChatSettings {
+            timezone: self.get_timezone(chat_id).await,
+            language: self.get_language(chat_id).await,
+            pomodoro_duration,
+            break_duration,
+            long_break_duration: self.config.long_break_duration,
+            long_break_interval: self.config.long_break_interval,
+            join_notifications: self.join_notifications_enabled(chat_id).await,
+            ask_to_continue: self.continue_prompt_enabled(chat_id).await,
+            pin_sessions: self.pin_sessions_enabled(chat_id).await,
+            quiet_hours: self.get_quiet_hours(chat_id).await,
+            recurrences: self
+                .recurrences
+                .lock()
+                .await
+                .get(&chat_id)
+                .map_or(0, Vec::len),
+        }
+    }
+
+    /// Return the number of active sessions, by state, their participants and chats
+    pub(crate) async fn metrics(&self) -> Metrics {
+        let mut metrics = Metrics::default();
+        let mut chats = HashSet::new();
+        for snapshot in self.all_snapshots().await {
+            metrics.count(&snapshot);
+            chats.insert(snapshot.cache_key.chat_id);
+        }
+        metrics.chats = chats.len();
+        metrics
+    }
+
+    /// Return the timezone of a chat, falling back to UTC if none has been set
+    pub(crate) async fn get_timezone(&self, chat_id: chat::Id) -> Tz {
+        self.timezones
+            .lock()
+            .await
+            .get(&chat_id)
+            .copied()
+            .unwrap_or(Tz::UTC)
+    }
+}
+
+/// Methods for handling participants
+impl State {
+    /// Rewrite the subscriber list at the end of the session's message.
+    ///
+    /// The message is rendered from the header of the session, or for sessions restored from older
+    /// files from the part of the message above its list, see `legacy_header`. Running Pomodoros
+    /// show their progress above the list. Groups and channels list their subscribers.
+    pub(crate) async fn update_participants_text(&self, bot: &Bot, message: &types::Message) {
+        let cache_key = CacheKey::new(message.chat.id, message.id);
+        let (message, header, subscribed_users, progress, countdown, ready) =
+            match self.entries.lock().await.get(&cache_key) {
+                Some((pomodoro, _key)) => (
+                    pomodoro.message.to_owned(),
+                    pomodoro.header.to_owned(),
+                    pomodoro.participant_names(),
+                    pomodoro.progress(),
+                    pomodoro.countdown(),
+                    pomodoro.ready_count(),
+                ),
+                None => {
+                    warn!(
+                        "Message id {} in chat {} not found!",
+                        cache_key.message_id, cache_key.chat_id
+                    );
+                    return;
+                }
+            };
+
+        let body = match header {
+            Some(header) => header,
+            // Sessions restored from older session files only know their message
+            None => match message.kind.to_owned().text() {
+                Some(text) => legacy_header(&text.value).to_string(),
+                _ => {
+                    warn!("Message is not a Text");
+                    return;
+                }
+            },
+        };
+        let kind = classify(&message.chat);
+        let is_group = kind == ChatKindClass::Group;
+        let mut msg = body.trim_end().to_string();
+        if let Some(progress) = progress {
+            msg = format!("{}\n{}", msg, markup::progress_bar(progress));
+        }
+        if let Some(countdown) = countdown {
+            msg = format!(
+                "{}\n⏳ {} remaining",
+                msg,
+                time::format_countdown(countdown)
+            );
+        }
+        if let (true, Some((ready, participants))) = (is_group, ready) {
+            if ready > 0 {
+                msg = format!("{}\n{}/{} ready", msg, ready, participants);
+            }
+        }
+        if is_group || kind == ChatKindClass::Channel {
+            let lang = self.get_language(message.chat.id).await;
+            msg = format!("{}\n\n{}\n", msg, messages::t(lang, "subscribers"));
+            // Large groups would otherwise exceed the length of a message
+            let budget = markup::MAX_MESSAGE_LENGTH.saturating_sub(markup::text_length(&msg));
+            msg.push_str(&markup::join_within(
+                &subscribed_users,
+                budget,
+                markup::text_length,
+            ));
+        }
+
+        // The buttons carry the key of the session, so they do not depend on their origin
+        let join_data = cache_key.to_callback_data("join");
+        let leave_data = cache_key.to_callback_data("leave");
+        let ready_data = cache_key.to_callback_data("ready");
+        let mut join_buttons = vec![
+            inline::Button::new("Join", inline::ButtonKind::CallbackData(&join_data)),
+            inline::Button::new("Leave", inline::ButtonKind::CallbackData(&leave_data)),
+        ];
+        // Only Pomodoros that have not started yet can be started early
+        if ready.is_some() {
+            join_buttons.push(inline::Button::new(
+                "Ready",
+                inline::ButtonKind::CallbackData(&ready_data),
+            ));
+        }
+        let join_row: &[inline::Button] = &join_buttons;
+        let join_markup: inline::Markup = &[join_row];
+        let edit_message = self.dispatcher(bot).call(message.chat.id, |bot| {
+            let edit = bot.edit_message_text(message.chat.id, message.id, &msg);
+            if is_group {
+                edit.reply_markup(inline::Keyboard::new(join_markup)).call()
+            } else {
+                edit.call()
+            }
+        });
+
+        match edit_message.await {
+            Ok(_) => {}
+            // The message was deleted, e.g. by an admin, so nobody could see the session anymore
+            Err(MethodCall::RequestError { description, .. })
+                if description.contains("message to edit not found") =>
+            {
+                warn!(
+                    "Message {} in chat {} was deleted, removing its session",
+                    cache_key.message_id, cache_key.chat_id
+                );
+                if let Err(err) = self.remove_session_from_queue(&cache_key).await {
+                    error!("{}", err);
+                }
+            }
+            // The rendered text did not change, so there is nothing to update
+            Err(MethodCall::RequestError { description, .. })
+                if description.contains("message is not modified") =>
+            {
+                debug!(
+                    "Message {} in chat {} is already up to date",
+                    cache_key.message_id, cache_key.chat_id
+                );
+            }
+            Err(err_msg) => error!("{}", err_msg),
+        }
+    }
+
+    /// Return the messages of all running Pomodoros, whose progress is shown
+    pub(crate) async fn running_session_messages(&self) -> Vec<types::Message> {
+        self.entries
+            .lock()
+            .await
+            .values()
+            .filter(|(session, _key)| session.is_running())
+            .map(|(session, _key)| session.message.to_owned())
+            .collect()
+    }
+
+    /// Return whether a user may create a Pomodoro with a button, which they may not if they
+    /// just did within `CREATION_DEBOUNCE` in the same chat
+    ///
+    /// A double tap on a button would otherwise create two sessions. Allowed creations are
+    /// remembered.
+    pub(crate) async fn debounce_creation(&self, chat_id: chat::Id, user_id: user::Id) -> bool {
+        let now = Instant::now();
+        let mut last_creations = self.last_creations.lock().await;
+        last_creations.retain(|_key, created| now.duration_since(*created) < CREATION_DEBOUNCE);
+        match last_creations.entry((chat_id, user_id)) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                true
+            }
+        }
+    }
+
+    /// Return participants of group sessions whose membership in the group is due to be checked
+    ///
+    /// Every participant is returned at most once per `MEMBERSHIP_CHECK_INTERVAL` and at most
+    /// `MAX_MEMBERSHIP_CHECKS` at once, so checking them stays within the rate limits.
+    /// Anonymous admins are skipped, they can't be looked up.
+    pub(crate) async fn due_membership_checks(&self) -> Vec<(CacheKey, types::User)> {
+        let now = Instant::now();
+        let entries = self.entries.lock().await;
+        let mut checks = self.membership_checks.lock().await;
+        checks.retain(|_participant, checked| {
+            now.duration_since(*checked) < MEMBERSHIP_CHECK_INTERVAL
+        });
+        let mut due = Vec::new();
+        for (cache_key, (session, _key)) in entries.iter() {
+            if classify(&session.message.chat) != ChatKindClass::Group {
+                continue;
+            }
+            for user in &session.participants {
+                if due.len() == MAX_MEMBERSHIP_CHECKS {
+                    return due;
+                }
+                if anonymous::is_anonymous_admin(user) {
+                    continue;
+                }
+                if let Entry::Vacant(entry) = checks.entry((cache_key.chat_id, user.id)) {
+                    entry.insert(now);
+                    due.push((cache_key.clone(), user.to_owned()));
+                }
+            }
+        }
+        due
+    }
+
+    /// Refresh the subscriber list of a session after a short delay, in the background.
+    ///
+    /// Refreshes that are requested while one is already pending are skipped, because the pending
+    /// refresh picks up their changes as well. This keeps the number of edits low when many users
+    /// join at once.
+    async fn update_participants_text_debounced(
+        self: &Arc<Self>,
+        bot: &Bot,
+        message: &types::Message,
+    ) {
+        let cache_key = CacheKey::from(message);
+        if !self.pending_edits.lock().await.insert(cache_key.clone()) {
+            return;
+        }
+        let (state, bot, message) = (Arc::clone(self), bot.clone(), message.to_owned());
+        tokio::spawn(async move {
+            delay_for(Duration::from_millis(500)).await;
+            state.pending_edits.lock().await.remove(&cache_key);
+            state.update_participants_text(&bot, &message).await;
+        });
+    }
+
+    /// Mark a participant of a waiting group Pomodoro as ready
+    ///
+    /// Once every participant is ready, the Pomodoro starts right away instead of at its start
+    /// time. Like starting early with /start, this needs another participant besides the creator
+    /// if `Config::early_start_requires_participants` is set.
+    pub(crate) async fn mark_ready(
+        self: &Arc<Self>,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        user_id: &user::Id,
+    ) -> Result<&'static str, StateError> {
+        self.session_exists(cache_key).await?;
+
+        let (message, all_ready) = {
+            let mut entries = self.entries.lock().await;
+            let (session, delay_key) = match entries.get_mut(cache_key) {
+                Some((session, delay_key))
+                    if session.is_waiting()
+                        && classify(&session.message.chat) == ChatKindClass::Group =>
+                {
+                    (session, delay_key)
+                }
+                Some(_) => {
+                    return Err(StateError::InvalidState(
+                        "Only group Pomodoros that have not started yet wait for everyone.",
+                    ))
+                }
+                None => return Err(StateError::SessionNotFound),
+            };
+            if !session.participants.iter().any(|user| user.id == *user_id) {
+                return Err(StateError::NotParticipant(None));
+            }
+            if !session.ready.insert(*user_id) {
+                return Ok("You are ready already!");
+            }
+            let all_ready = session.ready_count().is_some_and(|(ready, participants)| {
+                ready == participants
+                    && (participants > 1 || !self.config.early_start_requires_participants)
+            });
+            if all_ready {
+                // The start is handled like any other, by the expiration of the session
+                self.remove_expiration(delay_key.take()).await;
+                self.cancel_nudges(session).await;
+                *delay_key = Some(
+                    self.expirations
+                        .lock()
+                        .await
+                        .insert(cache_key.to_owned(), Duration::from_secs(0)),
+                );
+            }
+            (session.message.to_owned(), all_ready)
+        };
+
+        if all_ready {
+            Ok("Everyone is ready, starting now!")
+        } else {
+            self.update_participants_text_debounced(bot, &message).await;
+            Ok("Ready!")
+        }
+    }
+
+    /// Add a participant to a session and refresh the subscriber list of its message
+    ///
+    /// If `cache_key` is `None`, the newest session of the chat that has not started yet is joined.
+    pub(crate) async fn join_session(
+        self: &Arc<Self>,
+        bot: &Bot,
+        chat: &types::Chat,
+        cache_key: Option<CacheKey>,
+        user: types::User,
+    ) -> Result<&'static str, StateError> {
+        let cache_key = match cache_key {
+            Some(cache_key) => cache_key,
+            None => self
+                .newest_session_in_chat(chat, |session| session.is_waiting())
+                .await
+                .ok_or(StateError::NoSessions)?,
+        };
+        self.session_exists(&cache_key).await?;
+
+        let (message, creator) = match self.entries.lock().await.get_mut(&cache_key) {
+            Some((pomodoro, _key)) => {
+                if !pomodoro.join(user.to_owned()) {
+                    return Err(StateError::AlreadyParticipant(None));
+                }
+                (pomodoro.message.to_owned(), pomodoro.creator.to_owned())
+            }
+            None => return Err(StateError::SessionNotFound),
+        };
+
+        self.update_participants_text_debounced(bot, &message).await;
+        if creator.id != user.id && self.join_notifications_enabled(message.chat.id).await {
+            notify_creator_on_join(self.dispatcher(bot), &creator, &user).await;
+        }
+        Ok("Yay!")
+    }
+
+    /// Add another user to a session by their username
+    ///
+    /// Telegram does not resolve usernames for bots, so only users the bot knows from this chat
+    /// can be added: participants of its sessions and its administrators.
+    /// Only the creator of the session is permitted to add users.
+    pub(crate) async fn add_participant_by_username(
+        self: &Arc<Self>,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        owner_id: &user::Id,
+        username: &str,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+        if self.is_owner(cache_key, owner_id).await.is_err() {
+            return Err(StateError::NotOwner("add participants"));
+        }
+
+        let username = username.trim().trim_start_matches('@');
+        let matches = |user: &types::User| {
+            user.username
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(username))
+        };
+        let mut user = self
+            .entries
+            .lock()
+            .await
+            .values()
+            .filter(|(session, _key)| session.message.chat.id == cache_key.chat_id)
+            .flat_map(|(session, _key)| session.participants.iter())
+            .find(|user| matches(user))
+            .cloned();
+        if user.is_none() {
+            let administrators = self.dispatcher(bot).call(cache_key.chat_id, |bot| {
+                bot.get_chat_administrators(cache_key.chat_id).call()
+            });
+            match administrators.await {
+                Ok(members) => {
+                    user = members
+                        .into_iter()
+                        .map(|member| member.user)
+                        .find(|user| matches(user));
+                }
+                Err(err) => error!("{}", err),
+            }
+        }
+        let user = user.ok_or_else(|| StateError::UnknownUser(username.to_string()))?;
+
+        let message = match self.entries.lock().await.get_mut(cache_key) {
+            Some((session, _key)) => {
+                if !session.join(user) {
+                    return Err(StateError::AlreadyParticipant(Some(username.to_string())));
+                }
+                session.message.to_owned()
+            }
+            None => return Err(StateError::SessionNotFound),
+        };
+        self.update_participants_text_debounced(bot, &message).await;
+        Ok(format!("@{} has been added to the session.", username))
+    }
+}
+
+/// Private methods
+impl State {
+    /// Return a new sequence number, which orders sessions by their creation
+    fn next_seq(&self) -> u64 {
+        self.next_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Checks whether a pomodoro exists in chat
+    async fn session_exists(&self, cache_key: &CacheKey) -> Result<(), StateError> {
+        if !self.entries.lock().await.contains_key(cache_key) {
+            debug!(
+                "A Pomodoro in chat {} with id {} does not exist!",
+                cache_key.chat_id, cache_key.message_id
+            );
+            return Err(StateError::SessionNotFound);
+        }
+        Ok(())
+    }
+
+    /// Checks whether the specified user is the owner of the session
+    async fn is_owner(&self, cache_key: &CacheKey, user_id: &user::Id) -> Result<(), StateError> {
+        self.session_exists(cache_key).await?;
+
+        if let Some((pomodoro, _key)) = self.entries.lock().await.get(cache_key) {
+            if pomodoro.creator.id.ne(user_id) && !pomodoro.admins.contains(user_id) {
+                debug!(
+                    "User id {} is not the owner of Pomodoro {} in chat {}",
+                    user_id, cache_key.message_id, cache_key.chat_id
+                );
+                return Err(StateError::NotOwner("do this"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return a Vec of Sessions for a given chat
+    async fn sessions_in_chat(&self, chat: &chat::Chat) -> Vec<Session> {
+        self.entries
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(_cache_key, (session, _key))| {
+                if session.message.chat.id.eq(&chat.id) {
+                    Some(session.to_owned())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Fail if the chat is a private chat that has a session already
+    ///
+    /// A private chat holds at most one session at a time, a Pomodoro or a break.
+    async fn ensure_private_chat_is_free(&self, chat: &chat::Chat) -> Result<(), StateError> {
+        if classify(chat) == ChatKindClass::Private && !self.sessions_in_chat(chat).await.is_empty()
+        {
+            return Err(StateError::PrivateSessionRunning);
+        }
+        Ok(())
+    }
+
+    /// Remember a removed session, so it can be restored with /undo for a while
+    async fn bury(&self, mut session: Session, removed_by: &types::User) {
+        if self.config.undo_window.is_zero() {
+            return;
+        }
+        // The message of the session is deleted, so it is no longer pinned
+        session.pinned = None;
+        self.tombstones
+            .lock()
+            .await
+            .insert(session.chat().id, Tombstone::new(session, removed_by));
+    }
+
+    /// Return the newest session in a chat that matches `filter`.
+    async fn newest_session_in_chat(
+        &self,
+        chat: &chat::Chat,
+        filter: impl Fn(&Session) -> bool,
+    ) -> Option<CacheKey> {
+        let mut sessions = self.sessions_in_chat(chat).await;
+        sessions.sort_by_key(|elem_a| elem_a.seq);
+        sessions = sessions
+            .iter()
+            .filter_map(|elem| {
+                if filter(elem) {
+                    Some(elem.to_owned())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<Session>>();
+        match sessions.pop() {
+            Some(session) => Some(CacheKey::new(session.message.chat.id, session.message.id)),
+            _ => None,
+        }
+    }
+
+    /// Add a Session to the DelayQueue, along with its heads-up
+    ///
+    /// With `solo_start`, a group Pomodoro starts early if nobody joins it, see
+    /// `schedule_solo_start`.
+    async fn add_session_to_queue(&self, mut pomodoro: Session, solo_start: bool) {
+        let cache_key = CacheKey::new(pomodoro.message.chat.id, pomodoro.message.id);
+        pomodoro.quiet_hours = self.quiet_hours_with_timezone(cache_key.chat_id).await;
+        let mut entries = self.entries.lock().await;
+        let delay_key = self
+            .expirations
+            .lock()
+            .await
+            .insert_at(cache_key.clone(), pomodoro.start_time);
+        self.schedule_pre_start(&cache_key, &mut pomodoro).await;
+        if solo_start {
+            self.schedule_solo_start(&cache_key, &mut pomodoro).await;
+        }
+        entries.insert(cache_key, (pomodoro, Some(delay_key)));
+    }
+
+    /// Put a reminder for a running Pomodoro into the reminder queue, replacing its previous one
+    ///
+    /// Nothing is scheduled if the reminder is disabled or the Pomodoro ends before it would fire.
+    /// Pomodoros that are not running keep their heads-up.
+    async fn schedule_reminder(&self, cache_key: &CacheKey, pomodoro: &mut Session) {
+        if !pomodoro.is_running() {
+            return;
+        }
+        let mut reminders = self.reminders.lock().await;
+        if let Some(key) = pomodoro.reminder_key.take() {
+            reminders.remove(&key);
+        }
+        if let Some(when) = pomodoro.reminder_time() {
+            if when > Instant::now() {
+                let key = reminders.insert_at((cache_key.to_owned(), Nudge::Reminder), when);
+                pomodoro.reminder_key = Some(key);
+            }
+        }
+    }
+
+    /// Put a heads-up for a group Pomodoro waiting to be started into the reminder queue,
+    /// replacing its previous one
+    ///
+    /// Nothing is scheduled if the Pomodoro starts within the next minute.
+    async fn schedule_pre_start(&self, cache_key: &CacheKey, pomodoro: &mut Session) {
+        if !pomodoro.is_waiting() {
+            return;
+        }
+        let mut reminders = self.reminders.lock().await;
+        if let Some(key) = pomodoro.reminder_key.take() {
+            reminders.remove(&key);
+        }
+        if let Some(when) = pomodoro.pre_start_time() {
+            if when > Instant::now() {
+                let key = reminders.insert_at((cache_key.to_owned(), Nudge::Reminder), when);
+                pomodoro.reminder_key = Some(key);
+            }
+        }
+    }
+
+    /// Put a check into the reminder queue that starts a group Pomodoro early if nobody joined
+    ///
+    /// Nothing is scheduled if `Config::solo_start_delay` is disabled or the Pomodoro starts
+    /// before the check would happen.
+    async fn schedule_solo_start(&self, cache_key: &CacheKey, pomodoro: &mut Session) {
+        let delay = self.config.solo_start_delay;
+        let is_group = classify(&pomodoro.message.chat) == ChatKindClass::Group;
+        let when = pomodoro.creation_time + delay;
+        if is_group && !delay.is_zero() && when < pomodoro.start_time {
+            let key = self
+                .reminders
+                .lock()
+                .await
+                .insert_at((cache_key.to_owned(), Nudge::SoloStart), when);
+            pomodoro.solo_start_key = Some(key);
+        }
+    }
+
+    /// Remove the reminder, heads-up and solo start of a session from the reminder queue
+    ///
+    /// Only call it while holding the lock of `entries`, which the poll loop takes before it
+    /// yields a key, so no key is removed twice.
+    async fn cancel_nudges(&self, session: &mut Session) {
+        let mut reminders = self.reminders.lock().await;
+        for key in [session.reminder_key.take(), session.solo_start_key.take()]
+            .iter()
+            .flatten()
+        {
+            reminders.remove(key);
+        }
+    }
+
+    /// Take a session out of the entries, along with its entries in the reminder queue
+    async fn take_entry(
+        &self,
+        entries: &mut Entries,
+        cache_key: &CacheKey,
+    ) -> Option<(Session, Option<delay_queue::Key>)> {
+        let (mut session, delay_key) = entries.remove(cache_key)?;
+        self.cancel_nudges(&mut session).await;
+        Some((session, delay_key))
+    }
+
+    /// Take a group Pomodoro out of the state if it is still alone when its solo start is due
+    ///
+    /// The session is returned so it can be started, see `schedule_solo_start`.
+    pub(self) async fn take_due_solo_start(&self, cache_key: &CacheKey) -> Option<Session> {
+        let mut entries = self.entries.lock().await;
+        let (session, _key) = entries.get(cache_key)?;
+        if !session.is_waiting() || session.participants.len() != 1 {
+            return None;
+        }
+        let (session, delay_key) = self.take_entry(&mut entries, cache_key).await?;
+        drop(entries);
+        self.remove_expiration(delay_key).await;
+        Some(session)
+    }
+
+    /// Return the session whose reminder or heads-up expired
+    ///
+    /// Reminders of sessions that were cancelled, paused, started early or rescheduled meanwhile
+    /// were removed from the queue along with them.
+    pub(self) async fn due_reminder(&self, cache_key: &CacheKey) -> Option<Session> {
+        let entries = self.entries.lock().await;
+        let (session, _key) = entries.get(cache_key)?;
+        if session.is_waiting() || session.is_running() {
+            Some(session.to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Publish the current state of a session
+    fn publish_state(&self, cache_key: &CacheKey, session: &Session) {
+        // Sending only fails if nobody is subscribed, which is fine
+        let _ = self.events.0.send(SessionEvent::StateChanged {
+            chat_id: cache_key.chat_id,
+            message_id: cache_key.message_id,
+            state: session.state.to_owned(),
+        });
+    }
+
+    /// Publish that a session has been removed
+    pub(self) fn publish_ended(&self, cache_key: &CacheKey) {
+        // Sending only fails if nobody is subscribed, which is fine
+        let _ = self.events.0.send(SessionEvent::Ended {
+            chat_id: cache_key.chat_id,
+            message_id: cache_key.message_id,
+        });
+    }
+
+    /// Remove a key from the DelayQueue, if there is one
+    async fn remove_expiration(&self, delay_key: Option<delay_queue::Key>) {
+        if let Some(delay_key) = delay_key {
+            self.expirations.lock().await.remove(&delay_key);
+        }
+    }
+
+    /// Remove a session from the DelayQueue and return it
+    async fn remove_session_from_queue(&self, cache_key: &CacheKey) -> Result<Session, StateError> {
+        self.session_exists(cache_key).await?;
+
+        let entry = {
+            let mut entries = self.entries.lock().await;
+            self.take_entry(&mut entries, cache_key).await
+        };
+        match entry {
+            Some((session, delay_key)) => {
+                self.remove_expiration(delay_key).await;
+                self.publish_ended(cache_key);
+                Ok(session)
+            }
+            None => {
+                let err =
+                    StateError::Internal("the session vanished while removing it".to_string());
+                error!("{}", err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Delete the message of a session
+    async fn delete_message(&self, bot: &Bot, cache_key: &CacheKey) -> Result<(), MethodCall> {
+        self.dispatcher(bot)
+            .call(cache_key.chat_id, |bot| {
+                bot.delete_message(cache_key.chat_id, cache_key.message_id)
+                    .call()
+            })
+            .await
+    }
+
+    /// Keep a session whose last participant left until `Config::empty_session_grace` is over
+    async fn schedule_deletion(&self, cache_key: &CacheKey, session: &mut Session) {
+        let due = Instant::now() + self.config.empty_session_grace;
+        session.deletion_due = Some(due);
+        self.deletions
+            .lock()
+            .await
+            .insert_at(cache_key.clone(), due);
+    }
+
+    /// Delete a session whose grace period is over, unless someone joined it meanwhile
+    ///
+    /// A session that was joined and left again has a later deadline and is skipped as well.
+    async fn delete_if_abandoned(&self, bot: &Bot, cache_key: &CacheKey) {
+        let mut entries = self.entries.lock().await;
+        let due = match entries.get(cache_key) {
+            Some((session, _key)) => session
+                .deletion_due
+                .is_some_and(|due| due <= Instant::now()),
+            None => false,
+        };
+        if !due {
+            return;
+        }
+        let entry = self.take_entry(&mut entries, cache_key).await;
+        drop(entries);
+        if let Some((session, delay_key)) = entry {
+            self.delete_abandoned(bot, cache_key, session, delay_key)
+                .await;
+        }
+    }
+
+    /// Delete a session that its last participant left, along with its message
+    ///
+    /// The session can be restored with /undo, which brings its last participant back.
+    async fn delete_abandoned(
+        &self,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        mut session: Session,
+        delay_key: Option<delay_queue::Key>,
+    ) {
+        self.remove_expiration(delay_key).await;
+        self.publish_ended(cache_key);
+        // The creator is handed on whenever they leave, so they are always the last to leave
+        let user = session.creator.to_owned();
+        session.deletion_due = None;
+        session.participants.push(user.to_owned());
+        self.bury(session, &user).await;
+        if let Err(err) = self.delete_message(bot, cache_key).await {
+            error!("{}", err);
+        }
+    }
+
+    /// Remove a participant from a session.
+    async fn remove_participant(
+        &self,
+        bot: &Bot,
+        cache_key: &CacheKey,
+        user: &types::User,
+    ) -> Result<String, StateError> {
+        self.session_exists(cache_key).await?;
+
+        let mut entries = self.entries.lock().await;
+        let pomodoro = match entries.get_mut(cache_key) {
+            Some((pomodoro, _key)) => pomodoro,
+            None => {
+                let err = StateError::Internal(format!(
+                    "Failed to delete user {} (@{})!",
+                    &user.id,
+                    user.username.as_ref().unwrap_or(&user.first_name)
+                ));
+                error!("{}", err);
+                return Err(err);
             }
+        };
+
+        let msg = format!(
+            "@{} left the session.",
+            user.username.as_ref().unwrap_or(&user.first_name)
+        );
+        match pomodoro.leave(user.id) {
+            Departure::NotParticipant => Err(StateError::NotParticipant(None)),
+            Departure::Left => Ok(msg),
+            // the last participant left, others may still join during the grace period
+            Departure::Abandoned if !self.config.empty_session_grace.is_zero() => {
+                self.schedule_deletion(cache_key, pomodoro).await;
+                Ok(msg)
+            }
+            Departure::Abandoned => {
+                // the last participant left, so the session is deleted
+                let entry = self.take_entry(&mut entries, cache_key).await;
+                drop(entries);
+                if let Some((session, delay_key)) = entry {
+                    self.delete_abandoned(bot, cache_key, session, delay_key)
+                        .await;
+                }
+                Ok(msg)
+            }
+        }
+    }
+}
+
+/// Return the text of a session message above its list of subscribers, in any language
+///
+/// The list starts at the first line that consists of nothing but the marker, which follows an
+/// empty line. Names are rendered on the same line as other names or after `@`, so they never
+/// form such a line, no matter what they contain.
+fn legacy_header(text: &str) -> &str {
+    let markers: Vec<&str> = Lang::ALL
+        .iter()
+        .map(|&lang| messages::t(lang, "subscribers"))
+        .collect();
+    let mut offset = 0;
+    let mut previous_is_empty = false;
+    for line in text.split('\n') {
+        if previous_is_empty && markers.contains(&line.trim_end()) {
+            return text[..offset].trim_end();
         }
+        previous_is_empty = line.trim().is_empty();
+        offset += line.len() + 1;
+    }
+    text.trim_end()
+}
 
-        return return_val;
+/// Send the creator of a session a private message that someone joined.
+///
+/// Telegram refuses the message if the creator never started a private chat with the bot, which
+/// is expected and therefore not reported as an error.
+async fn notify_creator_on_join(
+    dispatcher: Dispatcher<'_>,
+    creator: &types::User,
+    user: &types::User,
+) {
+    let text = format!(
+        "@{} joined your Pomodoro",
+        user.username.as_ref().unwrap_or(&user.first_name)
+    );
+    let chat_id = chat::Id::from(creator.id);
+    match dispatcher
+        .call(chat_id, |bot| bot.send_message(chat_id, &text).call())
+        .await
+    {
+        Ok(_) => {}
+        Err(MethodCall::RequestError {
+            error_code: 403, ..
+        }) => {
+            debug!("Creator {} has not started a private chat", creator.id);
+        }
+        Err(err) => error!("{}", err),
     }
 }
 
+/// Send many messages concurrently, at most `MAX_CONCURRENT_SENDS` at a time
+///
+/// Failed messages are logged one by one and do not stop the others from being sent.
+pub(super) async fn send_all<F>(sends: impl IntoIterator<Item = F>)
+where
+    F: Future<Output = Result<types::Message, MethodCall>>,
+{
+    stream::iter(sends)
+        .buffer_unordered(MAX_CONCURRENT_SENDS)
+        .for_each(|result| async move {
+            if let Err(err) = result {
+                error!("{}", err);
+            }
+        })
+        .await
+}
+
+/// The outcome of a user trying to leave a session
+pub(crate) enum LeaveOutcome {
+    /// The user left the session, contains a message for the chat
+    Left(String),
+    /// The user is not subscribed to the session(s)
+    NotSubscribed,
+    /// The session does not exist (anymore)
+    NotFound,
+}
+
 /// A custom identifier of `chat::Id` and `message::Id` that acts as a key for the HashMap and
 /// DelayQueue.
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
 pub struct CacheKey {
     pub(self) chat_id: chat::Id,
     pub(self) message_id: message::Id,
@@ -544,10 +2612,215 @@ pub struct CacheKey {
 
 impl CacheKey {
     /// Return a new CacheKey
-    pub(self) fn new(chat_id: chat::Id, message_id: message::Id) -> CacheKey {
+    pub(crate) fn new(chat_id: chat::Id, message_id: message::Id) -> CacheKey {
         CacheKey {
             chat_id,
             message_id,
         }
     }
+
+    /// Return the chat of the session
+    pub(crate) fn chat_id(&self) -> chat::Id {
+        self.chat_id
+    }
+
+    /// Return the message that identifies the session
+    pub(crate) fn message_id(&self) -> message::Id {
+        self.message_id
+    }
+}
+
+/// Methods for referring to a session from inline buttons
+impl CacheKey {
+    /// Encode the key together with an action into callback data, e.g. `join:-1001:42`
+    pub(crate) fn to_callback_data(&self, action: &str) -> String {
+        format!("{}:{}:{}", action, self.chat_id.0, self.message_id.0)
+    }
+
+    /// Decode callback data created by `to_callback_data` into the action and the key
+    ///
+    /// Returns `None` if the data does not contain a key.
+    pub(crate) fn from_callback_data(data: &str) -> Option<(&str, CacheKey)> {
+        let mut parts = data.rsplitn(3, ':');
+        let message_id = parts.next()?.parse().ok()?;
+        let chat_id = parts.next()?.parse().ok()?;
+        let action = parts.next()?;
+        Some((
+            action,
+            CacheKey::new(chat::Id(chat_id), message::Id(message_id)),
+        ))
+    }
+}
+
+impl From<&types::Message> for CacheKey {
+    fn from(message: &types::Message) -> Self {
+        CacheKey::new(message.chat.id, message.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{anonymous, test_util};
+
+    /// Put a waiting group Pomodoro of `creator` into the state and return its key
+    async fn insert_pomodoro(state: &State, creator: types::User) -> CacheKey {
+        let message = test_util::message(test_util::group_json(-100), 10, "Pomodoro");
+        let session = Session::new_pomodoro(message, creator, None, None, 5).unwrap();
+        let cache_key = CacheKey::from(&session.message);
+        state
+            .entries
+            .lock()
+            .await
+            .insert(cache_key.clone(), (session, None));
+        cache_key
+    }
+
+    fn alice() -> types::User {
+        test_util::user(1001, "Alice", Some("alice"))
+    }
+
+    fn bob() -> types::User {
+        test_util::user(1002, "Bob", Some("bob"))
+    }
+
+    /// A session of an anonymous administrator, shared with Bob as an administrator
+    async fn insert_anonymous_pomodoro(state: &State) -> CacheKey {
+        let cache_key = insert_pomodoro(state, anonymous::placeholder().unwrap()).await;
+        if let Some((session, _key)) = state.entries.lock().await.get_mut(&cache_key) {
+            session.admins.insert(bob().id);
+            session.join(alice());
+        }
+        cache_key
+    }
+
+    #[tokio::test]
+    async fn creator_is_owner() {
+        let state = State::default();
+        let cache_key = insert_pomodoro(&state, alice()).await;
+        assert!(state.is_owner(&cache_key, &alice().id).await.is_ok());
+        assert!(state.is_owner(&cache_key, &bob().id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn admins_own_sessions_of_anonymous_admins() {
+        let state = State::default();
+        let cache_key = insert_anonymous_pomodoro(&state).await;
+        let placeholder = anonymous::placeholder().unwrap();
+        assert!(state.is_owner(&cache_key, &placeholder.id).await.is_ok());
+        assert!(state.is_owner(&cache_key, &bob().id).await.is_ok());
+        // Alice only joined the session
+        assert!(state.is_owner(&cache_key, &alice().id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn transfer_clears_the_admins() {
+        let state = State::default();
+        let cache_key = insert_anonymous_pomodoro(&state).await;
+        state
+            .transfer_ownership(&cache_key, &bob().id, "alice")
+            .await
+            .unwrap();
+        assert!(state.is_owner(&cache_key, &alice().id).await.is_ok());
+        assert!(state.is_owner(&cache_key, &bob().id).await.is_err());
+        let entries = state.entries.lock().await;
+        assert!(entries[&cache_key].0.admins.is_empty());
+    }
+
+    /// Render a session message the way the bot does, with `names` as its subscribers
+    fn rendered(header: &str, marker: &str, names: &[&str]) -> String {
+        let names: Vec<String> = names.iter().map(|name| format!("@{}", name)).collect();
+        format!("{}\n\n{}\n{}", header, marker, names.join(" "))
+    }
+
+    const HEADER: &str = "@alice has created a new Pomodoro!\nSession will start at 12:05 (UTC)";
+
+    #[test]
+    fn legacy_header_splits_at_the_subscriber_list() {
+        let text = rendered(HEADER, "Subscribers:", &["alice", "bob"]);
+        assert_eq!(legacy_header(&text), HEADER);
+    }
+
+    #[test]
+    fn legacy_header_ignores_markers_in_names() {
+        for name in [
+            "Subscribers:",
+            "Teilnehmer:",
+            "evil\nSubscribers:",
+            "x\n\nSubscribers:",
+        ] {
+            let text = rendered(HEADER, "Subscribers:", &["alice", name, "carol"]);
+            assert_eq!(legacy_header(&text), HEADER, "name {:?}", name);
+        }
+    }
+
+    #[test]
+    fn legacy_header_ignores_markers_in_the_creators_name() {
+        let header = "@Subscribers: has created a new Pomodoro!\nSession will start at 12:05 (UTC)";
+        let text = rendered(header, "Subscribers:", &["Subscribers:"]);
+        assert_eq!(legacy_header(&text), header);
+    }
+
+    #[test]
+    fn legacy_header_finds_the_marker_of_any_language() {
+        let text = rendered(HEADER, "Participantes:", &["Subscribers:"]);
+        assert_eq!(legacy_header(&text), HEADER);
+    }
+
+    #[test]
+    fn legacy_header_keeps_messages_without_a_list() {
+        assert_eq!(
+            legacy_header("Pomodoro session has been started!\n"),
+            "Pomodoro session has been started!"
+        );
+    }
+
+    #[tokio::test]
+    async fn pausing_removes_the_reminder() {
+        let state = State::default();
+        let message = test_util::message(test_util::group_json(-100), 10, "Pomodoro");
+        let mut session = Session::new_pomodoro(message, alice(), None, None, 5).unwrap();
+        session.reminder = Some(Duration::from_secs(60));
+        let cache_key = CacheKey::from(&session.message);
+        state.start_session(session).await;
+        assert_eq!(state.reminders.lock().await.len(), 1);
+
+        state.pause_session(&cache_key, &alice().id).await.unwrap();
+        assert!(state.reminders.lock().await.is_empty());
+        let entries = state.entries.lock().await;
+        assert!(entries[&cache_key].0.reminder_key.is_none());
+    }
+
+    #[tokio::test]
+    async fn cancelling_removes_the_heads_up() {
+        let state = State::default();
+        let message = test_util::message(test_util::group_json(-100), 10, "Pomodoro");
+        let start_time = Instant::now() + Duration::from_secs(600);
+        let session = Session::new_pomodoro(message, alice(), Some(start_time), None, 5).unwrap();
+        let cache_key = CacheKey::from(&session.message);
+        state.add_session_to_queue(session, false).await;
+        assert_eq!(state.reminders.lock().await.len(), 1);
+
+        let session = state.remove_session_from_queue(&cache_key).await.unwrap();
+        assert!(session.reminder_key.is_none());
+        assert!(state.reminders.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn debounced_refreshes_return_right_away() {
+        let state = Arc::new(State::default());
+        let bot = Bot::new("1234567890:ABCDE_FGH".to_string());
+        let message = test_util::message(test_util::group_json(-100), 10, "Pomodoro");
+        let started = Instant::now();
+
+        state
+            .update_participants_text_debounced(&bot, &message)
+            .await;
+        state
+            .update_participants_text_debounced(&bot, &message)
+            .await;
+        assert!(started.elapsed() < Duration::from_millis(500));
+        // The second refresh is merged into the pending one
+        assert_eq!(state.pending_edits.lock().await.len(), 1);
+    }
 }
@@ -3,97 +3,541 @@ use core::{
     result::Result::{Err, Ok},
     time::Duration,
 };
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc, task::Poll};
 
-use futures_util::stream::poll_fn;
-use tbot::{types::chat, Bot};
-use tokio::{join, stream::StreamExt, time::delay_for};
+use futures_util::future::poll_fn;
+use tbot::{
+    errors::MethodCall,
+    types::{self, chat, chat::member::Status},
+    Bot,
+};
+use tokio::{
+    join,
+    sync::broadcast,
+    time::{delay_for, delay_until},
+};
 
-use super::{session::Session, State};
+use log::{debug, error, warn};
+
+use super::{
+    send_all,
+    session::{Nudge, Session},
+    session_state::SessionState,
+    CacheKey, Dispatcher, SessionEvent, State,
+};
+use crate::{
+    bot::util,
+    chat_kind::{classify, ChatKindClass},
+    time,
+};
+
+/// Poll for expired entries and start polling again if a transition panicked
+///
+/// The locks of the state are tokio mutexes, which are released while unwinding and never
+/// poisoned. So the sessions that are still queued are handled by the next loop, only the
+/// session whose transition panicked is lost.
+pub(crate) async fn supervise_expired_entries(bot: Bot, state: Arc<State>) {
+    loop {
+        match tokio::spawn(poll_for_expired_entries(bot.clone(), state.clone())).await {
+            Err(err) if err.is_panic() => {
+                error!(
+                    "Polling for expired sessions panicked, restarting it: {}",
+                    err
+                );
+                delay_for(Duration::from_secs(1)).await;
+            }
+            _ => {
+                error!("Polling for expired sessions stopped");
+                return;
+            }
+        }
+    }
+}
 
 /// Periodically poll for expired entries from the DelayQueue
-pub(crate) async fn poll_for_expired_entries(bot: Bot, state: Arc<State>) {
+async fn poll_for_expired_entries(bot: Bot, state: Arc<State>) {
+    let dispatcher = state.dispatcher(&bot);
     // There might be a better way to poll new expirations, but this should be fine for now...
-    while let item = poll_fn(|cx| state.expirations.lock().unwrap().poll_expired(cx))
-        .next()
-        .await
-    {
-        if let Some(Ok(result)) = item {
-            let cache_key = result.into_inner();
-            let entry;
-            {
-                entry = state.entries.lock().unwrap().remove(&cache_key)
+    loop {
+        // Only hold the lock for a single poll, so other tasks can insert new entries meanwhile
+        let item = {
+            let mut expirations = state.expirations.lock().await;
+            poll_fn(|cx| Poll::Ready(expirations.poll_expired(cx))).await
+        };
+        let reminder = {
+            // The key is forgotten under the lock of the entries, so it is never removed once gone
+            let mut entries = state.entries.lock().await;
+            let mut reminders = state.reminders.lock().await;
+            let reminder = poll_fn(|cx| Poll::Ready(reminders.poll_expired(cx))).await;
+            if let Poll::Ready(Some(Ok(expired))) = &reminder {
+                let (cache_key, nudge) = expired.get_ref();
+                if let Some((session, _key)) = entries.get_mut(cache_key) {
+                    session.nudge_key(*nudge).take();
+                }
             }
+            reminder
+        };
+        let deletion = {
+            let mut deletions = state.deletions.lock().await;
+            poll_fn(|cx| Poll::Ready(deletions.poll_expired(cx))).await
+        };
+        let idle = !matches!(item, Poll::Ready(Some(_)))
+            && !matches!(reminder, Poll::Ready(Some(_)))
+            && !matches!(deletion, Poll::Ready(Some(_)));
+
+        if let Poll::Ready(Some(Ok(result))) = deletion {
+            state.delete_if_abandoned(&bot, &result.into_inner()).await;
+        }
+
+        if let Poll::Ready(Some(Ok(result))) = reminder {
+            let (cache_key, nudge) = result.into_inner();
+            remind_participants(dispatcher, state.clone(), cache_key, nudge).await;
+        }
+        if let Poll::Ready(Some(Ok(result))) = item {
+            let cache_key = result.into_inner();
+            let entry = {
+                let mut entries = state.entries.lock().await;
+                state.take_entry(&mut entries, &cache_key).await
+            };
+            // Dispatch on the state itself, so every state has exactly one transition
             if let Some((session, _key)) = entry {
-                if session.is_waiting() {
-                    start_pomodoro(&bot, state.clone(), session).await;
-                } else if session.is_running() {
-                    end_pomodoro(&bot, state.clone(), session).await;
-                } else if session.is_awaiting_break() {
-                    start_break(state.clone(), session);
-                } else if session.is_taking_a_break() {
-                    end_break(&bot, session).await;
+                // Nobody is left to take part in the next state
+                if session.deletion_due.is_some() {
+                    state
+                        .delete_abandoned(&bot, &cache_key, session, None)
+                        .await;
+                    continue;
+                }
+                match session.state {
+                    SessionState::PomodoroWaiting => {
+                        start_pomodoro(dispatcher, state.clone(), session).await
+                    }
+                    SessionState::PomodoroRunning => {
+                        end_pomodoro(dispatcher, state.clone(), session).await
+                    }
+                    SessionState::BreakWaiting => start_break(state.clone(), session).await,
+                    SessionState::BreakRunning => end_break(dispatcher, &state, session).await,
+                    // Paused sessions are taken out of the DelayQueue, so this is a stale key
+                    SessionState::PomodoroPaused => {
+                        warn!(
+                            "Paused session {} in chat {} expired, dropping it",
+                            cache_key.message_id, cache_key.chat_id
+                        );
+                    }
                 }
             }
-        } else {
+        }
+        if idle {
             delay_for(Duration::from_secs(1)).await;
         }
     }
 }
 
-fn start_break(state: Arc<State>, session: Session) {
-    state.start_break(session);
+/// Periodically create the Pomodoros of recurring sessions
+///
+/// The sessions are subject to the same limits as the ones created with /25.
+pub(crate) async fn poll_recurrences(bot: Bot, state: Arc<State>) {
+    loop {
+        for recurrence in state.due_recurrences().await {
+            util::create_pomodoro(
+                &bot,
+                state.clone(),
+                recurrence.chat,
+                recurrence.creator,
+                None,
+                recurrence.duration,
+                None,
+            )
+            .await;
+        }
+        delay_for(Duration::from_secs(1)).await;
+    }
+}
+
+/// Post a summary of the Pomodoros completed that day to every chat, once a day at `hour` UTC
+///
+/// The first summary is only posted once the hour comes around again, so a restart never posts
+/// a day twice.
+pub(crate) async fn post_daily_summaries(bot: Bot, state: Arc<State>, hour: u32) {
+    loop {
+        delay_until(time::next_utc_hour(hour)).await;
+        let summaries: Vec<(chat::Id, String)> = state
+            .take_daily_stats()
+            .await
+            .into_iter()
+            .filter(|(_chat_id, stats)| stats.completed_pomodoros > 0)
+            .map(|(chat_id, stats)| (chat_id, stats.summary()))
+            .collect();
+        let dispatcher = state.dispatcher(&bot);
+        // Collected first, a lazy iterator would not be Send
+        let posts: Vec<_> = summaries
+            .iter()
+            .map(|(chat_id, summary)| {
+                dispatcher.call(*chat_id, move |bot| {
+                    bot.send_message(*chat_id, summary.as_str()).call()
+                })
+            })
+            .collect();
+        send_all(posts).await;
+    }
+}
+
+/// Periodically refresh the progress bar and countdown in the messages of running Pomodoros
+///
+/// Messages are edited one after another, so a busy bot does not run into the rate limits.
+/// Sessions that are paused or over are no longer running and drop out by themselves.
+pub(crate) async fn update_progress(bot: Bot, state: Arc<State>) {
+    loop {
+        delay_for(Duration::from_secs(30)).await;
+        for message in state.running_session_messages().await {
+            state.update_participants_text(&bot, &message).await;
+        }
+    }
+}
+
+/// Periodically remove participants who are no longer in a group from its sessions
+///
+/// Only a few participants are checked at a time, see `State::due_membership_checks`. Users the
+/// bot can't look up are kept, since they might still be in the group.
+pub(crate) async fn prune_departed_participants(bot: Bot, state: Arc<State>) {
+    let dispatcher = state.dispatcher(&bot);
+    loop {
+        delay_for(Duration::from_secs(60)).await;
+        for (cache_key, user) in state.due_membership_checks().await {
+            let chat_id = cache_key.chat_id;
+            let member = dispatcher
+                .call(chat_id, |bot| bot.get_chat_member(chat_id, user.id).call())
+                .await;
+            let departed = match member {
+                Ok(member) => matches!(
+                    member.status,
+                    Status::Left
+                        | Status::Kicked { .. }
+                        | Status::Restricted {
+                            is_member: false,
+                            ..
+                        }
+                ),
+                Err(err) => {
+                    debug!(
+                        "Could not check whether user {} is in chat {}: {}",
+                        user.id, chat_id, err
+                    );
+                    false
+                }
+            };
+            if departed {
+                debug!("User {} left chat {}, removing them", user.id, chat_id);
+                if let Err(err) = state.leave_session(&bot, &cache_key, &user).await {
+                    error!("{}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Log the lifecycle events of all sessions
+pub(crate) async fn log_session_events(mut events: broadcast::Receiver<SessionEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(SessionEvent::StateChanged {
+                chat_id,
+                message_id,
+                state,
+            }) => debug!(
+                "Session {} in chat {} is now {:?}",
+                message_id, chat_id, state
+            ),
+            Ok(SessionEvent::Ended {
+                chat_id,
+                message_id,
+            }) => debug!("Session {} in chat {} ended", message_id, chat_id),
+            Err(broadcast::RecvError::Lagged(missed)) => {
+                warn!("Missed {} session events", missed)
+            }
+            Err(broadcast::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Periodically write all sessions to disk so they survive a restart
+pub(crate) async fn persist_sessions(state: Arc<State>, path: PathBuf) {
+    loop {
+        delay_for(Duration::from_secs(10)).await;
+        if let Err(err) = state.save_to_disk(&path).await {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Periodically write the configuration of all chats to disk so it survives a restart
+pub(crate) async fn persist_chat_config(state: Arc<State>, path: PathBuf) {
+    loop {
+        delay_for(Duration::from_secs(60)).await;
+        if let Err(err) = state.save_chat_config(&path).await {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Remind the participants of a Pomodoro that it is about to start or end
+///
+/// Group Pomodoros that nobody joined may be started early instead.
+/// The session stays in the state.
+async fn remind_participants(
+    dispatcher: Dispatcher<'_>,
+    state: Arc<State>,
+    cache_key: CacheKey,
+    nudge: Nudge,
+) {
+    match nudge {
+        Nudge::SoloStart => {
+            if let Some(session) = state.take_due_solo_start(&cache_key).await {
+                start_pomodoro(dispatcher, state, session).await;
+            }
+        }
+        Nudge::Reminder => {
+            if let Some(session) = state.due_reminder(&cache_key).await {
+                let result = if session.is_waiting() {
+                    session.notify_participants_pre_start(dispatcher).await
+                } else {
+                    session.notify_participants_reminder(dispatcher).await
+                };
+                if let Err(err_msg) = result {
+                    error!("{}", err_msg);
+                }
+            }
+        }
+    }
+}
+
+/// Start a break that was requested with `/5`, which keeps its own duration
+async fn start_break(state: Arc<State>, session: Session) {
+    let duration = session.duration;
+    state.start_break(session, false, duration).await;
 }
 
 /// Start a new pomodoro session
-async fn start_pomodoro(bot: &Bot, state: Arc<State>, mut pomodoro: Session) {
-    match pomodoro.message().chat.kind {
-        chat::Kind::Group { .. } | chat::Kind::Supergroup { .. } => {
-            pomodoro.notify_participants_on_start(bot).await;
-            state.start_session(pomodoro);
+async fn start_pomodoro(dispatcher: Dispatcher<'_>, state: Arc<State>, mut pomodoro: Session) {
+    match classify(&pomodoro.message.chat) {
+        ChatKindClass::Group | ChatKindClass::Channel => {
+            pomodoro.notify_participants_on_start(dispatcher).await;
+            state.start_session(pomodoro).await;
         }
-        chat::Kind::Private { .. } => {
-            state.start_session(pomodoro);
+        ChatKindClass::Private => {
+            state.start_session(pomodoro).await;
         }
-        _ => {
-            dbg!("/start called outside of a chat");
+        ChatKindClass::Other => {
+            warn!("/start called outside of a chat");
         }
     }
 }
 
 /// End a running pomodoro session.
-async fn end_pomodoro(bot: &Bot, state: Arc<State>, mut pomodoro: Session) {
-    if let Err(err_msg) = pomodoro.notify_participants_on_end(&bot).await {
-        dbg!(err_msg.to_string());
+///
+/// Every `long_break_interval` completed Pomodoros, a long break is taken instead of a short one.
+async fn end_pomodoro(dispatcher: Dispatcher<'_>, state: Arc<State>, mut pomodoro: Session) {
+    let long_break = state.complete_pomodoro(pomodoro.chat().id).await;
+    state.record_completed_pomodoro(&pomodoro).await;
+    state.record_history(&pomodoro).await;
+    let break_duration = state.break_duration(pomodoro.chat().id, long_break).await;
+    let lang = state.get_language(pomodoro.chat().id).await;
+    pomodoro.unpin(dispatcher).await;
+    let notice = pomodoro
+        .notify_participants_on_end(dispatcher, break_duration, long_break, lang)
+        .await;
+    start_announced_break(&state, pomodoro, long_break, break_duration, notice).await;
+}
+
+/// Start the break of a Pomodoro whose end was announced with `notice`
+///
+/// The break is keyed on the message announcing it, so without one there is nothing to
+/// start. The session is already out of the queues, so it is dropped.
+async fn start_announced_break(
+    state: &State,
+    pomodoro: Session,
+    long_break: bool,
+    break_duration: Duration,
+    notice: Result<types::Message, MethodCall>,
+) {
+    if let Err(err_msg) = notice {
+        error!(
+            "Failed to announce the break in chat {}, dropping the session: {}",
+            pomodoro.chat().id,
+            err_msg
+        );
+        state.publish_ended(&CacheKey::new(pomodoro.chat().id, pomodoro.message().id));
+        return;
     }
 
-    state.start_break(pomodoro);
+    state
+        .start_break(pomodoro, long_break, break_duration)
+        .await;
 }
 
-async fn end_break(bot: &Bot, pomodoro: Session) {
-    match pomodoro.message.chat.kind {
-        chat::Kind::Private { .. } => {
+/// End a break, the next Pomodoro of a chain starts right away
+pub(super) async fn end_break(dispatcher: Dispatcher<'_>, state: &State, pomodoro: Session) {
+    state.publish_ended(&CacheKey::new(pomodoro.chat().id, pomodoro.message().id));
+    if pomodoro.long_break {
+        state.reset_completed_pomodoros(pomodoro.chat().id).await;
+    }
+    match pomodoro.rounds {
+        Some(rounds) if !rounds.is_last() => {
+            state.start_next_round(dispatcher, &pomodoro, rounds).await;
+            return;
+        }
+        Some(rounds) => {
+            state.reset_completed_pomodoros(pomodoro.chat().id).await;
+            if let Err(err_msg) = pomodoro
+                .notify_participants_on_rounds_done(dispatcher, &rounds)
+                .await
+            {
+                error!("{}", err_msg);
+            }
+            return;
+        }
+        None => {}
+    }
+    let ask_to_continue = state.continue_prompt_enabled(pomodoro.chat().id).await;
+    state.remember_continuation(&pomodoro).await;
+
+    match classify(&pomodoro.message.chat) {
+        ChatKindClass::Private => {
             join!(
                 async {
-                    if let Err(err_msg) = bot
-                        .delete_message(pomodoro.chat().id, pomodoro.message().id)
-                        .call()
-                        .await
-                    {
-                        dbg!(err_msg.to_string());
+                    if let Err(err_msg) = pomodoro.delete_message(dispatcher).await {
+                        error!("{}", err_msg);
                     }
                 },
                 async {
-                    if let Err(err_msg) = pomodoro.notify_participants_on_break_end(&bot).await {
-                        dbg!(err_msg.to_string());
+                    if let Err(err_msg) = pomodoro
+                        .notify_participants_on_break_end(dispatcher, ask_to_continue)
+                        .await
+                    {
+                        error!("{}", err_msg);
                     }
                 },
             );
         }
         _ => {
-            if let Err(err_msg) = pomodoro.notify_participants_on_break_end(&bot).await {
-                dbg!(err_msg.to_string());
+            if let Err(err_msg) = pomodoro
+                .notify_participants_on_break_end(dispatcher, ask_to_continue)
+                .await
+            {
+                error!("{}", err_msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, messages::Lang, test_util};
+
+    fn bot() -> Bot {
+        Bot::new("1234567890:ABCDE_FGH".to_string())
+    }
+
+    fn running_pomodoro() -> Session {
+        let message = test_util::message(test_util::group_json(-100), 10, "Pomodoro");
+        let creator = test_util::user(1001, "Alice", None);
+        let mut pomodoro = Session::new_pomodoro(message, creator, None, None, 5).unwrap();
+        pomodoro.start();
+        pomodoro
+    }
+
+    #[tokio::test]
+    async fn failed_end_notice_drops_the_session() {
+        let state = State::new(Config::default());
+        let pomodoro = running_pomodoro();
+        let chat_id = pomodoro.chat().id;
+        let cache_key = CacheKey::new(chat_id, pomodoro.message().id);
+        state.start_session(pomodoro).await;
+        // Take the session out of the queues like the poll loop does once it expired
+        let (pomodoro, delay_key) = {
+            let mut entries = state.entries.lock().await;
+            state.take_entry(&mut entries, &cache_key).await.unwrap()
+        };
+        state.expirations.lock().await.remove(&delay_key.unwrap());
+        let mut events = state.subscribe_events();
+
+        let notice = Err(MethodCall::RequestError {
+            description: "Forbidden: bot was kicked from the group chat".to_string(),
+            error_code: 403,
+            migrate_to_chat_id: None,
+            retry_after: None,
+        });
+        let break_duration = state.config.break_duration;
+        start_announced_break(&state, pomodoro, false, break_duration, notice).await;
+
+        // Neither the session nor a break of it is left
+        assert!(state.entries.lock().await.is_empty());
+        assert!(state.expirations.lock().await.is_empty());
+        match events.try_recv() {
+            Ok(SessionEvent::Ended {
+                chat_id: ended_chat,
+                message_id,
+            }) => {
+                assert_eq!(ended_chat, chat_id);
+                assert_eq!(message_id.0, 10);
             }
+            _ => panic!("The end of the session was not published"),
         }
     }
+
+    #[tokio::test]
+    async fn announced_end_starts_the_break() {
+        let state = State::new(Config::default());
+        let bot = bot();
+        let pomodoro = running_pomodoro();
+        let chat_id = pomodoro.chat().id;
+
+        let notice = state
+            .dispatcher(&bot)
+            .call(chat_id, |_bot| async {
+                Ok(test_util::message(test_util::group_json(-100), 11, "Break"))
+            })
+            .await;
+        let break_duration = state.config.break_duration;
+        start_announced_break(&state, pomodoro, false, break_duration, notice).await;
+
+        let entries = state.entries.lock().await;
+        assert_eq!(entries.len(), 1);
+        let (session, delay_key) = entries.values().next().unwrap();
+        assert_eq!(session.state, SessionState::BreakRunning);
+        assert!(delay_key.is_some());
+    }
+
+    #[tokio::test]
+    async fn break_follows_the_defaults_of_the_chat() {
+        let state = State::new(Config::default());
+        let bot = bot();
+        let pomodoro = running_pomodoro();
+        let chat_id = pomodoro.chat().id;
+        let ten_minutes = Duration::from_secs(10 * 60);
+        state
+            .set_defaults(chat_id, Duration::from_secs(50 * 60), ten_minutes)
+            .await
+            .unwrap();
+
+        let break_duration = state.break_duration(chat_id, false).await;
+        assert_eq!(break_duration, ten_minutes);
+        assert_eq!(
+            pomodoro.end_header(break_duration, false, Lang::En),
+            "Session is over! Now take a short, 10 minute break"
+        );
+
+        let notice = state
+            .dispatcher(&bot)
+            .call(chat_id, |_bot| async {
+                Ok(test_util::message(test_util::group_json(-100), 11, "Break"))
+            })
+            .await;
+        start_announced_break(&state, pomodoro, false, break_duration, notice).await;
+
+        let entries = state.entries.lock().await;
+        let (session, _key) = entries.values().next().unwrap();
+        assert_eq!(session.duration, ten_minutes);
+    }
 }
@@ -0,0 +1,452 @@
+//! Persistence of sessions and chat configuration across restarts.
+//!
+//! Telegram types of tbot can only be deserialized, so they are written to disk in the shape of
+//! the Bot API and read back with tbot's own deserializers. Instants are meaningless across
+//! restarts and are stored as unix timestamps in milliseconds instead.
+//!
+//! The configuration of chats is written to a file of its own, which carries a version so that
+//! files of older releases can be migrated.
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs, io,
+    path::Path,
+    sync::atomic::Ordering,
+};
+
+use core::time::Duration;
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use tbot::types::{self, chat, message, user};
+use tokio::time::Instant;
+
+use super::{
+    quiet_hours::QuietHours,
+    session::{Rounds, Session},
+    session_state::SessionState,
+    CacheKey, State,
+};
+use crate::{messages::Lang, time};
+
+/// Version of the layout of the chat configuration file, bump it on incompatible changes
+const CONFIG_VERSION: u32 = 1;
+
+/// The chat configuration file
+#[derive(Serialize, Deserialize)]
+struct ConfigFile {
+    version: u32,
+    #[serde(default)]
+    chats: Vec<ChatConfig>,
+}
+
+/// The settings of a single chat that differ from the defaults
+#[derive(Serialize, Deserialize)]
+struct ChatConfig {
+    chat_id: chat::Id,
+    #[serde(default)]
+    timezone: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    /// Default Pomodoro and break durations in seconds
+    #[serde(default)]
+    defaults: Option<(u64, u64)>,
+    #[serde(default = "enabled")]
+    join_notifications: bool,
+    #[serde(default)]
+    ask_to_continue: bool,
+    #[serde(default)]
+    pin_sessions: bool,
+    /// A range like `22:00-08:00`
+    #[serde(default)]
+    quiet_hours: Option<String>,
+}
+
+/// Join notifications are on unless a chat turned them off
+fn enabled() -> bool {
+    true
+}
+
+/// A single entry of the sessions file
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    cache_key: CacheKey,
+    session: Session,
+    /// Unix timestamp in milliseconds of when the entry shall be yielded back by the DelayQueue.
+    expires_at: i64,
+}
+
+/// Methods for persisting sessions
+impl State {
+    /// Write all sessions to `path`
+    pub(crate) async fn save_to_disk(&self, path: &Path) -> io::Result<()> {
+        let records: Vec<Entry> = self
+            .entries
+            .lock()
+            .await
+            .iter()
+            .map(|(cache_key, (session, _key))| Entry {
+                cache_key: cache_key.to_owned(),
+                session: session.to_owned(),
+                expires_at: time::instant_to_timestamp(session.deadline()),
+            })
+            .collect();
+
+        // Write to a temporary file first so a crash never leaves a truncated file behind
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&records)?)?;
+        fs::rename(tmp_path, path)
+    }
+
+    /// Load the sessions saved in `path` and put them back into the DelayQueue.
+    ///
+    /// Sessions whose expiration already passed are yielded back immediately.
+    /// Returns the number of restored sessions.
+    pub(crate) async fn load_from_disk(&self, path: &Path) -> io::Result<usize> {
+        if !path.exists() {
+            return Ok(0);
+        }
+        let records: Vec<Entry> = serde_json::from_slice(&fs::read(path)?)?;
+
+        let mut restored = 0;
+        for Entry {
+            cache_key,
+            mut session,
+            expires_at,
+        } in records
+        {
+            if self.session_exists(&cache_key).await.is_ok() {
+                continue;
+            }
+            // New sessions have to be ordered after the restored ones
+            self.next_seq.fetch_max(session.seq + 1, Ordering::Relaxed);
+            // The reminders must not expire before the session is in the entries
+            let mut entries = self.entries.lock().await;
+            // Paused sessions stay out of the DelayQueue until they are resumed
+            let delay_key = if session.is_paused() {
+                None
+            } else {
+                let when = time::timestamp_to_instant(expires_at).max(Instant::now());
+                Some(
+                    self.expirations
+                        .lock()
+                        .await
+                        .insert_at(cache_key.clone(), when),
+                )
+            };
+            self.schedule_pre_start(&cache_key, &mut session).await;
+            self.schedule_reminder(&cache_key, &mut session).await;
+            // The grace period of a session that everyone left starts over
+            if session.participants.is_empty() {
+                self.schedule_deletion(&cache_key, &mut session).await;
+            }
+            entries.insert(cache_key, (session, delay_key));
+            restored += 1;
+        }
+        Ok(restored)
+    }
+}
+
+/// Methods for persisting the configuration of chats
+impl State {
+    /// Write the configuration of all chats that changed a setting to `path`
+    pub(crate) async fn save_chat_config(&self, path: &Path) -> io::Result<()> {
+        let timezones = self.timezones.lock().await.clone();
+        let languages = self.languages.lock().await.clone();
+        let defaults = self.defaults.lock().await.clone();
+        let join_disabled_chats = self.join_disabled_chats.lock().await.clone();
+        let continue_prompt_chats = self.continue_prompt_chats.lock().await.clone();
+        let pin_chats = self.pin_chats.lock().await.clone();
+        let quiet_hours = self.quiet_hours.lock().await.clone();
+
+        // Sorted, so the file only changes if the configuration does
+        let chat_ids: BTreeSet<i64> = timezones
+            .keys()
+            .chain(languages.keys())
+            .chain(defaults.keys())
+            .chain(join_disabled_chats.iter())
+            .chain(continue_prompt_chats.iter())
+            .chain(pin_chats.iter())
+            .chain(quiet_hours.keys())
+            .map(|chat_id| chat_id.0)
+            .collect();
+        let chats = chat_ids
+            .into_iter()
+            .map(chat::Id)
+            .map(|chat_id| ChatConfig {
+                chat_id,
+                timezone: timezones.get(&chat_id).map(|tz| tz.name().to_string()),
+                language: languages.get(&chat_id).map(|lang| lang.code().to_string()),
+                defaults: defaults.get(&chat_id).map(|(pomodoro, break_duration)| {
+                    (pomodoro.as_secs(), break_duration.as_secs())
+                }),
+                join_notifications: !join_disabled_chats.contains(&chat_id),
+                ask_to_continue: continue_prompt_chats.contains(&chat_id),
+                pin_sessions: pin_chats.contains(&chat_id),
+                quiet_hours: quiet_hours.get(&chat_id).map(ToString::to_string),
+            })
+            .collect();
+        let file = ConfigFile {
+            version: CONFIG_VERSION,
+            chats,
+        };
+
+        // Write to a temporary file first so a crash never leaves a truncated file behind
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&file)?)?;
+        fs::rename(tmp_path, path)
+    }
+
+    /// Load the configuration of chats saved in `path`.
+    ///
+    /// Nothing is applied if the file is malformed or written by a newer release, so the bot
+    /// starts with the defaults. Settings that cannot be parsed are skipped.
+    /// Returns the number of restored chats.
+    pub(crate) async fn load_chat_config(&self, path: &Path) -> Result<usize, String> {
+        if !path.exists() {
+            return Ok(0);
+        }
+        let content = fs::read(path).map_err(|err| err.to_string())?;
+        let file: ConfigFile = serde_json::from_slice(&content).map_err(|err| err.to_string())?;
+        if file.version > CONFIG_VERSION {
+            return Err(format!(
+                "version {} is newer than the supported version {}",
+                file.version, CONFIG_VERSION
+            ));
+        }
+
+        let restored = file.chats.len();
+        for chat in file.chats {
+            let chat_id = chat.chat_id;
+            if let Some(name) = chat.timezone {
+                match name.parse() {
+                    Ok(tz) => self.set_timezone(chat_id, tz).await,
+                    Err(err) => warn!("Skipping the timezone of chat {}: {}", chat_id, err),
+                }
+            }
+            if let Some(code) = chat.language {
+                match code.parse::<Lang>() {
+                    Ok(lang) => self.set_language(chat_id, lang).await,
+                    Err(err) => warn!("Skipping the language of chat {}: {}", chat_id, err),
+                }
+            }
+            if let Some((pomodoro, break_duration)) = chat.defaults {
+                let pomodoro = Duration::from_secs(pomodoro);
+                let break_duration = Duration::from_secs(break_duration);
+                if let Err(err) = self.set_defaults(chat_id, pomodoro, break_duration).await {
+                    warn!(
+                        "Skipping the default durations of chat {}: {}",
+                        chat_id, err
+                    );
+                }
+            }
+            self.set_join_notifications(chat_id, chat.join_notifications)
+                .await;
+            self.set_continue_prompt(chat_id, chat.ask_to_continue)
+                .await;
+            self.set_pin_sessions(chat_id, chat.pin_sessions).await;
+            if let Some(range) = chat.quiet_hours {
+                match QuietHours::parse(&range) {
+                    Some(quiet_hours) => self.set_quiet_hours(chat_id, Some(quiet_hours)).await,
+                    None => warn!("Skipping the quiet hours of chat {}: {}", chat_id, range),
+                }
+            }
+        }
+        Ok(restored)
+    }
+}
+
+/// The representation of a Session that is written to disk
+#[derive(Serialize)]
+struct SessionRef<'a> {
+    state: &'a SessionState,
+    message: Value,
+    creator: Value,
+    participants: Vec<Value>,
+    creation_time: i64,
+    start_time: i64,
+    duration: u64,
+    long_break: bool,
+    remaining: Option<u64>,
+    reminder: Option<u64>,
+    seq: u64,
+    header: Option<&'a str>,
+    muted: &'a HashSet<user::Id>,
+    pinned: Option<message::Id>,
+    admins: &'a HashSet<user::Id>,
+    focus_mode: bool,
+    /// The current round, the number of rounds and the length of their Pomodoros in seconds
+    rounds: Option<(u32, u32, u64)>,
+    ready: &'a HashSet<user::Id>,
+    label: Option<&'a str>,
+}
+
+/// The representation of a Session that is read from disk
+#[derive(Deserialize)]
+struct SessionData {
+    state: SessionState,
+    message: types::Message,
+    creator: types::User,
+    participants: Vec<types::User>,
+    creation_time: i64,
+    start_time: i64,
+    duration: u64,
+    #[serde(default)]
+    long_break: bool,
+    #[serde(default)]
+    remaining: Option<u64>,
+    #[serde(default)]
+    reminder: Option<u64>,
+    #[serde(default)]
+    seq: u64,
+    #[serde(default)]
+    header: Option<String>,
+    #[serde(default)]
+    muted: HashSet<user::Id>,
+    #[serde(default)]
+    pinned: Option<message::Id>,
+    #[serde(default)]
+    admins: HashSet<user::Id>,
+    #[serde(default)]
+    focus_mode: bool,
+    #[serde(default)]
+    rounds: Option<(u32, u32, u64)>,
+    #[serde(default)]
+    ready: HashSet<user::Id>,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+impl Serialize for Session {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SessionRef {
+            state: &self.state,
+            message: message_to_value(&self.message),
+            creator: user_to_value(&self.creator),
+            participants: self.participants.iter().map(user_to_value).collect(),
+            creation_time: time::instant_to_timestamp(self.creation_time),
+            start_time: time::instant_to_timestamp(self.start_time),
+            duration: self.duration.as_secs(),
+            long_break: self.long_break,
+            remaining: self.remaining.map(|remaining| remaining.as_secs()),
+            reminder: self.reminder.map(|reminder| reminder.as_secs()),
+            seq: self.seq,
+            header: self.header.as_deref(),
+            muted: &self.muted,
+            pinned: self.pinned,
+            admins: &self.admins,
+            focus_mode: self.focus_mode,
+            rounds: self
+                .rounds
+                .map(|rounds| (rounds.current, rounds.total, rounds.length.as_secs())),
+            ready: &self.ready,
+            label: self.label.as_deref(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Session {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = SessionData::deserialize(deserializer)?;
+        Ok(Session {
+            state: data.state,
+            message: data.message,
+            creator: data.creator,
+            participants: data.participants.into_iter().collect(),
+            creation_time: time::timestamp_to_instant(data.creation_time),
+            start_time: time::timestamp_to_instant(data.start_time),
+            duration: Duration::from_secs(data.duration),
+            long_break: data.long_break,
+            remaining: data.remaining.map(Duration::from_secs),
+            reminder: data.reminder.map(Duration::from_secs),
+            seq: data.seq,
+            header: data.header,
+            muted: data.muted,
+            pinned: data.pinned,
+            admins: data.admins,
+            focus_mode: data.focus_mode,
+            rounds: data.rounds.map(|(current, total, length)| Rounds {
+                current,
+                total,
+                length: Duration::from_secs(length),
+            }),
+            ready: data.ready,
+            quiet_hours: None,
+            deletion_due: None,
+            reminder_key: None,
+            solo_start_key: None,
+            label: data.label,
+        })
+    }
+}
+
+/// Insert `value` into `map` unless it is `None`
+fn insert_some<T: Serialize>(map: &mut Map<String, Value>, key: &str, value: &Option<T>) {
+    if let Some(value) = value {
+        map.insert(key.to_string(), serde_json::json!(value));
+    }
+}
+
+/// Convert a Message into its Bot API representation
+fn message_to_value(message: &types::Message) -> Value {
+    let mut map = Map::new();
+    map.insert("message_id".to_string(), serde_json::json!(message.id));
+    map.insert("date".to_string(), serde_json::json!(message.date));
+    map.insert("chat".to_string(), chat_to_value(&message.chat));
+    if let message::Kind::Text(text) = &message.kind {
+        map.insert("text".to_string(), serde_json::json!(text.value));
+    }
+    Value::Object(map)
+}
+
+/// Convert a Chat into its Bot API representation
+fn chat_to_value(chat: &types::Chat) -> Value {
+    let mut map = Map::new();
+    map.insert("id".to_string(), serde_json::json!(chat.id));
+    let kind = match &chat.kind {
+        chat::Kind::Private {
+            username,
+            first_name,
+            last_name,
+            ..
+        } => {
+            insert_some(&mut map, "username", username);
+            insert_some(&mut map, "first_name", &Some(first_name));
+            insert_some(&mut map, "last_name", last_name);
+            "private"
+        }
+        chat::Kind::Group { title, .. } => {
+            insert_some(&mut map, "title", &Some(title));
+            "group"
+        }
+        chat::Kind::Supergroup {
+            title, username, ..
+        } => {
+            insert_some(&mut map, "title", &Some(title));
+            insert_some(&mut map, "username", username);
+            "supergroup"
+        }
+        chat::Kind::Channel {
+            title, username, ..
+        } => {
+            insert_some(&mut map, "title", &Some(title));
+            insert_some(&mut map, "username", username);
+            "channel"
+        }
+        _ => "private",
+    };
+    map.insert("type".to_string(), serde_json::json!(kind));
+    Value::Object(map)
+}
+
+/// Convert a User into its Bot API representation
+fn user_to_value(user: &types::User) -> Value {
+    let mut map = Map::new();
+    map.insert("id".to_string(), serde_json::json!(user.id));
+    map.insert("is_bot".to_string(), serde_json::json!(user.is_bot));
+    map.insert("first_name".to_string(), serde_json::json!(user.first_name));
+    insert_some(&mut map, "last_name", &user.last_name);
+    insert_some(&mut map, "username", &user.username);
+    insert_some(&mut map, "language_code", &user.language_code);
+    Value::Object(map)
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+
+use chrono::{NaiveTime, Utc};
+use chrono_tz::Tz;
+
+/// A daily range of time in which nobody is mentioned in a chat, e.g. `22:00-08:00`
+///
+/// The range is in the timezone of the chat and may span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QuietHours {
+    pub(crate) start: NaiveTime,
+    pub(crate) end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Parse a range like `22:00-08:00`
+    ///
+    /// Returns `None` if the input is malformed or the range is empty.
+    pub(crate) fn parse(input: &str) -> Option<QuietHours> {
+        let mut times = input.split('-');
+        let start = NaiveTime::parse_from_str(times.next()?.trim(), "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(times.next()?.trim(), "%H:%M").ok()?;
+        if times.next().is_some() || start == end {
+            return None;
+        }
+        Some(QuietHours { start, end })
+    }
+
+    /// Return whether `time` falls into the range
+    pub(crate) fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// Return whether it is quiet right now in the timezone `tz`
+    pub(crate) fn is_quiet_now(&self, tz: Tz) -> bool {
+        self.contains(Utc::now().with_timezone(&tz).time())
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
@@ -0,0 +1,31 @@
+use core::time::Duration;
+
+use tbot::types;
+use tokio::time::Instant;
+
+/// A Pomodoro that is created again and again in a fixed interval, see `/25 every`.
+///
+/// The breaks of the created sessions follow the defaults of the chat.
+#[derive(Debug, Clone)]
+pub(crate) struct Recurrence {
+    /// The chat the sessions are created in
+    pub(crate) chat: types::Chat,
+    /// The user who registered the recurrence and creates every session
+    pub(crate) creator: types::User,
+    /// Time between the creation of two sessions
+    pub(crate) interval: Duration,
+    /// Length of every Pomodoro. `None` falls back to the default of the chat.
+    pub(crate) duration: Option<Duration>,
+    /// When the next session is going to be created
+    pub(super) next_run: Instant,
+}
+
+impl Recurrence {
+    /// Advance to the next run after now, skipping runs that were missed meanwhile
+    pub(super) fn advance(&mut self) {
+        let now = Instant::now();
+        while self.next_run <= now {
+            self.next_run += self.interval;
+        }
+    }
+}
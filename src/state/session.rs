@@ -1,13 +1,27 @@
+use chrono_tz::Tz;
 use core::time::Duration;
 use std::collections::HashSet;
 
-use tbot::{errors::MethodCall, types, types::chat, Bot};
-use tokio::{join, time::Instant};
+use tbot::{
+    errors::MethodCall,
+    types,
+    types::{chat, message, parameters::Text, user},
+};
+use tokio::{join, time::delay_queue, time::Instant};
 
-use crate::{markup, time};
+use log::{debug, error, warn};
 
-use super::session_state::SessionState;
+use crate::{
+    anonymous,
+    chat_kind::{classify, ChatKindClass},
+    markup,
+    messages::{self, Lang},
+    time,
+};
 
+use super::{
+    quiet_hours::QuietHours, session_state::SessionState, Dispatcher, SessionSnapshot, StateError,
+};
 
 /// A struct that holds a Session
 ///
@@ -31,22 +45,25 @@ pub struct Session {
     /// The user who creates the session
     pub(super) creator: types::User,
 
-    /// A HashSet of participants who joined the session.
+    /// The participants of the session in the order they joined.
     /// (The creator of the session is always included).
-    pub(super) participants: HashSet<types::User>,
+    pub(super) participants: Vec<types::User>,
 
     /// A timestamp of the creation time
     pub(super) creation_time: Instant,
 
     /// The timestamp when the session shall be started.
     ///
+    /// Once the session is running, this is the timestamp when it actually started.
+    ///
     /// The default value depends on the session type and the kind of chat where the session was
     /// registered.
     ///
     /// If the session is a break, then the default start time is "now".
     /// If the session is a Pomodoro, then the start time is:
     /// - "now" for Pomodoros created in private chats,
-    /// - the next `minute % 5 == 0` of an hour for Pomodoros created in Groups or SuperGroups.
+    /// - the next `minute % interval == 0` of an hour for Pomodoros created in Groups or
+    ///   SuperGroups, where the interval defaults to 5 minutes.
     pub(super) start_time: Instant,
 
     /// Duration of the session
@@ -54,29 +71,136 @@ pub struct Session {
     /// Defaults to
     /// - 25 minutes for Pomodoros,
     /// -  5 minutes for breaks
+    ///
     /// unless otherwise specified.
     pub(super) duration: Duration,
+
+    /// Whether the session is a long break, which is taken after a number of completed Pomodoros.
+    pub(super) long_break: bool,
+
+    /// The time that was left when the session was paused
+    pub(super) remaining: Option<Duration>,
+
+    /// How long before the end of the Pomodoro the participants get reminded.
+    /// `None` disables the reminder.
+    pub(super) reminder: Option<Duration>,
+
+    /// A sequence number that orders sessions by their creation.
+    ///
+    /// Unlike the message id, it does not change when the message of the session is replaced.
+    pub(super) seq: u64,
+
+    /// The text of the session message above its list of subscribers.
+    ///
+    /// The message is rendered from it whenever the list changes, so user supplied names can
+    /// never be mistaken for a part of the message. `None` for breaks and sessions restored from
+    /// older session files.
+    pub(super) header: Option<String>,
+
+    /// Participants who are listed, but not mentioned when the session starts or ends.
+    pub(super) muted: HashSet<user::Id>,
+
+    /// The message of the session that the bot pinned, if any.
+    ///
+    /// It is tracked separately, since `message` is replaced when the session starts.
+    pub(super) pinned: Option<message::Id>,
+
+    /// Administrators of the group who may manage the session as if they created it.
+    ///
+    /// Only sessions created by an anonymous administrator have them, see `crate::anonymous`.
+    pub(super) admins: HashSet<user::Id>,
+
+    /// Whether messages of the participants are deleted while the Pomodoro is running.
+    pub(super) focus_mode: bool,
+
+    /// The chain of Pomodoros the session is part of, if it was started with e.g. `/focus 4`.
+    ///
+    /// It is kept when the Pomodoro turns into its break, so the break knows what follows.
+    pub(super) rounds: Option<Rounds>,
+
+    /// Participants of a group Pomodoro who pressed "Ready" before it started.
+    ///
+    /// Once all of them are, the Pomodoro starts early. Cleared when it starts.
+    pub(super) ready: HashSet<user::Id>,
+
+    /// The quiet hours of the chat and its timezone, during which nobody is mentioned.
+    ///
+    /// A copy of the chat's settings, which `State` keeps up to date.
+    pub(super) quiet_hours: Option<(QuietHours, Tz)>,
+
+    /// When the session is deleted because its last participant left, unless someone joins it
+    /// before. See `Config::empty_session_grace`.
+    pub(super) deletion_due: Option<Instant>,
+
+    /// The key of the reminder or heads-up of the session in `State::reminders`, if one is
+    /// scheduled.
+    pub(super) reminder_key: Option<delay_queue::Key>,
+
+    /// The key of the check in `State::reminders` that starts the session early if nobody joined,
+    /// see `Config::solo_start_delay`.
+    pub(super) solo_start_key: Option<delay_queue::Key>,
+
+    /// What the session is about, e.g. `Chapter 3 review`, see `markup::sanitize_label`.
+    pub(super) label: Option<String>,
+}
+
+/// What an entry of `State::reminders` is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Nudge {
+    /// A reminder before the session ends, or a heads-up before it starts
+    Reminder,
+    /// A check whether the session starts early because nobody joined it
+    SoloStart,
+}
+
+/// A chain of Pomodoros that follow each other with a break in between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Rounds {
+    /// The round of the current Pomodoro, starting at 1
+    pub(super) current: u32,
+    /// The number of Pomodoros in the chain
+    pub(super) total: u32,
+    /// Length of every Pomodoro of the chain
+    pub(super) length: Duration,
+}
+
+/// What became of a session when a user left it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Departure {
+    /// The user did not take part in the session, nothing changed
+    NotParticipant,
+    /// The user left, the session goes on with the others
+    Left,
+    /// The user was the last participant, so the session is abandoned
+    Abandoned,
+}
+
+impl Rounds {
+    /// Return true if no Pomodoro follows the current one
+    pub(super) fn is_last(&self) -> bool {
+        self.current >= self.total
+    }
 }
 
 impl Session {
     /// Create a new Pomodoro Session
     ///
     /// Depending on `chat_kind`, the Pomodoro is scheduled to start either immediately or at the
-    /// next `minute % 5 == 0` of the current hour.
+    /// next `minute % interval == 0` of the current hour.
     pub(super) fn new_pomodoro(
         message: types::Message,
         creator: types::User,
         start_time: Option<Instant>,
         duration: Option<Duration>,
-    ) -> Result<Session, String> {
-        let mut participants = HashSet::new();
-        participants.insert(creator.to_owned());
+        interval: u32,
+    ) -> Result<Session, StateError> {
+        let participants = vec![creator.to_owned()];
 
         let creation_time = Instant::now();
         let duration = duration.unwrap_or(Duration::from_secs(60 * 25));
 
-        match message.chat.kind {
-            chat::Kind::Private { .. } => Ok(Session {
+        match classify(&message.chat) {
+            ChatKindClass::Private => Ok(Session {
                 message,
                 creator,
                 participants,
@@ -84,21 +208,52 @@ impl Session {
                 start_time: start_time.unwrap_or(Instant::now()),
                 duration,
                 state: SessionState::PomodoroWaiting,
+                long_break: false,
+                remaining: None,
+                reminder: None,
+                seq: 0,
+                header: None,
+                muted: HashSet::new(),
+                pinned: None,
+                admins: HashSet::new(),
+                focus_mode: false,
+                ready: HashSet::new(),
+                quiet_hours: None,
+                deletion_due: None,
+                reminder_key: None,
+                solo_start_key: None,
+                label: None,
+                rounds: None,
             }),
-            chat::Kind::Group { .. } | chat::Kind::Supergroup { .. } => Ok(Session {
+            ChatKindClass::Group | ChatKindClass::Channel => Ok(Session {
                 message,
                 creator,
                 participants,
                 creation_time,
-                start_time: start_time.unwrap_or_else(|| time::instant_at_minute()),
+                start_time: start_time.unwrap_or_else(|| time::instant_at_interval(interval)),
                 duration,
                 state: SessionState::PomodoroWaiting,
+                long_break: false,
+                remaining: None,
+                reminder: None,
+                seq: 0,
+                header: None,
+                muted: HashSet::new(),
+                pinned: None,
+                admins: HashSet::new(),
+                focus_mode: false,
+                ready: HashSet::new(),
+                quiet_hours: None,
+                deletion_due: None,
+                reminder_key: None,
+                solo_start_key: None,
+                label: None,
+                rounds: None,
             }),
-            _ => {
-                let err_msg =
-                    "Chat kind is neither a group nor a supergroup nor a private chat".to_string();
-                dbg!(&err_msg);
-                Err(err_msg)
+            ChatKindClass::Other => {
+                let err = StateError::UnsupportedChat;
+                warn!("{}", err);
+                Err(err)
             }
         }
     }
@@ -109,9 +264,8 @@ impl Session {
         creator: types::User,
         start_time: Option<Instant>,
         duration: Option<Duration>,
-    ) -> Result<Session, String> {
-        let mut participants = HashSet::new();
-        participants.insert(creator.to_owned());
+    ) -> Result<Session, StateError> {
+        let participants = vec![creator.to_owned()];
 
         let creation_time = Instant::now();
         let duration = duration.unwrap_or_else(|| Duration::from_secs(60 * 5));
@@ -124,20 +278,174 @@ impl Session {
             start_time: start_time.unwrap_or(creation_time),
             duration,
             state: SessionState::BreakWaiting,
+            long_break: false,
+            remaining: None,
+            reminder: None,
+            seq: 0,
+            header: None,
+            muted: HashSet::new(),
+            pinned: None,
+            admins: HashSet::new(),
+            focus_mode: false,
+            ready: HashSet::new(),
+            quiet_hours: None,
+            deletion_due: None,
+            reminder_key: None,
+            solo_start_key: None,
+            label: None,
+            rounds: None,
         })
     }
 
     /// Convert a pomodoro session to a break session
-    pub(crate) fn convert_to_break(&mut self) {
-        self.duration = Duration::from_secs(60 * 5);
+    pub(crate) fn convert_to_break(&mut self, duration: Duration) {
+        self.duration = duration;
+        self.start_time = Instant::now();
         self.state = SessionState::BreakRunning;
     }
 
+    /// Convert a pomodoro session to a long break session
+    pub(crate) fn convert_to_long_break(&mut self, duration: Duration) {
+        self.convert_to_break(duration);
+        self.long_break = true;
+    }
+
+    /// Mark the session as a running Pomodoro that starts now
+    pub(super) fn start(&mut self) {
+        self.start_time = Instant::now();
+        self.state = SessionState::PomodoroRunning;
+        self.ready.clear();
+    }
+
+    /// Pause a running session and remember the remaining time
+    pub(super) fn pause(&mut self) {
+        self.remaining = Some(self.deadline().saturating_duration_since(Instant::now()));
+        self.state = SessionState::PomodoroPaused;
+    }
+
+    /// Resume a paused session with the time that was remaining when it was paused
+    pub(super) fn resume(&mut self) {
+        let remaining = self.remaining.take().unwrap_or(self.duration);
+        let elapsed = self.duration.checked_sub(remaining).unwrap_or_default();
+        let now = Instant::now();
+        self.start_time = now.checked_sub(elapsed).unwrap_or(now);
+        self.state = SessionState::PomodoroRunning;
+    }
+
+    /// Return the Instant at which the participants of a running Pomodoro shall be reminded.
+    pub(super) fn reminder_time(&self) -> Option<Instant> {
+        match self.state {
+            SessionState::PomodoroRunning => {
+                let end = self.start_time + self.duration;
+                Some(end.checked_sub(self.reminder?).unwrap_or(end))
+            }
+            _ => None,
+        }
+    }
+
+    /// Return how much of a running Pomodoro has passed, between `0.0` and `1.0`
+    pub(super) fn progress(&self) -> Option<f64> {
+        SessionSnapshot::from(self).progress()
+    }
+
+    /// Return the time left of a running Pomodoro
+    pub(super) fn countdown(&self) -> Option<Duration> {
+        SessionSnapshot::from(self).countdown()
+    }
+
+    /// Return the Instant at which the participants of a group Pomodoro get a heads-up that it
+    /// is about to start.
+    ///
+    /// Only Pomodoros waiting to be started in groups, supergroups or channels get a heads-up.
+    pub(super) fn pre_start_time(&self) -> Option<Instant> {
+        match (&self.state, classify(&self.message.chat)) {
+            (SessionState::PomodoroWaiting, ChatKindClass::Group | ChatKindClass::Channel) => {
+                self.start_time.checked_sub(Duration::from_secs(60))
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the Instant at which the session is going to be yielded back by the DelayQueue.
+    ///
+    /// Sessions waiting to be started expire at their start time, running sessions at the end of
+    /// their duration. Paused sessions would expire after the remaining time once resumed.
+    pub(super) fn deadline(&self) -> Instant {
+        match self.state {
+            SessionState::PomodoroWaiting | SessionState::BreakWaiting => self.start_time,
+            SessionState::PomodoroRunning | SessionState::BreakRunning => {
+                self.start_time + self.duration
+            }
+            SessionState::PomodoroPaused => {
+                Instant::now() + self.remaining.unwrap_or(self.duration)
+            }
+        }
+    }
+
+    /// Add `user` to the participants, returns false if they already are one.
+    ///
+    /// Joining a session that everyone left saves it from being deleted, and the user who joined
+    /// becomes its creator.
+    pub(super) fn join(&mut self, user: types::User) -> bool {
+        if self.participants.iter().any(|p| p.id == user.id) {
+            return false;
+        }
+        if self.deletion_due.take().is_some() {
+            self.creator = user.to_owned();
+        }
+        self.participants.push(user);
+        true
+    }
+
+    /// Remove the participant with `user_id` from the session
+    ///
+    /// If the creator leaves, the earliest joiner of the others becomes the creator.
+    pub(super) fn leave(&mut self, user_id: user::Id) -> Departure {
+        if !self.participants.iter().any(|p| p.id == user_id) {
+            return Departure::NotParticipant;
+        }
+        self.participants.retain(|p| p.id != user_id);
+        self.muted.remove(&user_id);
+        // Compare ids only, the username of the creator might have changed since
+        if self.creator.id == user_id {
+            match self.participants.first().cloned() {
+                Some(user) => self.creator = user,
+                None => return Departure::Abandoned,
+            }
+        }
+        debug_assert!(
+            self.creator_is_participant(),
+            "The creator of a session has to be one of its participants"
+        );
+        Departure::Left
+    }
+
+    /// Return true if the creator is one of the participants, which has to hold for every
+    /// session that still exists, unless it is about to be deleted because everyone left.
+    pub(super) fn creator_is_participant(&self) -> bool {
+        self.participants
+            .iter()
+            .any(|user| user.id == self.creator.id)
+    }
+
     /// Return true if the session is a running Pomodoro session.
     pub(super) fn is_running(&self) -> bool {
         self.state.eq(&SessionState::PomodoroRunning)
     }
 
+    /// Return the key of the entry for `nudge` in `State::reminders`
+    pub(super) fn nudge_key(&mut self, nudge: Nudge) -> &mut Option<delay_queue::Key> {
+        match nudge {
+            Nudge::Reminder => &mut self.reminder_key,
+            Nudge::SoloStart => &mut self.solo_start_key,
+        }
+    }
+
+    /// Return true if the session is a paused Pomodoro session.
+    pub(super) fn is_paused(&self) -> bool {
+        self.state.eq(&SessionState::PomodoroPaused)
+    }
+
     /// Return true if the session is a running Break session.
     pub(super) fn is_taking_a_break(&self) -> bool {
         self.state.eq(&SessionState::BreakRunning)
@@ -153,50 +461,189 @@ impl Session {
         self.state.eq(&SessionState::BreakWaiting)
     }
 
+    /// Return a human readable description of the remaining time
+    pub(super) fn status(&self) -> String {
+        SessionSnapshot::from(self).status()
+    }
+
     /// Delete the previous message and replace it with the ping to all participants
-    pub(super) async fn notify_participants_on_start(&mut self, bot: &Bot) {
-        let text = format!(
-            "{}\n\n\
-        \
-         Session has started!",
-            self.string_of_subscribed_usernames()
-        );
-        let message_id = self.message.id;
-        let chat_id = self.message.chat.id;
+    pub(super) async fn notify_participants_on_start(&mut self, dispatcher: Dispatcher<'_>) {
+        let header = markup::with_label("Session has started!", self.label.as_deref());
+        let text = self.with_mentions(&header);
         let (delete_message_result, send_message_result) = join!(
-            bot.delete_message(chat_id, message_id).call(),
-            bot.send_message(chat_id, &text).call()
+            self.delete_message(dispatcher),
+            self.send_html(dispatcher, &text)
         );
         if let Err(err) = delete_message_result {
-            dbg!(err);
+            error!("{}", err);
         }
 
         match send_message_result {
             Ok(message) => {
                 self.message = message;
+                self.header = Some(header);
+                // Deleting the previous message unpinned it, so the new one takes its place
+                if self.pinned.is_some() {
+                    self.pinned = None;
+                    self.pin(dispatcher).await;
+                }
             }
             Err(err) => {
-                dbg!(err.to_string());
+                error!("{}", err);
+            }
+        }
+    }
+
+    /// Pin the message of the session without notifying the chat
+    async fn pin(&mut self, dispatcher: Dispatcher<'_>) {
+        if pin_message(dispatcher, &self.message).await {
+            self.pinned = Some(self.message.id);
+        }
+    }
+
+    /// Unpin the message the bot pinned for the session, if it is still pinned
+    ///
+    /// The Bot API only unpins the latest pinned message, so it is checked first that no one
+    /// pinned another message since.
+    pub(super) async fn unpin(&mut self, dispatcher: Dispatcher<'_>) {
+        let pinned = match self.pinned.take() {
+            Some(pinned) => pinned,
+            None => return,
+        };
+        let chat_id = self.message.chat.id;
+        let chat = match dispatcher
+            .call(chat_id, |bot| bot.get_chat(chat_id).call())
+            .await
+        {
+            Ok(chat) => chat,
+            Err(err) => {
+                debug!("{}", err);
                 return;
             }
+        };
+        let still_pinned = match &chat.kind {
+            chat::Kind::Group { pinned_message, .. }
+            | chat::Kind::Supergroup { pinned_message, .. } => pinned_message
+                .as_ref()
+                .is_some_and(|message| message.id == pinned),
+            _ => false,
+        };
+        if still_pinned {
+            if let Err(err) = dispatcher
+                .call(chat_id, |bot| bot.unpin_chat_message(chat_id).call())
+                .await
+            {
+                debug!(
+                    "Could not unpin message {} in chat {}: {}",
+                    pinned, chat.id, err
+                );
+            }
         }
     }
 
+    /// Remind the participants that the Pomodoro is about to end.
+    pub(super) async fn notify_participants_reminder(
+        &self,
+        dispatcher: Dispatcher<'_>,
+    ) -> Result<types::Message, MethodCall> {
+        let left = format!(
+            "{} left!",
+            time::format_remaining(self.start_time + self.duration)
+        );
+        let msg = match classify(&self.message.chat) {
+            ChatKindClass::Group => self.with_mentions(&left),
+            _ => markup::escape_html(&left),
+        };
+        self.send_html(dispatcher, &msg).await
+    }
+
+    /// Notify the participants that the Pomodoro is going to start in a minute.
+    pub(super) async fn notify_participants_pre_start(
+        &self,
+        dispatcher: Dispatcher<'_>,
+    ) -> Result<types::Message, MethodCall> {
+        let msg = self.with_mentions("Get ready, starting in 1 minute!");
+        self.send_html(dispatcher, &msg).await
+    }
+
+    /// Send an HTML message to the chat of the session
+    async fn send_html(
+        &self,
+        dispatcher: Dispatcher<'_>,
+        html: &str,
+    ) -> Result<types::Message, MethodCall> {
+        let chat_id = self.message.chat.id;
+        dispatcher
+            .call(chat_id, |bot| {
+                bot.send_message(chat_id, Text::with_html(html)).call()
+            })
+            .await
+    }
+
+    /// Send an HTML message to the chat of the session with buttons to continue or stop
+    async fn ask_to_continue(
+        &self,
+        dispatcher: Dispatcher<'_>,
+        html: &str,
+    ) -> Result<types::Message, MethodCall> {
+        let chat_id = self.message.chat.id;
+        dispatcher
+            .call(chat_id, |bot| {
+                bot.send_message(chat_id, Text::with_html(html))
+                    .reply_markup(markup::inline::ASK_TO_CONTINUE)
+                    .call()
+            })
+            .await
+    }
+
+    /// Delete the message of the session
+    pub(super) async fn delete_message(
+        &self,
+        dispatcher: Dispatcher<'_>,
+    ) -> Result<(), MethodCall> {
+        let (chat_id, message_id) = (self.message.chat.id, self.message.id);
+        dispatcher
+            .call(chat_id, |bot| {
+                bot.delete_message(chat_id, message_id).call()
+            })
+            .await
+    }
+
+    /// Return the header of the message announcing the end of the session and its break
+    pub(super) fn end_header(
+        &self,
+        break_duration: Duration,
+        long_break: bool,
+        lang: Lang,
+    ) -> String {
+        let key = if long_break {
+            "session_over_long"
+        } else {
+            "session_over_short"
+        };
+        markup::with_label(
+            &messages::tf(lang, key, &[&(break_duration.as_secs() / 60)]),
+            self.label.as_deref(),
+        )
+    }
+
+    /// Notify the participants that the session is over and which kind of break follows.
     pub(super) async fn notify_participants_on_end(
         &mut self,
-        bot: &Bot,
+        dispatcher: Dispatcher<'_>,
+        break_duration: Duration,
+        long_break: bool,
+        lang: Lang,
     ) -> Result<types::Message, MethodCall> {
-        let text = format!(
-            "{}\n\n\
-            Session is over! Now take a short, 5 minute break",
-            self.string_of_subscribed_usernames()
-        );
+        let header = self.end_header(break_duration, long_break, lang);
+        let text = self.with_mentions(&header);
 
-        match self.message.chat.kind {
-            chat::Kind::Group { .. } | chat::Kind::Supergroup { .. } => {
-                match bot.send_message(self.message.chat.id, &text).call().await {
+        match classify(&self.message.chat) {
+            ChatKindClass::Group | ChatKindClass::Channel => {
+                match self.send_html(dispatcher, &text).await {
                     Ok(message) => {
                         self.message = message.to_owned();
+                        self.header = Some(header);
                         Ok(message)
                     }
                     Err(err) => Err(err),
@@ -204,16 +651,16 @@ impl Session {
             }
             _ => {
                 let (delete_message_result, send_message_result) = join!(
-                    bot.delete_message(self.message.chat.id, self.message.id)
-                        .call(),
-                    bot.send_message(self.message.chat.id, &text).call(),
+                    self.delete_message(dispatcher),
+                    self.send_html(dispatcher, &text),
                 );
                 if let Err(err) = delete_message_result {
-                    dbg!(err.to_string());
+                    error!("{}", err);
                 }
                 match send_message_result {
                     Ok(message) => {
                         self.message = message.to_owned();
+                        self.header = Some(header);
                         Ok(message)
                     }
                     Err(err) => Err(err),
@@ -222,30 +669,31 @@ impl Session {
         }
     }
 
+    /// Notify the participants that the break is over.
+    ///
+    /// Private chats are always asked whether to continue, groups only if `ask_to_continue` is
+    /// set.
     pub(super) async fn notify_participants_on_break_end(
         &self,
-        bot: &Bot,
+        dispatcher: Dispatcher<'_>,
+        ask_to_continue: bool,
     ) -> Result<types::Message, MethodCall> {
-        let msg = match self.message.chat.kind {
-            chat::Kind::Group { .. } | chat::Kind::Supergroup { .. } => format!(
-                "{}\n\n\
-                Break is over!",
-                self.string_of_subscribed_usernames()
-            ),
-            _ => format!("Break is over! Do you want to continue?"),
+        let msg = match classify(&self.message.chat) {
+            ChatKindClass::Group if ask_to_continue => {
+                self.with_mentions("Break is over! Do you want to continue?")
+            }
+            ChatKindClass::Group => self.with_mentions("Break is over!"),
+            _ => markup::escape_html("Break is over! Do you want to continue?"),
         };
 
-        match self.message.chat.kind {
-            chat::Kind::Group { .. } | chat::Kind::Supergroup { .. } => {
-                bot.send_message(self.message.chat.id, &msg).call().await
-            }
+        match classify(&self.message.chat) {
+            ChatKindClass::Group if ask_to_continue => self.ask_to_continue(dispatcher, &msg).await,
+            ChatKindClass::Group => self.send_html(dispatcher, &msg).await,
+            ChatKindClass::Channel => self.send_html(dispatcher, "Break is over!").await,
             _ => {
                 let (delete_message_result, send_message_result) = join!(
-                    bot.delete_message(self.message.chat.id, self.message.id)
-                        .call(),
-                    bot.send_message(self.message.chat.id, &msg)
-                        .reply_markup(markup::inline::ASK_TO_CONTINUE)
-                        .call()
+                    self.delete_message(dispatcher),
+                    self.ask_to_continue(dispatcher, &msg)
                 );
                 delete_message_result?;
                 send_message_result
@@ -253,29 +701,328 @@ impl Session {
         }
     }
 
-    /// Return a String of all subscribed usernames
-    pub(super) fn string_of_subscribed_usernames(&self) -> String {
-        let mut subscribed_users = String::new();
-        for user in self.participants.iter() {
-            subscribed_users = format!(
-                "{} @{}",
-                subscribed_users,
-                user.username.as_ref().unwrap_or(&user.first_name)
-            );
+    /// Announce the next Pomodoro of a chain and return the message that represents it
+    ///
+    /// In private chats, the message of the break is deleted.
+    pub(super) async fn notify_participants_on_next_round(
+        &self,
+        dispatcher: Dispatcher<'_>,
+        rounds: &Rounds,
+    ) -> Result<types::Message, MethodCall> {
+        let msg = markup::escape_html(&format!(
+            "Break is over! Round {} of {} starts now.",
+            rounds.current + 1,
+            rounds.total
+        ));
+        match classify(&self.message.chat) {
+            ChatKindClass::Private => {
+                let (delete_message_result, send_message_result) = join!(
+                    self.delete_message(dispatcher),
+                    self.send_html(dispatcher, &msg)
+                );
+                if let Err(err) = delete_message_result {
+                    error!("{}", err);
+                }
+                send_message_result
+            }
+            _ => self.send_html(dispatcher, &msg).await,
         }
-        subscribed_users
+    }
+
+    /// Notify the participants that the last break of a chain is over
+    ///
+    /// In private chats, the message of the break is deleted.
+    pub(super) async fn notify_participants_on_rounds_done(
+        &self,
+        dispatcher: Dispatcher<'_>,
+        rounds: &Rounds,
+    ) -> Result<types::Message, MethodCall> {
+        let msg = self.with_mentions(&format!(
+            "Break is over! All {} rounds are done, well done!",
+            rounds.total
+        ));
+        match classify(&self.message.chat) {
+            ChatKindClass::Private => {
+                let (delete_message_result, send_message_result) = join!(
+                    self.delete_message(dispatcher),
+                    self.send_html(dispatcher, &msg)
+                );
+                if let Err(err) = delete_message_result {
+                    error!("{}", err);
+                }
+                send_message_result
+            }
+            _ => self.send_html(dispatcher, &msg).await,
+        }
+    }
+
+    /// Return `text` preceded by the mentions of all participants, as HTML
+    ///
+    /// Nobody is mentioned in channels, whose sessions have no real participants, during the
+    /// quiet hours of the chat, or if every participant muted the session.
+    pub(super) fn with_mentions(&self, text: &str) -> String {
+        let text = markup::escape_html(text);
+        if self
+            .quiet_hours
+            .is_some_and(|(quiet_hours, tz)| quiet_hours.is_quiet_now(tz))
+        {
+            return text;
+        }
+        // Large groups would otherwise exceed the length of a message
+        let budget = markup::MAX_MESSAGE_LENGTH.saturating_sub(markup::html_length(&text) + 2);
+        let mentions =
+            markup::join_within(&self.subscribed_mentions(), budget, markup::html_length);
+        match classify(&self.message.chat) {
+            ChatKindClass::Channel => text,
+            _ if mentions.is_empty() => text,
+            _ => format!("{}\n\n{}", mentions, text),
+        }
+    }
+
+    /// Return the HTML mentions of all participants that have not muted the session
+    ///
+    /// Messages containing them have to be sent with the HTML parse mode.
+    pub(super) fn subscribed_mentions(&self) -> Vec<String> {
+        self.participants
+            .iter()
+            .filter(|user| !self.muted.contains(&user.id) && !anonymous::is_anonymous_admin(user))
+            .map(markup::mention_html)
+            .collect()
     }
 }
 
 /// Getters
 impl Session {
+    /// Return how many participants of a waiting Pomodoro are ready, and how many there are
+    pub(super) fn ready_count(&self) -> Option<(usize, usize)> {
+        if !self.is_waiting() {
+            return None;
+        }
+        let ready = self
+            .participants
+            .iter()
+            .filter(|user| self.ready.contains(&user.id))
+            .count();
+        Some((ready, self.participants.len()))
+    }
+
+    /// Return the usernames of all participants, or their first names if they have none
+    pub(super) fn participant_names(&self) -> Vec<String> {
+        self.participants
+            .iter()
+            .map(|user| format!("@{}", user.username.as_ref().unwrap_or(&user.first_name)))
+            .collect()
+    }
+
     // TODO Is it possible to return a reference?
     pub(super) fn chat(&self) -> chat::Chat {
         self.message.chat.to_owned()
     }
 
+    /// Return the title of the chat of the session, or "Private chat"
+    pub(super) fn chat_name(&self) -> &str {
+        match &self.message.chat.kind {
+            chat::Kind::Group { title, .. }
+            | chat::Kind::Supergroup { title, .. }
+            | chat::Kind::Channel { title, .. } => title,
+            _ => "Private chat",
+        }
+    }
+
     // TODO Is it possible to return a reference?
     pub(super) fn message(&self) -> types::Message {
         self.message.to_owned()
     }
 }
+
+/// Pin a message without notifying the chat and return whether it worked
+///
+/// Failures, e.g. because the bot is not allowed to pin messages, are only logged.
+pub(super) async fn pin_message(dispatcher: Dispatcher<'_>, message: &types::Message) -> bool {
+    let (chat_id, message_id) = (message.chat.id, message.id);
+    match dispatcher
+        .call(chat_id, |bot| {
+            bot.pin_chat_message(chat_id, message_id)
+                .is_notification_disabled(true)
+                .call()
+        })
+        .await
+    {
+        Ok(()) => true,
+        Err(err) => {
+            debug!(
+                "Could not pin message {} in chat {}: {}",
+                message.id, message.chat.id, err
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, test_util};
+    use serde_json::json;
+
+    /// A Pomodoro in a group that `creator` created
+    fn group_pomodoro(creator: types::User) -> Session {
+        let message = test_util::message(test_util::group_json(-100), 10, "Pomodoro");
+        Session::new_pomodoro(message, creator, None, None, 5).unwrap()
+    }
+
+    fn alice() -> types::User {
+        test_util::user(1001, "Alice", Some("alice"))
+    }
+
+    fn bob() -> types::User {
+        test_util::user(1002, "Bob", None)
+    }
+
+    /// A waiting 25 minute Pomodoro in a group, read from disk like a restored session
+    fn restored_pomodoro() -> Session {
+        let session = json!({
+            "state": "PomodoroWaiting",
+            "message": test_util::message_json(test_util::group_json(-100), 10, "Pomodoro"),
+            "creator": { "id": 1001, "is_bot": false, "first_name": "Alice" },
+            "participants": [{ "id": 1001, "is_bot": false, "first_name": "Alice" }],
+            "creation_time": time::instant_to_timestamp(Instant::now()),
+            "start_time": time::instant_to_timestamp(Instant::now()),
+            "duration": 25 * 60,
+        });
+        serde_json::from_str(&session.to_string()).unwrap()
+    }
+
+    /// The results of all state predicates, exactly one of them may be true
+    fn predicates(session: &Session) -> [bool; 5] {
+        [
+            session.is_waiting(),
+            session.is_running(),
+            session.is_paused(),
+            session.is_awaiting_break(),
+            session.is_taking_a_break(),
+        ]
+    }
+
+    fn ids(session: &Session) -> Vec<user::Id> {
+        session.participants.iter().map(|user| user.id).collect()
+    }
+
+    #[test]
+    fn joining_twice_is_idempotent() {
+        let mut session = group_pomodoro(alice());
+        assert!(session.join(bob()));
+        assert!(!session.join(bob()));
+        assert!(!session.join(alice()));
+        assert_eq!(ids(&session), vec![alice().id, bob().id]);
+    }
+
+    #[test]
+    fn creator_leaving_promotes_the_earliest_joiner() {
+        let mut session = group_pomodoro(alice());
+        session.join(bob());
+        session.join(test_util::user(1003, "Carol", None));
+        assert_eq!(session.leave(alice().id), Departure::Left);
+        assert_eq!(session.creator.id, bob().id);
+        assert!(session.creator_is_participant());
+    }
+
+    #[test]
+    fn last_participant_leaving_abandons_the_session() {
+        let mut session = group_pomodoro(alice());
+        assert_eq!(session.leave(alice().id), Departure::Abandoned);
+        assert!(session.participants.is_empty());
+    }
+
+    #[test]
+    fn non_member_leaving_is_a_no_op() {
+        let mut session = group_pomodoro(alice());
+        session.join(bob());
+        session.muted.insert(bob().id);
+        let carol = test_util::user(1003, "Carol", None);
+        assert_eq!(session.leave(carol.id), Departure::NotParticipant);
+        assert_eq!(ids(&session), vec![alice().id, bob().id]);
+        assert_eq!(session.creator.id, alice().id);
+        assert!(session.muted.contains(&bob().id));
+    }
+
+    #[test]
+    fn creator_stays_a_participant_when_the_creator_leaves() {
+        let mut session = group_pomodoro(alice());
+        assert!(session.creator_is_participant());
+        session.join(bob());
+        session.leave(alice().id);
+        assert!(session.creator_is_participant());
+        assert_eq!(ids(&session), vec![bob().id]);
+    }
+
+    #[test]
+    fn creator_is_no_participant_once_the_last_participant_left() {
+        let mut session = group_pomodoro(alice());
+        session.join(bob());
+        assert_eq!(session.leave(bob().id), Departure::Left);
+        assert!(session.creator_is_participant());
+        assert_eq!(session.leave(alice().id), Departure::Abandoned);
+        // Only sessions that are about to be deleted may break the invariant
+        assert!(!session.creator_is_participant());
+    }
+
+    #[test]
+    fn joining_an_abandoned_session_makes_the_joiner_its_creator() {
+        let mut session = group_pomodoro(alice());
+        assert_eq!(session.leave(alice().id), Departure::Abandoned);
+        session.deletion_due = Some(Instant::now());
+        assert!(session.join(bob()));
+        assert_eq!(session.creator.id, bob().id);
+        assert!(session.deletion_due.is_none());
+        assert!(session.creator_is_participant());
+    }
+
+    #[test]
+    fn pomodoro_runs_and_turns_into_a_break() {
+        let mut session = restored_pomodoro();
+        assert_eq!(session.state, SessionState::PomodoroWaiting);
+        assert_eq!(session.duration, Duration::from_secs(25 * 60));
+        assert_eq!(predicates(&session), [true, false, false, false, false]);
+
+        session.start();
+        assert_eq!(session.state, SessionState::PomodoroRunning);
+        assert_eq!(session.duration, Duration::from_secs(25 * 60));
+        assert_eq!(predicates(&session), [false, true, false, false, false]);
+
+        session.convert_to_break(Config::default().break_duration);
+        assert_eq!(session.state, SessionState::BreakRunning);
+        assert_eq!(session.duration, Duration::from_secs(5 * 60));
+        assert!(!session.long_break);
+        assert_eq!(predicates(&session), [false, false, false, false, true]);
+    }
+
+    #[test]
+    fn long_break_is_a_running_break() {
+        let mut session = restored_pomodoro();
+        session.start();
+        session.convert_to_long_break(Config::default().long_break_duration);
+        assert_eq!(session.state, SessionState::BreakRunning);
+        assert_eq!(session.duration, Duration::from_secs(15 * 60));
+        assert!(session.long_break);
+    }
+
+    #[test]
+    fn paused_pomodoro_is_only_paused() {
+        let mut session = restored_pomodoro();
+        session.start();
+        session.pause();
+        assert_eq!(predicates(&session), [false, false, true, false, false]);
+        session.resume();
+        assert_eq!(predicates(&session), [false, true, false, false, false]);
+    }
+
+    #[test]
+    fn break_waits_before_it_runs() {
+        let message = test_util::message(test_util::group_json(-100), 11, "Break");
+        let session = Session::new_break(message, alice(), None, None).unwrap();
+        assert_eq!(session.state, SessionState::BreakWaiting);
+        assert_eq!(session.duration, Duration::from_secs(5 * 60));
+        assert_eq!(predicates(&session), [false, false, false, true, false]);
+    }
+}
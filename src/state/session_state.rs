@@ -1,10 +1,14 @@
+use serde::{Deserialize, Serialize};
+
 /// An enumeration representing the state of a session.
-#[derive(Eq, PartialEq, Debug, Clone)]
-pub(super) enum SessionState {
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum SessionState {
     /// A Pomodoro waiting to be started
     PomodoroWaiting,
     /// A running Pomodoro
     PomodoroRunning,
+    /// A Pomodoro that has been paused
+    PomodoroPaused,
     /// A break waiting to be started
     BreakWaiting,
     /// A running break
@@ -0,0 +1,94 @@
+use core::time::Duration;
+
+use tokio::time::Instant;
+
+use super::{session::Session, session_state::SessionState, CacheKey};
+use crate::time;
+
+/// A read-only copy of a session, see `State::snapshot`
+///
+/// It is taken while the sessions are locked, but does not follow later changes of the session.
+#[derive(Debug, Clone)]
+pub(crate) struct SessionSnapshot {
+    pub(crate) cache_key: CacheKey,
+    pub(crate) state: SessionState,
+    /// The username of the creator, or their first name if they have none
+    pub(crate) creator: String,
+    pub(crate) participants: usize,
+    /// When the session starts, or when it started once it is running
+    pub(crate) start_time: Instant,
+    pub(crate) duration: Duration,
+    /// How much of `duration` was left when the snapshot was taken.
+    ///
+    /// Sessions that have not started yet have all of it left.
+    pub(crate) remaining: Duration,
+}
+
+impl From<&Session> for SessionSnapshot {
+    fn from(session: &Session) -> Self {
+        let remaining = match session.state {
+            SessionState::PomodoroWaiting | SessionState::BreakWaiting => session.duration,
+            SessionState::PomodoroRunning | SessionState::BreakRunning => {
+                (session.start_time + session.duration).saturating_duration_since(Instant::now())
+            }
+            SessionState::PomodoroPaused => session.remaining.unwrap_or(session.duration),
+        };
+        SessionSnapshot {
+            cache_key: CacheKey::from(&session.message),
+            state: session.state.to_owned(),
+            creator: session
+                .creator
+                .username
+                .as_ref()
+                .unwrap_or(&session.creator.first_name)
+                .to_owned(),
+            participants: session.participants.len(),
+            start_time: session.start_time,
+            duration: session.duration,
+            remaining,
+        }
+    }
+}
+
+impl SessionSnapshot {
+    /// Return how much of a running Pomodoro has passed, between `0.0` and `1.0`
+    pub(crate) fn progress(&self) -> Option<f64> {
+        if self.state != SessionState::PomodoroRunning || self.duration.as_secs() == 0 {
+            return None;
+        }
+        let elapsed = self.duration.saturating_sub(self.remaining);
+        Some((elapsed.as_secs_f64() / self.duration.as_secs_f64()).min(1.0))
+    }
+
+    /// Return the time left of a running Pomodoro
+    pub(crate) fn countdown(&self) -> Option<Duration> {
+        match self.state {
+            SessionState::PomodoroRunning => Some(self.remaining),
+            _ => None,
+        }
+    }
+
+    /// Return a human readable description of the remaining time
+    pub(crate) fn status(&self) -> String {
+        let remaining = time::format_minutes(self.remaining);
+        match self.state {
+            SessionState::PomodoroWaiting => {
+                format!(
+                    "Pomodoro starts in {}",
+                    time::format_remaining(self.start_time)
+                )
+            }
+            SessionState::PomodoroRunning => format!("{} remaining in Pomodoro", remaining),
+            SessionState::PomodoroPaused => {
+                format!("Pomodoro is paused with {} remaining", remaining)
+            }
+            SessionState::BreakWaiting => {
+                format!(
+                    "Break starts in {}",
+                    time::format_remaining(self.start_time)
+                )
+            }
+            SessionState::BreakRunning => format!("{} remaining in break", remaining),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use tbot::types::{self, message, user};
+use tokio::time::Instant;
+
+use super::session::Session;
+
+/// A session that was cancelled or left by its last participant, kept for `/undo`
+#[derive(Debug, Clone)]
+pub(super) struct Tombstone {
+    /// The session as it was when it was removed
+    pub(super) session: Session,
+    /// The text of the deleted session message, which is sent again on restore
+    pub(super) text: String,
+    /// The user who removed the session, who is the only one allowed to restore it
+    pub(super) removed_by: user::Id,
+    pub(super) removed_at: Instant,
+}
+
+impl Tombstone {
+    pub(super) fn new(session: Session, removed_by: &types::User) -> Tombstone {
+        let text = match &session.message.kind {
+            message::Kind::Text(text) => text.value.to_owned(),
+            _ => session
+                .header
+                .to_owned()
+                .unwrap_or_else(|| session.status()),
+        };
+        Tombstone {
+            session,
+            text,
+            removed_by: removed_by.id,
+            removed_at: Instant::now(),
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use core::time::Duration;
+
+/// Statistics about the Pomodoros a user completed.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct UserStats {
+    /// Number of Pomodoros the user took part in until their natural end
+    pub(crate) completed_pomodoros: u32,
+    /// The accumulated duration of all completed Pomodoros
+    pub(crate) focus_time: Duration,
+}
+
+impl UserStats {
+    /// Count a completed Pomodoro of the given duration
+    pub(super) fn add_pomodoro(&mut self, duration: Duration) {
+        self.completed_pomodoros += 1;
+        self.focus_time += duration;
+    }
+}
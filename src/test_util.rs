@@ -0,0 +1,47 @@
+//! Telegram types for tests.
+//!
+//! tbot's types can only be deserialized, so they are built from the JSON of the Bot API just
+//! like sessions are restored from disk.
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tbot::types::{Chat, Message, User};
+
+/// Deserialize `value` from its text, since tbot borrows strings while deserializing
+fn parse<T: DeserializeOwned>(value: Value) -> T {
+    serde_json::from_str(&value.to_string()).unwrap()
+}
+
+/// A user with the given id and names
+pub(crate) fn user(id: i64, first_name: &str, username: Option<&str>) -> User {
+    let mut user = json!({ "id": id, "is_bot": false, "first_name": first_name });
+    if let Some(username) = username {
+        user["username"] = json!(username);
+    }
+    parse(user)
+}
+
+/// The JSON of a group with the given id
+pub(crate) fn group_json(id: i64) -> Value {
+    json!({ "id": id, "type": "group", "title": "Study group" })
+}
+
+/// A group with the given id
+pub(crate) fn group(id: i64) -> Chat {
+    parse(group_json(id))
+}
+
+/// A message of the bot in the chat given as JSON
+pub(crate) fn message(chat: Value, message_id: u32, text: &str) -> Message {
+    parse(message_json(chat, message_id, text))
+}
+
+/// The JSON of a message of the bot in the chat given as JSON
+pub(crate) fn message_json(chat: Value, message_id: u32, text: &str) -> Value {
+    json!({
+        "message_id": message_id,
+        "date": 1_600_000_000,
+        "chat": chat,
+        "from": { "id": 1, "is_bot": true, "first_name": "Chaostomato" },
+        "text": text,
+    })
+}
@@ -1,29 +1,293 @@
-use chrono::{Timelike, Utc};
+use chrono::{DateTime, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use core::time::Duration;
+use std::{fmt, ops::RangeInclusive};
 use tokio::time::Instant;
 
-/// Return an Instant that approximately represents the next `minute % 5 == 0` of the current hour
-pub(crate) fn instant_at_minute() -> Instant {
-    Instant::now()
-        .checked_add(
-            chrono::Duration::minutes((5 - Utc::now().minute() % 5) as i64)
-                .to_std()
-                .unwrap(),
-        )
-        .unwrap()
+/// Return an Instant that represents the next `minute % minutes == 0` of the current hour
+///
+/// An interval of `0` is treated as `1`.
+pub(crate) fn instant_at_interval(minutes: u32) -> Instant {
+    Instant::now() + delay_until_interval(Utc::now(), minutes)
+}
+
+/// Return the time from `now` until the next `minute % minutes == 0`, on the full minute
+///
+/// The delay is computed from the wall clock alone, so it can be applied to a monotonic
+/// `Instant` without mixing both clocks. At a boundary, the next one is a full interval away.
+/// An interval of `0` is treated as `1`.
+fn delay_until_interval(now: DateTime<Utc>, minutes: u32) -> Duration {
+    let minutes = minutes.max(1);
+    let interval = Duration::from_secs(u64::from(minutes) * 60);
+    // The nanoseconds exceed a second during a leap second
+    let elapsed = Duration::from_secs(u64::from(now.minute() % minutes * 60 + now.second()))
+        + Duration::from_nanos(u64::from(now.nanosecond() % 1_000_000_000));
+    interval - elapsed
 }
 
-/// Return a String representation of the calculated time
-pub(crate) fn future_point_as_hh_mm() -> String {
-    let duration = duration_since_now();
+/// Return a String representation of the calculated time in the given timezone
+pub(crate) fn future_point_as_hh_mm(interval: u32, tz: Tz) -> String {
+    instant_as_hh_mm(instant_at_interval(interval), tz)
+}
+
+/// Return a String representation of a future Instant in the given timezone
+pub(crate) fn instant_as_hh_mm(instant: Instant, tz: Tz) -> String {
+    let duration = instant.saturating_duration_since(Instant::now());
     Utc::now()
         .checked_add_signed(chrono::Duration::from_std(duration).unwrap())
         .unwrap()
+        .with_timezone(&tz)
         .format("%H:%M")
         .to_string()
 }
 
-/// Take an Instant and calculate the Duration between that Instant and "now"
-fn duration_since_now() -> std::time::Duration {
-    let instant = instant_at_minute();
-    instant.duration_since(Instant::now())
+/// Parse a clock time like `14:30` in the given timezone and return the next Instant it occurs
+///
+/// If the time already passed today, the Instant of the next day is returned.
+/// Returns `None` if the input is malformed.
+pub(crate) fn parse_clock_time(input: &str, tz: Tz) -> Option<Instant> {
+    let time = NaiveTime::parse_from_str(input.trim(), "%H:%M").ok()?;
+    let now = Utc::now();
+    let today = now.with_timezone(&tz).naive_local().date();
+
+    // Try tomorrow as well, in case the time already passed or does not exist today (DST)
+    let start = (0..2)
+        .filter_map(|days| {
+            let date = today + chrono::Duration::days(days);
+            tz.from_local_datetime(&date.and_time(time)).earliest()
+        })
+        .find(|start| start.with_timezone(&Utc) > now)?;
+
+    let delta = (start.with_timezone(&Utc) - now).to_std().ok()?;
+    Instant::now().checked_add(delta)
+}
+
+/// Why a user supplied duration was rejected, see `parse_duration`
+///
+/// The `Display` implementation is the message shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ParseError {
+    /// The input is not a duration like `50`, `90m` or `1h30m`
+    Malformed,
+    /// The duration is shorter or longer than allowed
+    OutOfRange(RangeInclusive<Duration>),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed => write!(f, "That is not a duration like 50, 90m or 1h30m."),
+            ParseError::OutOfRange(bounds) => write!(
+                f,
+                "The duration has to be between {} and {}.",
+                format_minutes(*bounds.start()),
+                format_minutes(*bounds.end())
+            ),
+        }
+    }
+}
+
+/// Parse a user supplied duration like `50`, `90m`, `1h` or `1h30m` that lies within `bounds`
+///
+/// A plain number is interpreted as minutes.
+pub(crate) fn parse_duration(
+    input: &str,
+    bounds: RangeInclusive<Duration>,
+) -> Result<Duration, ParseError> {
+    match parse_any_duration(input) {
+        Some(duration) if bounds.contains(&duration) => Ok(duration),
+        Some(_) => Err(ParseError::OutOfRange(bounds)),
+        None => Err(ParseError::Malformed),
+    }
+}
+
+/// Parse a duration like `parse_duration`, without any bounds
+///
+/// Returns `None` if the input is malformed or does not fit into a `Duration`.
+fn parse_any_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    if let Ok(minutes) = input.parse::<u64>() {
+        return Some(Duration::from_secs(minutes.checked_mul(60)?));
+    }
+
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in input.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' | 'm' if !number.is_empty() => {
+                let unit = if c == 'h' { 60 * 60 } else { 60 };
+                let value = number.parse::<u64>().ok()?.checked_mul(unit)?;
+                seconds = seconds.checked_add(value)?;
+                number.clear();
+            }
+            _ => return None,
+        }
+    }
+    if !number.is_empty() || input.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+/// Return the next Instant at which the UTC clock shows `hour` o'clock
+///
+/// If that hour already started today, the Instant of the next day is returned.
+pub(crate) fn next_utc_hour(hour: u32) -> Instant {
+    let now = Utc::now();
+    let today = now.naive_utc().date().and_hms(hour % 24, 0, 0);
+    let next = if today > now.naive_utc() {
+        today
+    } else {
+        today + chrono::Duration::days(1)
+    };
+    let delta = (next - now.naive_utc()).to_std().unwrap_or_default();
+    Instant::now() + delta
+}
+
+/// Convert an Instant into a unix timestamp in milliseconds
+pub(crate) fn instant_to_timestamp(instant: Instant) -> i64 {
+    let now = Instant::now();
+    let offset = if instant >= now {
+        chrono::Duration::from_std(instant - now).unwrap_or_else(|_| chrono::Duration::zero())
+    } else {
+        -chrono::Duration::from_std(now - instant).unwrap_or_else(|_| chrono::Duration::zero())
+    };
+    (Utc::now() + offset).timestamp_millis()
+}
+
+/// Convert a unix timestamp in milliseconds back into an Instant
+///
+/// Timestamps that cannot be represented fall back to "now".
+pub(crate) fn timestamp_to_instant(timestamp: i64) -> Instant {
+    let now = Instant::now();
+    let delta = timestamp - Utc::now().timestamp_millis();
+    if delta >= 0 {
+        now.checked_add(std::time::Duration::from_millis(delta as u64))
+    } else {
+        now.checked_sub(std::time::Duration::from_millis(delta.unsigned_abs()))
+    }
+    .unwrap_or(now)
+}
+
+/// Return a human readable representation of the time left until `instant`, e.g. "18 minutes"
+pub(crate) fn format_remaining(instant: Instant) -> String {
+    format_minutes(instant.saturating_duration_since(Instant::now()))
+}
+
+/// Return a countdown like `17:32`, or `1:05:00` for an hour or more
+pub(crate) fn format_countdown(duration: std::time::Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// Return a human readable representation of a duration in minutes, e.g. "5 minutes"
+pub(crate) fn format_minutes(duration: std::time::Duration) -> String {
+    match duration.as_secs().div_ceil(60) {
+        0 => "less than a minute".to_string(),
+        1 => "1 minute".to_string(),
+        minutes => format!("{} minutes", minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.ymd(2020, 9, 13).and_hms(12, minute, second)
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn delay_until_interval_at_representative_minutes() {
+        assert_eq!(delay_until_interval(at(0, 0), 5), minutes(5));
+        assert_eq!(delay_until_interval(at(4, 0), 5), minutes(1));
+        assert_eq!(delay_until_interval(at(5, 0), 5), minutes(5));
+        assert_eq!(delay_until_interval(at(59, 0), 5), minutes(1));
+    }
+
+    #[test]
+    fn delay_until_interval_within_a_minute() {
+        assert_eq!(delay_until_interval(at(4, 30), 5), Duration::from_secs(30));
+        let just_before = at(4, 59) + chrono::Duration::milliseconds(999);
+        assert_eq!(
+            delay_until_interval(just_before, 5),
+            Duration::from_millis(1)
+        );
+        let just_after = at(5, 0) + chrono::Duration::milliseconds(1);
+        assert_eq!(
+            delay_until_interval(just_after, 5),
+            minutes(5) - Duration::from_millis(1)
+        );
+    }
+
+    #[test]
+    fn delay_until_interval_never_exceeds_the_interval() {
+        for minute in 0..60 {
+            for second in [0, 1, 59] {
+                let delay = delay_until_interval(at(minute, second), 5);
+                assert!(delay > Duration::from_secs(0) && delay <= minutes(5));
+            }
+        }
+    }
+
+    #[test]
+    fn delay_until_interval_treats_zero_as_one_minute() {
+        assert_eq!(delay_until_interval(at(7, 15), 0), Duration::from_secs(45));
+    }
+
+    /// The defaults of `Config::min_duration` and `Config::max_duration`
+    const BOUNDS: RangeInclusive<Duration> =
+        Duration::from_secs(60)..=Duration::from_secs(4 * 3600);
+
+    fn parse(input: &str) -> Result<Duration, ParseError> {
+        parse_duration(input, BOUNDS)
+    }
+
+    #[test]
+    fn parse_duration_accepts_minutes_and_units() {
+        assert_eq!(parse("50"), Ok(minutes(50)));
+        assert_eq!(parse("90m"), Ok(minutes(90)));
+        assert_eq!(parse("1h"), Ok(minutes(60)));
+        assert_eq!(parse("1h30m"), Ok(minutes(90)));
+        assert_eq!(parse(" 25 "), Ok(minutes(25)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_garbage() {
+        for input in ["", "abc", "5x", "h", "1h30", "m5", "1.5h", "-5", "1 h"] {
+            assert_eq!(parse(input), Err(ParseError::Malformed), "{:?}", input);
+        }
+        // Too large for a Duration
+        assert_eq!(parse("99999999999999999999"), Err(ParseError::Malformed));
+    }
+
+    #[test]
+    fn parse_duration_rejects_durations_out_of_range() {
+        assert_eq!(parse("0"), Err(ParseError::OutOfRange(BOUNDS)));
+        assert_eq!(parse("100000"), Err(ParseError::OutOfRange(BOUNDS)));
+    }
+
+    #[test]
+    fn parse_duration_includes_both_bounds() {
+        assert_eq!(parse("1"), Ok(minutes(1)));
+        assert_eq!(parse("4h"), Ok(minutes(240)));
+        assert_eq!(parse("0m"), Err(ParseError::OutOfRange(BOUNDS)));
+        assert_eq!(parse("4h1m"), Err(ParseError::OutOfRange(BOUNDS)));
+    }
+
+    #[test]
+    fn out_of_range_error_names_the_bounds() {
+        assert_eq!(
+            ParseError::OutOfRange(BOUNDS).to_string(),
+            "The duration has to be between 1 minute and 240 minutes."
+        );
+    }
 }